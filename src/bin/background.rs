@@ -0,0 +1,111 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use image::{ImageBuffer, Luma};
+use radar_to_mmdet3d::radar::background::{
+    merge_background_maps, BackgroundMap, BackgroundMeta, MergeMode,
+};
+
+const USAGE: &str = "Usage:
+    background inspect <map.npy> [--png <out.png>]
+    background merge <out.npy> <in.npy>... [--mode max|median]";
+
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    match args.first().map(String::as_str) {
+        Some("inspect") => inspect(&args[1..]),
+        Some("merge") => merge(&args[1..]),
+        _ => Err(anyhow!("{USAGE}")),
+    }
+}
+
+fn inspect(args: &[String]) -> Result<()> {
+    let (paths, png) = split_option(args, "--png")?;
+    let [path] = paths.as_slice() else {
+        return Err(anyhow!("{USAGE}"));
+    };
+
+    let map = BackgroundMap::load(path)?;
+    let (coverage, min, mean, max) = map.coverage();
+    println!("name: {}", map.meta.name);
+    println!("calibration hash: {}", map.meta.calibration_hash);
+    println!("model: {}", map.meta.model);
+    println!("size: {}x{}", map.meta.width, map.meta.height);
+    println!("coverage: {:.1}%", coverage * 100.0);
+    println!("depth: min {min:.0}, mean {mean:.0}, max {max:.0}");
+
+    if let Some(png) = png {
+        // Near is bright, pixels without background are black.
+        let preview: ImageBuffer<Luma<u8>, Vec<u8>> =
+            ImageBuffer::from_fn(map.meta.width, map.meta.height, |x, y| {
+                let depth = map.depth_map.get_pixel(x, y).0[0];
+                if !depth.is_normal() || max <= min {
+                    return Luma([0]);
+                }
+                Luma([(255.0 - (depth - min) / (max - min) * 235.0) as u8])
+            });
+        preview.save(&png)?;
+        println!("preview saved to {png}");
+    }
+
+    Ok(())
+}
+
+fn merge(args: &[String]) -> Result<()> {
+    let (paths, mode) = split_option(args, "--mode")?;
+    let mode = mode
+        .as_deref()
+        .map(MergeMode::try_from)
+        .transpose()?
+        .unwrap_or(MergeMode::Median);
+    let [output, inputs @ ..] = paths.as_slice() else {
+        return Err(anyhow!("{USAGE}"));
+    };
+    if inputs.is_empty() {
+        return Err(anyhow!("{USAGE}"));
+    }
+
+    let maps = inputs
+        .iter()
+        .map(BackgroundMap::load)
+        .collect::<Result<Vec<_>>>()?;
+    let merged = merge_background_maps(&maps, mode)?;
+
+    let output = Path::new(output);
+    let dir = output.parent().unwrap_or(Path::new("."));
+    let name = output
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .ok_or_else(|| anyhow!("Invalid output path {:?}", output))?;
+    BackgroundMap {
+        meta: BackgroundMeta {
+            name: name.to_string(),
+            ..merged.meta
+        },
+        depth_map: merged.depth_map,
+    }
+    .save(dir)?;
+    println!("merged {} maps into {:?}", maps.len(), output);
+
+    Ok(())
+}
+
+// Splits `--flag value` from the positional arguments.
+fn split_option(args: &[String], flag: &str) -> Result<(Vec<String>, Option<String>)> {
+    let mut positional = Vec::new();
+    let mut value = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == flag {
+            value = Some(
+                args.next()
+                    .ok_or_else(|| anyhow!("Missing value of {flag}"))?
+                    .clone(),
+            );
+        } else {
+            positional.push(arg.clone());
+        }
+    }
+    Ok((positional, value))
+}
@@ -1,4 +1,4 @@
-use std::fs;
+use std::{collections::HashMap, fs};
 
 use anyhow::Result;
 use serde::Deserialize;
@@ -9,6 +9,12 @@ pub struct RadarConfig {
     pub detect: DetectorConfig,
     pub locate: LocatorConfig,
     pub instances: Vec<RadarInstanceConfig>,
+    #[serde(default)]
+    pub export: ExportConfig,
+    #[serde(default)]
+    pub labels: LabelConfig,
+    #[serde(default)]
+    pub field: FieldConfig,
 }
 
 #[derive(Debug, Deserialize)]
@@ -19,19 +25,409 @@ pub struct DetectorConfig {
     pub armor_conf_thresh: f32,
     pub car_nms_thresh: f32,
     pub armor_nms_thresh: f32,
-    pub execution: String,
+    pub execution: ExecutionConfig,
+    #[serde(default)]
+    pub preprocess: PreprocessConfig,
+    #[serde(default)]
+    pub car_model: ModelConfig,
+    #[serde(default)]
+    pub armor_model: ModelConfig,
+    #[serde(default)]
+    pub crop: CropConfig,
+    #[serde(default)]
+    pub unknown: UnknownConfig,
+    #[serde(default)]
+    pub tracking: TrackingConfig,
+    #[serde(default = "default_label_assignment")]
+    pub label_assignment: String,
+    #[serde(default = "default_min_reassign_share")]
+    pub min_reassign_share: f32,
+    #[serde(default = "default_detector_backend")]
+    pub backend: String,
+    #[serde(default)]
+    pub precomputed: Option<PrecomputedConfig>,
+}
+
+fn default_detector_backend() -> String {
+    "model".to_string()
+}
+
+fn default_label_assignment() -> String {
+    "hungarian".to_string()
+}
+
+fn default_min_reassign_share() -> f32 {
+    0.5
+}
+
+fn default_yaw_method() -> String {
+    "min_area".to_string()
+}
+
+fn default_box_fitting() -> String {
+    "extent".to_string()
+}
+
+fn default_cluster_method() -> String {
+    "voxel_hash".to_string()
+}
+
+fn default_locate_mode() -> String {
+    "depth_map".to_string()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PrecomputedConfig {
+    pub path: String,
+    pub format: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum ExecutionConfig {
+    Name(String),
+    Session(SessionConfig),
+}
+
+// Untagged, a misspelled key would otherwise fall back to the defaults without an error.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    pub providers: Vec<ProviderConfig>,
+    pub intra_threads: Option<usize>,
+    pub inter_threads: Option<usize>,
+    pub parallel_execution: bool,
+    pub optimization_level: String,
+    pub optimized_model_dir: Option<String>,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            providers: Vec::new(),
+            intra_threads: None,
+            inter_threads: Some(4),
+            parallel_execution: true,
+            optimization_level: "level3".to_string(),
+            optimized_model_dir: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProviderConfig {
+    pub name: String,
+    #[serde(default)]
+    pub device_id: Option<i32>,
+    #[serde(default)]
+    pub device_type: Option<String>,
+    #[serde(default)]
+    pub fp16: bool,
+    #[serde(default)]
+    pub cache_dir: Option<String>,
+    #[serde(default)]
+    pub timing_cache_dir: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct PreprocessConfig {
+    pub resize_mode: String,
+    pub pad_color: [u8; 3],
+    pub filter: String,
+}
+
+impl Default for PreprocessConfig {
+    fn default() -> Self {
+        Self {
+            resize_mode: "letterbox".to_string(),
+            pad_color: [114, 114, 114],
+            filter: "triangle".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct CropConfig {
+    pub margin_ratio: f32,
+    pub min_size: [u32; 2],
+    pub small_crop: String,
+}
+
+impl Default for CropConfig {
+    fn default() -> Self {
+        Self {
+            margin_ratio: 0.1,
+            min_size: [16, 16],
+            small_crop: "upscale".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct UnknownConfig {
+    pub enabled: bool,
+    pub min_brightness: u8,
+    pub min_color_diff: u8,
+    pub min_pixels: u32,
+    pub min_dominance: f32,
+}
+
+impl Default for UnknownConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_brightness: 200,
+            min_color_diff: 60,
+            min_pixels: 20,
+            min_dominance: 0.7,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct TrackingConfig {
+    pub enabled: bool,
+    pub iou_threshold: f32,
+    pub high_confidence: f32,
+    pub max_age: usize,
+    pub vote_decay: f32,
+}
+
+impl Default for TrackingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            iou_threshold: 0.3,
+            high_confidence: 0.6,
+            max_age: 10,
+            vote_decay: 0.9,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct ModelConfig {
+    pub input_name: Option<String>,
+    pub output_name: Option<String>,
+    pub input_size: Option<[u32; 2]>,
+    pub class_names: Option<Vec<String>>,
+    pub output_format: Option<String>,
+    pub nms_class_agnostic: bool,
+    pub nms_method: Option<String>,
+    pub soft_nms_sigma: Option<f32>,
+    pub class_nms_thresh: HashMap<String, f32>,
+}
+
+impl Default for ModelConfig {
+    fn default() -> Self {
+        Self {
+            input_name: None,
+            output_name: None,
+            input_size: None,
+            class_names: None,
+            output_format: None,
+            nms_class_agnostic: true,
+            nms_method: None,
+            soft_nms_sigma: None,
+            class_nms_thresh: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct FieldConfig {
+    // Row-major 4x4 transform from lidar to field coordinates, both in mm
+    pub lidar_to_field: Option<[f32; 16]>,
+    // Points measured in both frames, the transform is fitted to them without a matrix
+    pub landmarks: Vec<LandmarkConfig>,
+    pub output_frame: String,
+}
+
+impl Default for FieldConfig {
+    fn default() -> Self {
+        Self {
+            lidar_to_field: None,
+            landmarks: Vec::new(),
+            output_frame: "lidar".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LandmarkConfig {
+    pub lidar: [f32; 3],
+    pub field: [f32; 3],
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct LabelConfig {
+    pub classes: Vec<LabelClassConfig>,
+    pub armor_classes: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LabelClassConfig {
+    pub name: String,
+    #[serde(default)]
+    pub output: Option<String>,
+    #[serde(default)]
+    pub team: String,
+    #[serde(default)]
+    pub kind: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct ExportConfig {
+    pub detection_formats: Vec<String>,
+    pub armor_crops: bool,
+    pub armor_ambiguity_ratio: f32,
+    pub assignment_records: bool,
+    pub min_label_confidence: f32,
+    pub min_label_points: usize,
+    pub label_metadata: bool,
+}
+
+impl Default for ExportConfig {
+    fn default() -> Self {
+        Self {
+            detection_formats: Vec::new(),
+            armor_crops: false,
+            armor_ambiguity_ratio: 0.8,
+            assignment_records: false,
+            min_label_confidence: 0.0,
+            min_label_points: 0,
+            label_metadata: false,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct LocatorConfig {
     pub cluster_epsilon: f32,
     pub cluster_min_points: usize,
+    #[serde(default = "default_cluster_method")]
+    pub cluster_method: String,
     pub min_valid_distance: f32,
     pub max_valid_distance: f32,
     pub min_valid_distance_diff: f32,
     pub max_valid_distance_diff: f32,
     pub zoom_factor: f32,
     pub scale_factor: f32,
+    #[serde(default = "default_yaw_method")]
+    pub yaw_method: String,
+    #[serde(default = "default_box_fitting")]
+    pub box_fitting: String,
+    #[serde(default = "default_locate_mode")]
+    pub mode: String,
+    // [length, width, height] by label kind
+    #[serde(default)]
+    pub size_priors: HashMap<String, [f32; 3]>,
+    // [a, b, c, d] of the ground plane ax + by + cz + d = 0 in lidar coordinates
+    #[serde(default)]
+    pub ground_plane: Option<[f32; 4]>,
+    #[serde(default)]
+    pub monocular_fallback: bool,
+    #[serde(default)]
+    pub background: BackgroundConfig,
+    #[serde(default)]
+    pub validity: ValidityConfig,
+    #[serde(default)]
+    pub ground: GroundConfig,
+    #[serde(default)]
+    pub fusion: FusionConfig,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct BackgroundConfig {
+    pub model: String,
+    pub percentile: f32,
+    pub bin_size: f32,
+    pub min_samples: u32,
+    pub frames: Option<[usize; 2]>,
+    pub exclude_detections: bool,
+    pub recording: Option<String>,
+    pub load_dir: Option<String>,
+    pub voxel_size: f32,
+    pub voxel_occupancy: f32,
+}
+
+impl Default for BackgroundConfig {
+    fn default() -> Self {
+        Self {
+            model: "max".to_string(),
+            percentile: 0.5,
+            bin_size: 50.0,
+            min_samples: 3,
+            frames: None,
+            exclude_detections: false,
+            recording: None,
+            load_dir: None,
+            voxel_size: 200.0,
+            voxel_occupancy: 0.1,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ValidityConfig {
+    pub min_range: Option<f32>,
+    pub max_range: Option<f32>,
+    // Degrees from the lidar x axis, counter-clockwise
+    pub min_azimuth: Option<f32>,
+    pub max_azimuth: Option<f32>,
+    pub min_height: Option<f32>,
+    pub max_height: Option<f32>,
+    // [x, y] vertices in field coordinates, needs `field.lidar_to_field`
+    pub arena: Vec<[f32; 2]>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct GroundConfig {
+    pub estimate: bool,
+    pub iterations: usize,
+    pub inlier_distance: f32,
+    pub max_tilt: f32,
+    pub removal_height: f32,
+}
+
+impl Default for GroundConfig {
+    fn default() -> Self {
+        Self {
+            estimate: false,
+            iterations: 500,
+            inlier_distance: 50.0,
+            max_tilt: 30.0,
+            removal_height: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct FusionConfig {
+    pub merge_distance: f32,
+    pub duplicate_distance: f32,
+}
+
+impl Default for FusionConfig {
+    fn default() -> Self {
+        Self {
+            merge_distance: 600.0,
+            duplicate_distance: 300.0,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
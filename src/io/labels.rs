@@ -0,0 +1,115 @@
+use std::{fs::File, io::BufWriter, path::Path};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{error, span, trace, Level};
+
+use crate::{config::ExportConfig, radar::fuse::FusedRobot};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LabelOutputOptions {
+    pub min_confidence: f32,
+    pub min_points: usize,
+    // Writes a json sidecar per label file, see `LabelMeta`.
+    pub metadata: bool,
+}
+
+impl LabelOutputOptions {
+    pub fn from_config(config: &ExportConfig) -> Self {
+        Self {
+            min_confidence: config.min_label_confidence,
+            min_points: config.min_label_points,
+            metadata: config.label_metadata,
+        }
+    }
+
+    pub fn accepts(&self, robot: &FusedRobot) -> bool {
+        let accepted =
+            robot.confidence >= self.min_confidence && robot.location.points >= self.min_points;
+        if !accepted {
+            trace!(
+                "Dropped weak label {} with confidence {} and {} points.",
+                robot.label,
+                robot.confidence,
+                robot.location.points
+            );
+        }
+        accepted
+    }
+}
+
+// Quality of one label line, in the same order as the lines of the label file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LabelMeta {
+    pub label: String,
+    pub confidence: f32,
+    // Foreground lidar points inside the detection boxes.
+    pub lidar_points: usize,
+    // Points of the selected cluster the box is computed from.
+    pub cluster_size: usize,
+    pub cameras: Vec<usize>,
+    // Located without lidar points, see `Locator::set_monocular_fallback`.
+    #[serde(default)]
+    pub monocular: bool,
+}
+
+impl From<&FusedRobot> for LabelMeta {
+    fn from(robot: &FusedRobot) -> Self {
+        Self {
+            label: robot.label.name_abbr().to_string(),
+            confidence: robot.confidence,
+            lidar_points: robot.location.roi_points,
+            cluster_size: robot.location.points,
+            cameras: robot.cameras.clone(),
+            monocular: robot.location.monocular,
+        }
+    }
+}
+
+pub fn save_label_meta<P>(metas: &[LabelMeta], file_path: P) -> Result<()>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let span = span!(Level::TRACE, "save_label_meta");
+    let _enter = span.enter();
+
+    let file = File::create(&file_path).map_err(|e| {
+        error!("Failed to create {:?}: {e}", file_path);
+        anyhow!("Failed to create {:?}: {e}", file_path)
+    })?;
+    serde_json::to_writer_pretty(BufWriter::new(file), metas).map_err(|e| {
+        error!("Failed to write label metadata to {:?}: {e}", file_path);
+        anyhow!("Failed to write label metadata to {:?}: {e}", file_path)
+    })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_save_label_meta() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("000001.json");
+        let metas = vec![LabelMeta {
+            label: "B3".to_string(),
+            confidence: 0.75,
+            lidar_points: 120,
+            cluster_size: 96,
+            cameras: vec![0, 2],
+            monocular: false,
+        }];
+
+        save_label_meta(&metas, &path)?;
+        let read: Vec<LabelMeta> = serde_json::from_str(&fs::read_to_string(&path)?)?;
+        assert_eq!(read, metas);
+
+        Ok(())
+    }
+}
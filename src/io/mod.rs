@@ -1,3 +1,5 @@
 pub mod hdf5;
+pub mod labels;
+pub mod npy;
 pub mod pcd;
 pub mod video;
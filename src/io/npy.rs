@@ -0,0 +1,152 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use anyhow::{anyhow, Result};
+use image::{ImageBuffer, Luma};
+use tracing::{error, span, trace, Level};
+
+const NPY_MAGIC: &[u8] = b"\x93NUMPY";
+
+// Writes a depth map as a little-endian float32 NPY array of shape (height, width).
+pub fn save_depth_map_npy<P>(depth_map: &ImageBuffer<Luma<f32>, Vec<f32>>, path: P) -> Result<()>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let span = span!(Level::TRACE, "save_depth_map_npy");
+    let _enter = span.enter();
+
+    let file = File::create(&path).map_err(|e| {
+        error!("Failed to create {:?}: {e}", path);
+        anyhow!("Failed to create {:?}: {e}", path)
+    })?;
+    let mut writer = BufWriter::new(file);
+
+    let (width, height) = depth_map.dimensions();
+    let mut header =
+        format!("{{'descr': '<f4', 'fortran_order': False, 'shape': ({height}, {width}), }}");
+    // Magic, version and header length take 10 bytes, the whole header is padded to 64 bytes.
+    let padding = 63 - (NPY_MAGIC.len() + 4 + header.len()) % 64;
+    header.extend(std::iter::repeat_n(' ', padding));
+    header.push('\n');
+
+    writer.write_all(NPY_MAGIC)?;
+    writer.write_all(&[1, 0])?;
+    writer.write_all(&(header.len() as u16).to_le_bytes())?;
+    writer.write_all(header.as_bytes())?;
+    for value in depth_map.as_raw() {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    writer.flush()?;
+
+    trace!("Saved {width}x{height} depth map to {:?}", path);
+    Ok(())
+}
+
+pub fn read_depth_map_npy<P>(path: P) -> Result<ImageBuffer<Luma<f32>, Vec<f32>>>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let span = span!(Level::TRACE, "read_depth_map_npy");
+    let _enter = span.enter();
+
+    let file = File::open(&path).map_err(|e| {
+        error!("Failed to open {:?}: {e}", path);
+        anyhow!("Failed to open {:?}: {e}", path)
+    })?;
+    let mut reader = BufReader::new(file);
+
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic[..6] != NPY_MAGIC {
+        return Err(anyhow!("{:?} is not a npy file", path));
+    }
+    let header_len = match magic[6] {
+        1 => {
+            let mut len = [0u8; 2];
+            reader.read_exact(&mut len)?;
+            u16::from_le_bytes(len) as usize
+        }
+        2 | 3 => {
+            let mut len = [0u8; 4];
+            reader.read_exact(&mut len)?;
+            u32::from_le_bytes(len) as usize
+        }
+        version => return Err(anyhow!("Unsupported npy version {version}")),
+    };
+    let mut header = vec![0u8; header_len];
+    reader.read_exact(&mut header)?;
+    let header = String::from_utf8(header)?;
+    trace!("Npy header: {}", header.trim());
+
+    if !header.contains("'descr': '<f4'") {
+        return Err(anyhow!(
+            "Expected little-endian float32 data, got header {header}"
+        ));
+    }
+    if !header.contains("'fortran_order': False") {
+        return Err(anyhow!("Fortran order npy is not supported"));
+    }
+    let (height, width) = parse_shape(&header)
+        .ok_or_else(|| anyhow!("Expected a 2d shape in header {}", header.trim()))?;
+
+    let mut data = vec![0u8; height * width * 4];
+    reader.read_exact(&mut data)?;
+    let values: Vec<f32> = data
+        .chunks_exact(4)
+        .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect();
+
+    ImageBuffer::from_raw(width as u32, height as u32, values)
+        .ok_or_else(|| anyhow!("Npy data does not match shape ({height}, {width})"))
+}
+
+fn parse_shape(header: &str) -> Option<(usize, usize)> {
+    let start = header.find("'shape':")? + "'shape':".len();
+    let shape = header[start..].trim_start().strip_prefix('(')?;
+    let shape = &shape[..shape.find(')')?];
+    let dims = shape
+        .split(',')
+        .map(str::trim)
+        .filter(|dim| !dim.is_empty())
+        .map(|dim| dim.parse().ok())
+        .collect::<Option<Vec<usize>>>()?;
+
+    match dims.as_slice() {
+        &[height, width] => Some((height, width)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_depth_map_npy_round_trip() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("background.npy");
+
+        let depth_map = ImageBuffer::from_fn(5, 3, |x, y| Luma([x as f32 * 1000.0 + y as f32]));
+        save_depth_map_npy(&depth_map, &path)?;
+
+        let bytes = fs::read(&path)?;
+        let header_len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+        assert_eq!((10 + header_len) % 64, 0);
+        assert_eq!(bytes.len(), 10 + header_len + 5 * 3 * 4);
+
+        let read = read_depth_map_npy(&path)?;
+        assert_eq!(read, depth_map);
+
+        assert_eq!(parse_shape("{'shape': (3, 5), }"), Some((3, 5)));
+        assert_eq!(parse_shape("{'shape': (3,), }"), None);
+
+        Ok(())
+    }
+}
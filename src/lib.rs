@@ -1,7 +1,6 @@
 use std::{
-    collections::HashMap, 
-    fs::{self, File}, 
-    io::{BufWriter, Write as _}, 
+    fs::{self, File, OpenOptions},
+    io::{BufWriter, Write},
     path::PathBuf,
 };
 
@@ -10,10 +9,18 @@ use anyhow::{anyhow, Result};
 use config::RadarInstanceConfig;
 use image::GenericImageView;
 use indicatif::{ProgressBar, ProgressStyle};
-use io::pcd::save_pointcloud;
+use io::{
+    hdf5::Hdf5PointCloudReader,
+    labels::{save_label_meta, LabelMeta, LabelOutputOptions},
+    pcd::save_pointcloud,
+};
+use nalgebra::Matrix4;
 use radar::{
-    detect::{RobotDetection, RobotDetector},
-    locate::Locator,
+    background::{BackgroundMap, BACKGROUND_DIR},
+    detect::{DetectionExporter, Detector, RobotDetection, RobotDetector},
+    field::{FieldTransform, OutputFrame},
+    fuse::{fuse_observations, Conflict, FusedRobot, FusionOptions, Observation},
+    locate::{LocateMode, Locator},
 };
 use rayon::prelude::*;
 use tracing::{error, info, warn};
@@ -105,8 +112,11 @@ pub fn build_model(detector: &mut RobotDetector) -> Result<()> {
 
 pub fn process_and_save_aligned_frames(
     aligner: &mut FrameAligner,
-    detector: &RobotDetector,
+    detector: &dyn Detector,
     locators: &mut Vec<Locator>,
+    mut exporter: Option<&mut DetectionExporter>,
+    background_recording: Option<&Hdf5PointCloudReader>,
+    output_transform: Option<&FieldTransform>,
     root_dir: &str,
 ) -> Result<Vec<Vec<Option<Vec<RobotDetection>>>>> {
     let align_frame_count = aligner.align_frame_count().map_err(|e| {
@@ -126,6 +136,26 @@ pub fn process_and_save_aligned_frames(
 
     let root_dir = PathBuf::from(root_dir);
 
+    // Points of the empty field recording, the background is built from them only.
+    let recording_frames = background_recording
+        .map(|reader| {
+            (0..reader.get_frame_num())
+                .map(|frame_idx| {
+                    reader.read_pointcloud_frame(frame_idx).map(|points| {
+                        points
+                            .into_iter()
+                            .map(|point| point * 1000.0)
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect::<Result<Vec<_>>>()
+        })
+        .transpose()
+        .map_err(|e| {
+            error!("Failed to read background recording: {e}");
+            e
+        })?;
+
     let detect_results = aligner
         .aligned_frame_iter()
         .map_err(|e| {
@@ -136,14 +166,50 @@ pub fn process_and_save_aligned_frames(
         .map(|(frame_idx, (images, point_cloud))| {
             progress_bar.set_position(frame_idx as u64);
 
+            let detections = images.iter().enumerate().map(|(idx, image)| {
+                if let Some(image) = image {
+                    detector.detect(image, idx, frame_idx).map_err(|e| {
+                        error!("Failed to detect image {idx} of frame {frame_idx}: {e}");
+                        e
+                    }).ok()
+                } else {
+                    warn!("Image {idx} of frame {frame_idx} is empty, skipped detect.");
+                    None
+                }
+            }).collect::<Vec<_>>();
+
             if let Some(point_cloud) = point_cloud {
                 let point_cloud: Vec<_> = point_cloud.into_par_iter().map(|point| point * 1000.0).collect();
                 locators.par_iter_mut().enumerate().for_each(|(idx, locator)| {
+                    match recording_frames.as_ref().and_then(|frames| frames.first()) {
+                        Some(points) => locator.update_ground_plane(points),
+                        None => locator.update_ground_plane(&point_cloud),
+                    }
+
                     if let Some(image_size) = images[idx]
                         .as_ref()
-                        .and_then(|image| Some(image.dimensions()))
+                        .map(|image| image.dimensions())
                     {
-                        if let Err(e) = locator.update_background_depth_map(&point_cloud, image_size) {
+                        if let Some(recording_frames) = recording_frames.as_ref() {
+                            if !locator.has_background() {
+                                for points in recording_frames {
+                                    if let Err(e) = locator.update_background_depth_map(points, image_size) {
+                                        error!("Failed to update background depth map {idx} from recording: {e}");
+                                    }
+                                }
+                            }
+                            return;
+                        }
+                        if !locator.background_options().contains_frame(frame_idx) {
+                            return;
+                        }
+
+                        let exclusions: Vec<_> = detections[idx]
+                            .iter()
+                            .flatten()
+                            .map(|detection| detection.bbox())
+                            .collect();
+                        if let Err(e) = locator.update_background(&point_cloud, image_size, &exclusions) {
                             error!("Failed to update background depth map for frame {frame_idx}: {e}");
                         }
                     } else {
@@ -151,8 +217,11 @@ pub fn process_and_save_aligned_frames(
                     }
                 });
 
+                let output_points = output_transform.map(|transform| {
+                    point_cloud.par_iter().map(|point| transform.to_field_output(point)).collect::<Vec<_>>()
+                });
                 if let Err(e) = save_pointcloud(
-                    &point_cloud,
+                    output_points.as_deref().unwrap_or(&point_cloud),
                     root_dir.join(format!("points/{:06}.pcd", frame_idx)),
                 ) {
                     error!("Failed to save point cloud of frame {frame_idx}: {e}");
@@ -162,17 +231,15 @@ pub fn process_and_save_aligned_frames(
                 warn!("Point cloud of frame {frame_idx} is empty, skipped point cloud save.");
             }
 
-            let detections = images.iter().enumerate().map(|(idx, image)| {
-                if let Some(image) = image {
-                    detector.detect(image).map_err(|e| {
-                        error!("Failed to detect image {idx} of frame {frame_idx}: {e}");
-                        e
-                    }).ok()
-                } else {
-                    warn!("Image {idx} of frame {frame_idx} is empty, skipped detect.");
-                    None
-                }
-            }).collect::<Vec<_>>();
+            if let Some(exporter) = exporter.as_deref_mut() {
+                detections.iter().zip(images.iter()).enumerate().for_each(|(idx, (detection, image))| {
+                    if let (Some(detection), Some(image)) = (detection, image) {
+                        if let Err(e) = exporter.add(idx, frame_idx, image.dimensions(), detection) {
+                            error!("Failed to export detections of image {idx} of frame {frame_idx}: {e}");
+                        }
+                    }
+                });
+            }
 
             images.into_iter().enumerate().for_each(|(idx, image)| {
                 if let Some(image) = image {
@@ -188,6 +255,13 @@ pub fn process_and_save_aligned_frames(
         })
         .collect::<Vec<_>>();
 
+    if let Some(exporter) = exporter {
+        exporter.finish().map_err(|e| {
+            error!("Failed to finish detection export: {e}");
+            e
+        })?;
+    }
+
     progress_bar.finish_with_message("Finished frame processing and saving.");
     Ok(detect_results)
 }
@@ -196,6 +270,9 @@ pub fn locate_and_save_results(
     detect_results_frames: Vec<Vec<Option<Vec<RobotDetection>>>>,
     aligner: &mut FrameAligner,
     locators: &mut Vec<Locator>,
+    fusion_options: &FusionOptions,
+    label_options: &LabelOutputOptions,
+    output_transform: Option<&FieldTransform>,
     root_dir: &str,
 ) -> Result<()> {
     let progress_bar = ProgressBar::new(detect_results_frames.len() as u64);
@@ -209,6 +286,20 @@ pub fn locate_and_save_results(
 
     let root_dir = PathBuf::from(root_dir);
 
+    if label_options.metadata {
+        let meta_dir = root_dir.join("labels_meta");
+        fs::create_dir_all(&meta_dir).map_err(|e| {
+            error!("Failed to create directory {:?}: {e}", meta_dir);
+            e
+        })?;
+    }
+
+    let conflict_path = root_dir.join("conflicts.txt");
+    let mut conflict_writer = BufWriter::new(File::create(&conflict_path).map_err(|e| {
+        error!("Failed to create {:?}: {e}", conflict_path);
+        e
+    })?);
+
     let aligner_iter = aligner.aligned_frame_iter().map_err(|e| {
         error!("Failed to extract iterator for aligner: {e}");
         e
@@ -275,23 +366,44 @@ pub fn locate_and_save_results(
             if let Some(locate_results) = locate_results {
                 let mut writer = BufWriter::new(file);
 
-                let mut results_map = HashMap::with_capacity(locate_results.len());
-                locate_results
+                let observations: Vec<_> = locate_results
                     .into_iter()
                     .zip(detect_results.into_iter())
-                    .for_each(|(locate_result, detect_result)| {
-                        if locate_result.is_some() && detect_result.is_some() {
-                            let locate_result = locate_result.unwrap();
-                            let detect_result = detect_result.unwrap();
-                            locate_result.into_iter().zip(detect_result.into_iter()).for_each(|(single_locate_result, single_detct_result)| {
-                                if single_locate_result.is_some() {
-                                    results_map.insert(single_detct_result.label, single_locate_result.unwrap());
-                                }
-                            });
-                        }
-                    });
-                
-                for (label, location) in results_map {
+                    .enumerate()
+                    .filter_map(|(camera_idx, (locate_result, detect_result))| {
+                        Some((camera_idx, locate_result?, detect_result?))
+                    })
+                    .flat_map(|(camera_idx, locate_result, detect_result)| {
+                        locate_result.into_iter().zip(detect_result).filter_map(
+                            move |(single_locate_result, single_detect_result)| {
+                                Some(Observation {
+                                    camera_idx,
+                                    label: single_detect_result.label,
+                                    confidence: single_detect_result.confidence,
+                                    location: single_locate_result?,
+                                })
+                            },
+                        )
+                    })
+                    .collect();
+
+                let fusion = fuse_observations(observations, fusion_options);
+                for conflict in fusion.conflicts.iter() {
+                    if let Err(e) = write_conflict(&mut conflict_writer, frame_idx, conflict) {
+                        error!("Failed to write conflict of frame {frame_idx}: {e}");
+                    }
+                }
+
+                let mut metas = Vec::with_capacity(fusion.robots.len());
+                for robot in fusion
+                    .robots
+                    .iter()
+                    .filter(|robot| label_options.accepts(robot))
+                {
+                    let FusedRobot { label, location, .. } = robot;
+                    let field_location = output_transform
+                        .map(|transform| transform.location_to_field_output(location));
+                    let location = field_location.as_ref().unwrap_or(location);
                     let line = format!(
                         "{:.2} {:.2} {:.2} {:.2} {:.2} {:.2} {:.2} {}\n",
                         location.center.x,
@@ -300,21 +412,152 @@ pub fn locate_and_save_results(
                         location.depth,
                         location.width,
                         location.height,
-                        0.0,
+                        location.yaw,
                         label.name_abbr()
                     );
                     if let Err(e) = writer.write_all(line.as_bytes()) {
                         error!("Failed to write to buffer: {e}");
                     }
+                    metas.push(LabelMeta::from(robot));
+                }
+
+                if label_options.metadata {
+                    let meta_path = root_dir.join(format!("labels_meta/{:06}.json", frame_idx));
+                    if let Err(e) = save_label_meta(&metas, &meta_path) {
+                        error!("Failed to save label metadata of frame {frame_idx}: {e}");
+                    }
                 }
             }
         });
 
+    conflict_writer.flush()?;
+
     progress_bar.finish_with_message("Finished locating and saving results.");
     Ok(())
 }
 
-pub fn save_calibs(radar_instances: &[RadarInstanceConfig], root_dir: &str) -> Result<()> {
+// One line per conflict: "<frame> split <label> <distance> <cameras> <cameras>" or
+// "<frame> duplicate <label> <label> <distance> <cameras> <cameras>", cameras joined by commas.
+fn write_conflict(writer: &mut impl Write, frame_idx: usize, conflict: &Conflict) -> Result<()> {
+    let join = |cameras: &[usize]| {
+        cameras
+            .iter()
+            .map(|camera_idx| camera_idx.to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    let line = match conflict {
+        Conflict::SplitLabel {
+            label,
+            distance,
+            cameras,
+        } => format!(
+            "{:06} split {} {:.2} {} {}\n",
+            frame_idx,
+            label.name_abbr(),
+            distance,
+            join(&cameras.0),
+            join(&cameras.1)
+        ),
+        Conflict::DuplicateRobot {
+            labels,
+            distance,
+            cameras,
+        } => format!(
+            "{:06} duplicate {} {} {:.2} {} {}\n",
+            frame_idx,
+            labels.0.name_abbr(),
+            labels.1.name_abbr(),
+            distance,
+            join(&cameras.0),
+            join(&cameras.1)
+        ),
+    };
+    writer.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+// Loads `<load_dir>/<mark>.npy` into the locator of each mark. A missing map or one built with
+// another calibration is skipped, the locator builds its background from the frames then.
+pub fn load_backgrounds(locators: &mut [Locator], marks: &[String], load_dir: &str) {
+    let load_dir = PathBuf::from(load_dir);
+
+    for (locator, mark) in locators.iter_mut().zip(marks) {
+        let file_path = load_dir.join(format!("{mark}.npy"));
+        if !file_path.exists() {
+            warn!("No saved background for {mark} in {:?}, building it.", load_dir);
+            continue;
+        }
+
+        match BackgroundMap::load(&file_path).and_then(|map| locator.load_background(map)) {
+            Ok(()) => info!("Loaded background for {mark} from {:?}", file_path),
+            Err(e) => warn!("Failed to load background {:?}, building it: {e}", file_path),
+        }
+    }
+}
+
+// Saves the depth map background of each locator to `<root_dir>/backgrounds/<mark>.npy`,
+// frustum locators keep a voxel grid instead and are skipped.
+pub fn save_backgrounds(locators: &mut [Locator], marks: &[String], root_dir: &str) -> Result<()> {
+    let background_dir = PathBuf::from(root_dir).join(BACKGROUND_DIR);
+
+    for (locator, mark) in locators.iter_mut().zip(marks) {
+        if locator.locate_mode() == LocateMode::Frustum {
+            info!("Frustum locating keeps a voxel background for {mark}, nothing to save.");
+            continue;
+        }
+        match locator.background(mark) {
+            Some(map) => map.save(&background_dir)?,
+            None => warn!("No background built for {mark}, nothing to save."),
+        }
+    }
+
+    Ok(())
+}
+
+// Appends the ground plane of each locator, configured or estimated, to the calib file of its
+// instance as "ground_plane a b c d" with ax + by + cz + d = 0 in lidar mm.
+pub fn save_ground_planes(
+    locators: &[Locator],
+    marks: &[String],
+    radar_instances: &[RadarInstanceConfig],
+    root_dir: &str,
+) -> Result<()> {
+    let root_dir = PathBuf::from(root_dir);
+
+    for (locator, mark) in locators.iter().zip(marks) {
+        let Some(ground_plane) = locator.ground_plane() else {
+            continue;
+        };
+        let idx = radar_instances
+            .iter()
+            .position(|instance| instance.name == *mark)
+            .ok_or_else(|| anyhow!("Failed to find instance config for mark {mark}"))?;
+        let file_path = root_dir.join(format!("calibs/{:06}.txt", idx));
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(&file_path)
+            .map_err(|e| {
+                error!("Failed to open {:?}: {e}", file_path);
+                e
+            })?;
+        let normal = ground_plane.normal;
+        write!(
+            file,
+            "\nground_plane {} {} {} {}",
+            normal.x, normal.y, normal.z, ground_plane.offset
+        )?;
+    }
+
+    Ok(())
+}
+
+pub fn save_calibs(
+    radar_instances: &[RadarInstanceConfig],
+    field_transform: Option<&FieldTransform>,
+    output_frame: OutputFrame,
+    root_dir: &str,
+) -> Result<()> {
     let root_dir = PathBuf::from(root_dir);
 
     for (idx, instance) in radar_instances.iter().enumerate() {
@@ -327,25 +570,45 @@ pub fn save_calibs(radar_instances: &[RadarInstanceConfig], root_dir: &str) -> R
         
         let mut writer = BufWriter::new(file);
 
+        // lidar2cam takes the saved points to the camera, in field metres for field output.
+        let lidar_to_camera = Matrix4::from_row_slice(&instance.lidar_to_camera);
+        let points_to_camera = match (output_frame, field_transform) {
+            (OutputFrame::Lidar, _) => lidar_to_camera,
+            (OutputFrame::Field, Some(field_transform)) => {
+                field_transform.output_to_camera(&lidar_to_camera)
+            }
+            (OutputFrame::Field, None) => {
+                return Err(anyhow!("Field output frame needs a lidar to field transform"));
+            }
+        };
+        let row_major = |matrix: &Matrix4<f32>| {
+            matrix
+                .transpose()
+                .iter()
+                .map(|value| value.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+
         let line = format!(
             "P{} {} {} {} {} {} {} {} {} {}\n\
-            lidar2cam{} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {}",
+            lidar2cam{} {}",
             idx,
             instance.intrinsic[0], instance.intrinsic[1], instance.intrinsic[2],
             instance.intrinsic[3], instance.intrinsic[4], instance.intrinsic[5],
             instance.intrinsic[6], instance.intrinsic[7], instance.intrinsic[8],
             idx,
-            instance.lidar_to_camera[0], instance.lidar_to_camera[1],
-            instance.lidar_to_camera[2], instance.lidar_to_camera[3],
-            instance.lidar_to_camera[4], instance.lidar_to_camera[5],
-            instance.lidar_to_camera[6], instance.lidar_to_camera[7],
-            instance.lidar_to_camera[8], instance.lidar_to_camera[9],
-            instance.lidar_to_camera[10], instance.lidar_to_camera[11],
-            instance.lidar_to_camera[12], instance.lidar_to_camera[13],
-            instance.lidar_to_camera[14], instance.lidar_to_camera[15]
+            row_major(&points_to_camera)
         );
         
         writer.write_all(line.as_bytes())?;
+
+        // Row-major lidar to field transform in mm, only for points saved in lidar coordinates.
+        if let (OutputFrame::Lidar, Some(field_transform)) = (output_frame, field_transform) {
+            writer.write_all(
+                format!("\nlidar2field {}", row_major(field_transform.matrix())).as_bytes(),
+            )?;
+        }
     }
 
     Ok(())
@@ -6,9 +6,20 @@ use radar_to_mmdet3d::{
     align::FrameAligner,
     build_model,
     config::{RadarConfig, SourceConfig},
-    create_output_dirs, locate_and_save_results, process_and_save_aligned_frames,
-    radar::{detect::RobotDetector, locate::Locator},
-    save_calibs, set_output_dir_name,
+    create_output_dirs,
+    io::{hdf5::Hdf5PointCloudReader, labels::LabelOutputOptions},
+    load_backgrounds, locate_and_save_results, process_and_save_aligned_frames,
+    radar::{
+        detect::{
+            ArmorCropExporter, AssignmentExporter, DetectionExporter, DetectionFormat, Detector,
+            LabelTaxonomy, PrecomputedDetector, RobotDetector,
+        },
+        field::{FieldTransform, OutputFrame},
+        fuse::FusionOptions,
+        locate::Locator,
+        validity::ValidityVolume,
+    },
+    save_backgrounds, save_calibs, save_ground_planes, set_output_dir_name,
 };
 use tracing::{error, span, Level};
 use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
@@ -40,40 +51,166 @@ fn main() -> Result<()> {
         error!("Failed to load radar configuration: {e}");
         e
     })?;
-    save_calibs(&radar_config.instances, output_dir.as_str()).map_err(|e| {
-        error!("Failed to save calibs: {e}");
+    // Monocular labels are only told apart from lidar ones in the label metadata.
+    if radar_config.locate.monocular_fallback && !radar_config.export.label_metadata {
+        error!("Monocular fallback needs export.label_metadata to mark its labels");
+        return Err(anyhow!(
+            "Monocular fallback needs export.label_metadata to mark its labels"
+        ));
+    }
+    let field_transform = FieldTransform::from_config(&radar_config.field).map_err(|e| {
+        error!("Invalid lidar to field transform: {e}");
         e
     })?;
-
-    let mut detector = RobotDetector::from_config(&radar_config.detect).map_err(|e| {
-        error!("Failed to initialize detector from config: {e}");
+    let output_frame = OutputFrame::try_from(radar_config.field.output_frame.as_str())?;
+    let output_transform = match output_frame {
+        OutputFrame::Lidar => None,
+        OutputFrame::Field => Some(field_transform.ok_or_else(|| {
+            anyhow!("Field output frame needs a lidar to field transform or landmarks")
+        })?),
+    };
+    save_calibs(
+        &radar_config.instances,
+        field_transform.as_ref(),
+        output_frame,
+        output_dir.as_str(),
+    )
+    .map_err(|e| {
+        error!("Failed to save calibs: {e}");
         e
     })?;
-    build_model(&mut detector).map_err(|e| {
-        error!("Failed to build detector model: {e}");
+
+    let mut taxonomy = LabelTaxonomy::from_config(&radar_config.labels).map_err(|e| {
+        error!("Failed to build label taxonomy from config: {e}");
         e
     })?;
+    if radar_config.detect.unknown.enabled {
+        taxonomy = taxonomy.with_unknown_labels()?;
+    }
 
-    let mut locators = aligner
-        .video_marks()
-        .into_iter()
+    let detector: Box<dyn Detector> = match radar_config.detect.backend.as_str() {
+        "model" => {
+            let mut detector = RobotDetector::from_config(&radar_config.detect, &taxonomy)
+                .map_err(|e| {
+                    error!("Failed to initialize detector from config: {e}");
+                    e
+                })?;
+            build_model(&mut detector).map_err(|e| {
+                error!("Failed to build detector model: {e}");
+                e
+            })?;
+            if radar_config.export.armor_crops {
+                let mut crop_exporter = ArmorCropExporter::new(
+                    output_dir.as_str(),
+                    radar_config.export.armor_ambiguity_ratio,
+                )
+                .map_err(|e| {
+                    error!("Failed to initialize armor crop exporter: {e}");
+                    e
+                })?;
+                crop_exporter.set_taxonomy(taxonomy.clone());
+                detector.set_crop_exporter(crop_exporter);
+            }
+            if radar_config.export.assignment_records {
+                let assignment_exporter =
+                    AssignmentExporter::new(output_dir.as_str()).map_err(|e| {
+                        error!("Failed to initialize label assignment exporter: {e}");
+                        e
+                    })?;
+                detector.set_assignment_exporter(assignment_exporter);
+            }
+            Box::new(detector)
+        }
+        "precomputed" => {
+            let precomputed_config = radar_config
+                .detect
+                .precomputed
+                .as_ref()
+                .ok_or_else(|| anyhow!("Missing precomputed config for precomputed backend"))?;
+            Box::new(
+                PrecomputedDetector::from_config(precomputed_config, &taxonomy).map_err(|e| {
+                    error!("Failed to initialize precomputed detector from config: {e}");
+                    e
+                })?,
+            )
+        }
+        backend => return Err(anyhow!("Unknown detector backend {backend}")),
+    };
+
+    let validity = ValidityVolume::from_config(&radar_config.locate.validity, field_transform)
+        .map_err(|e| {
+            error!("Invalid validity volume: {e}");
+            e
+        })?;
+
+    let marks = aligner.video_marks();
+    let mut locators = marks
+        .iter()
         .map(|mark| {
             let instance_config = radar_config
                 .instances
                 .iter()
-                .find(|instance_config| instance_config.name == mark)
+                .find(|instance_config| instance_config.name == *mark)
                 .ok_or_else(|| anyhow!("Failed to find instance config for mark {mark}"))?;
 
-            Locator::from_config(&radar_config.locate, instance_config).map_err(|e| {
-                error!("Failed to create locator for mark {mark}: {e}");
-                e
-            })
+            let mut locator =
+                Locator::from_config(&radar_config.locate, instance_config).map_err(|e| {
+                    error!("Failed to create locator for mark {mark}: {e}");
+                    e
+                })?;
+            locator.set_validity(validity.clone());
+            Ok(locator)
         })
-        .collect::<Result<Vec<_>, _>>()?;
+        .collect::<Result<Vec<_>>>()?;
+    if let Some(load_dir) = radar_config.locate.background.load_dir.as_deref() {
+        load_backgrounds(&mut locators, &marks, load_dir);
+    }
+    let detection_formats = radar_config
+        .export
+        .detection_formats
+        .iter()
+        .map(|format| DetectionFormat::try_from(format.as_str()))
+        .collect::<Result<Vec<_>>>()
+        .map_err(|e| {
+            error!("Invalid detection export format: {e}");
+            e
+        })?;
+    let mut exporter = if detection_formats.is_empty() {
+        None
+    } else {
+        Some(
+            DetectionExporter::new(
+                output_dir.as_str(),
+                num_videos,
+                detection_formats,
+                &taxonomy,
+            )
+            .map_err(|e| {
+                error!("Failed to initialize detection exporter: {e}");
+                e
+            })?,
+        )
+    };
+
+    let background_recording = radar_config
+        .locate
+        .background
+        .recording
+        .as_deref()
+        .map(Hdf5PointCloudReader::from_file)
+        .transpose()
+        .map_err(|e| {
+            error!("Failed to open background recording: {e}");
+            e
+        })?;
+
     let detect_result = process_and_save_aligned_frames(
         &mut aligner,
-        &detector,
+        detector.as_ref(),
         &mut locators,
+        exporter.as_mut(),
+        background_recording.as_ref(),
+        output_transform.as_ref(),
         output_dir.as_str(),
     )
     .map_err(|e| {
@@ -81,10 +218,28 @@ fn main() -> Result<()> {
         e
     })?;
 
+    save_backgrounds(&mut locators, &marks, output_dir.as_str()).map_err(|e| {
+        error!("Failed to save backgrounds: {e}");
+        e
+    })?;
+    save_ground_planes(
+        &locators,
+        &marks,
+        &radar_config.instances,
+        output_dir.as_str(),
+    )
+    .map_err(|e| {
+        error!("Failed to save ground planes: {e}");
+        e
+    })?;
+
     locate_and_save_results(
         detect_result,
         &mut aligner,
         &mut locators,
+        &FusionOptions::from_config(&radar_config.locate.fusion),
+        &LabelOutputOptions::from_config(&radar_config.export),
+        output_transform.as_ref(),
         output_dir.as_str(),
     )
     .map_err(|e| {
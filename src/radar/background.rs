@@ -0,0 +1,488 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+};
+
+use anyhow::{anyhow, Result};
+use image::{ImageBuffer, Luma};
+use nalgebra::Point3;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, span, Level};
+
+use crate::{
+    config::BackgroundConfig,
+    io::npy::{read_depth_map_npy, save_depth_map_npy},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum BackgroundModel {
+    // Farthest depth seen at each pixel.
+    #[default]
+    Max,
+    // Per-pixel depth histogram, the background is the given percentile of the samples.
+    // Pixels with fewer than `min_samples` samples have no background.
+    Percentile {
+        percentile: f32,
+        bin_size: f32,
+        min_samples: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BackgroundOptions {
+    pub model: BackgroundModel,
+    // Frames [start, end) the background is built from, all frames if none.
+    pub frames: Option<(usize, usize)>,
+    // Skips points inside detected cars when updating the background.
+    pub exclude_detections: bool,
+    // Edge of the cubic voxels of the occupancy background, used by frustum locating.
+    pub voxel_size: f32,
+    // Share of background frames a voxel is occupied in to be background.
+    pub voxel_occupancy: f32,
+}
+
+impl Default for BackgroundOptions {
+    fn default() -> Self {
+        Self {
+            model: BackgroundModel::default(),
+            frames: None,
+            exclude_detections: false,
+            voxel_size: 200.0,
+            voxel_occupancy: 0.1,
+        }
+    }
+}
+
+impl BackgroundOptions {
+    pub fn from_config(config: &BackgroundConfig) -> Result<Self> {
+        let model = match config.model.to_lowercase().as_str() {
+            "max" => BackgroundModel::Max,
+            "median" | "percentile" => {
+                let percentile = if config.model.eq_ignore_ascii_case("median") {
+                    0.5
+                } else {
+                    config.percentile
+                };
+                if !(0.0..=1.0).contains(&percentile) {
+                    return Err(anyhow!("Percentile {percentile} is not in [0, 1]"));
+                }
+                if !config.bin_size.is_normal() || config.bin_size < 0.0 {
+                    return Err(anyhow!("Bin size {} is not positive", config.bin_size));
+                }
+                BackgroundModel::Percentile {
+                    percentile,
+                    bin_size: config.bin_size,
+                    min_samples: config.min_samples,
+                }
+            }
+            _ => {
+                return Err(anyhow!(
+                    "Failed to convert {} to background model",
+                    config.model
+                ))
+            }
+        };
+
+        if !config.voxel_size.is_normal() || config.voxel_size < 0.0 {
+            return Err(anyhow!("Voxel size {} is not positive", config.voxel_size));
+        }
+        if !(0.0..=1.0).contains(&config.voxel_occupancy) {
+            return Err(anyhow!(
+                "Voxel occupancy {} is not in [0, 1]",
+                config.voxel_occupancy
+            ));
+        }
+
+        Ok(Self {
+            model,
+            frames: config.frames.map(|[start, end]| (start, end)),
+            exclude_detections: config.exclude_detections,
+            voxel_size: config.voxel_size,
+            voxel_occupancy: config.voxel_occupancy,
+        })
+    }
+
+    #[inline]
+    pub fn contains_frame(&self, frame_idx: usize) -> bool {
+        self.frames
+            .is_none_or(|(start, end)| (start..end).contains(&frame_idx))
+    }
+}
+
+// Sparse per-pixel depth histograms, each pixel keeps (bin, count) pairs sorted by bin.
+#[derive(Debug, Clone)]
+pub struct DepthHistogram {
+    width: u32,
+    height: u32,
+    bin_size: f32,
+    bins: Vec<Vec<(u16, u32)>>,
+}
+
+impl DepthHistogram {
+    pub fn new(width: u32, height: u32, bin_size: f32) -> Self {
+        Self {
+            width,
+            height,
+            bin_size,
+            bins: vec![Vec::new(); (width * height) as usize],
+        }
+    }
+
+    #[inline]
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn add(&mut self, u: u32, v: u32, depth: f32) {
+        let bin = (depth / self.bin_size).clamp(0.0, u16::MAX as f32) as u16;
+        let pixel = &mut self.bins[(v * self.width + u) as usize];
+        match pixel.binary_search_by_key(&bin, |&(bin, _)| bin) {
+            Ok(idx) => pixel[idx].1 += 1,
+            Err(idx) => pixel.insert(idx, (bin, 1)),
+        }
+    }
+
+    pub fn percentile_map(
+        &self,
+        percentile: f32,
+        min_samples: u32,
+    ) -> ImageBuffer<Luma<f32>, Vec<f32>> {
+        let span = span!(Level::TRACE, "DepthHistogram::percentile_map");
+        let _enter = span.enter();
+
+        let mut depth_map = ImageBuffer::new(self.width, self.height);
+        let mut background_pixels = 0;
+        for (pixel, bins) in depth_map.pixels_mut().zip(self.bins.iter()) {
+            let total: u32 = bins.iter().map(|&(_, count)| count).sum();
+            if total == 0 || total < min_samples {
+                continue;
+            }
+
+            let rank = ((total as f32 * percentile).ceil() as u32).clamp(1, total);
+            let mut cumulative = 0;
+            for &(bin, count) in bins {
+                cumulative += count;
+                if cumulative >= rank {
+                    *pixel = Luma([(bin as f32 + 0.5) * self.bin_size]);
+                    background_pixels += 1;
+                    break;
+                }
+            }
+        }
+
+        debug!(
+            "Built {}th percentile background with {background_pixels} pixels",
+            percentile * 100.0
+        );
+        depth_map
+    }
+}
+
+// Sparse voxel occupancy of the empty field, counting the frames each voxel holds points in.
+#[derive(Debug, Clone)]
+pub struct VoxelGrid {
+    voxel_size: f32,
+    occupancy: f32,
+    frames: u32,
+    counts: HashMap<(i32, i32, i32), u32>,
+}
+
+impl VoxelGrid {
+    pub fn new(voxel_size: f32, occupancy: f32) -> Self {
+        Self {
+            voxel_size,
+            occupancy,
+            frames: 0,
+            counts: HashMap::new(),
+        }
+    }
+
+    #[inline]
+    fn voxel(&self, point: &Point3<f32>) -> (i32, i32, i32) {
+        (
+            (point.x / self.voxel_size).floor() as i32,
+            (point.y / self.voxel_size).floor() as i32,
+            (point.z / self.voxel_size).floor() as i32,
+        )
+    }
+
+    // Adds the points of one frame.
+    pub fn add_frame<'a>(&mut self, points: impl IntoIterator<Item = &'a Point3<f32>>) {
+        let voxels: HashSet<_> = points.into_iter().map(|point| self.voxel(point)).collect();
+        for voxel in voxels {
+            *self.counts.entry(voxel).or_insert(0) += 1;
+        }
+        self.frames += 1;
+        debug!(
+            "Voxel grid has {} voxels after {} frames.",
+            self.counts.len(),
+            self.frames
+        );
+    }
+
+    pub fn is_background(&self, point: &Point3<f32>) -> bool {
+        let min_count = ((self.frames as f32 * self.occupancy).ceil() as u32).max(1);
+        self.counts
+            .get(&self.voxel(point))
+            .is_some_and(|&count| count >= min_count)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeMode {
+    Max,
+    Median,
+}
+
+impl TryFrom<&str> for MergeMode {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> std::result::Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "max" => Ok(MergeMode::Max),
+            "median" => Ok(MergeMode::Median),
+            _ => Err(anyhow!("Failed to convert {value} to merge mode")),
+        }
+    }
+}
+
+// Directory of the saved background depth maps in the output directory.
+pub const BACKGROUND_DIR: &str = "backgrounds";
+
+// Sidecar of a saved background map, maps are only reused with the calibration they were built with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BackgroundMeta {
+    pub name: String,
+    pub calibration_hash: String,
+    pub width: u32,
+    pub height: u32,
+    pub model: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct BackgroundMap {
+    pub meta: BackgroundMeta,
+    pub depth_map: ImageBuffer<Luma<f32>, Vec<f32>>,
+}
+
+impl BackgroundMap {
+    // Writes `<dir>/<name>.npy` and `<dir>/<name>.json`.
+    pub fn save<P: AsRef<Path>>(&self, dir: P) -> Result<()> {
+        let span = span!(Level::TRACE, "BackgroundMap::save");
+        let _enter = span.enter();
+
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir).map_err(|e| {
+            error!("Failed to create directory {:?}: {e}", dir);
+            anyhow!("Failed to create directory {:?}: {e}", dir)
+        })?;
+
+        save_depth_map_npy(&self.depth_map, dir.join(format!("{}.npy", self.meta.name)))?;
+        let meta_path = dir.join(format!("{}.json", self.meta.name));
+        fs::write(&meta_path, serde_json::to_string_pretty(&self.meta)?).map_err(|e| {
+            error!("Failed to write {:?}: {e}", meta_path);
+            anyhow!("Failed to write {:?}: {e}", meta_path)
+        })?;
+
+        debug!("Saved background map {} to {:?}", self.meta.name, dir);
+        Ok(())
+    }
+
+    // Reads a map saved by `save` from its npy path, the sidecar sits next to it.
+    pub fn load<P: AsRef<Path>>(npy_path: P) -> Result<Self> {
+        let span = span!(Level::TRACE, "BackgroundMap::load");
+        let _enter = span.enter();
+
+        let npy_path = npy_path.as_ref();
+        let meta_path = npy_path.with_extension("json");
+        let meta: BackgroundMeta =
+            serde_json::from_str(&fs::read_to_string(&meta_path).map_err(|e| {
+                error!("Failed to read {:?}: {e}", meta_path);
+                anyhow!("Failed to read {:?}: {e}", meta_path)
+            })?)?;
+        let depth_map = read_depth_map_npy(npy_path)?;
+        if depth_map.dimensions() != (meta.width, meta.height) {
+            return Err(anyhow!(
+                "Background map {:?} is {:?}, its sidecar says {}x{}",
+                npy_path,
+                depth_map.dimensions(),
+                meta.width,
+                meta.height
+            ));
+        }
+
+        Ok(Self { meta, depth_map })
+    }
+
+    // Share of pixels with a background depth, and the min, mean and max of those depths.
+    pub fn coverage(&self) -> (f32, f32, f32, f32) {
+        let depths: Vec<_> = self
+            .depth_map
+            .as_raw()
+            .iter()
+            .copied()
+            .filter(|depth| depth.is_normal())
+            .collect();
+        if depths.is_empty() {
+            return (0.0, 0.0, 0.0, 0.0);
+        }
+
+        let (min, max, sum) = depths
+            .iter()
+            .fold((f32::MAX, f32::MIN, 0.0f64), |(min, max, sum), &depth| {
+                (min.min(depth), max.max(depth), sum + depth as f64)
+            });
+        (
+            depths.len() as f32 / self.depth_map.as_raw().len() as f32,
+            min,
+            (sum / depths.len() as f64) as f32,
+            max,
+        )
+    }
+}
+
+// Merges maps of the same camera and calibration from several sessions. Pixels without a
+// background in some sessions take the others.
+pub fn merge_background_maps(maps: &[BackgroundMap], mode: MergeMode) -> Result<BackgroundMap> {
+    let span = span!(Level::TRACE, "merge_background_maps");
+    let _enter = span.enter();
+
+    let first = maps
+        .first()
+        .ok_or_else(|| anyhow!("No background map to merge"))?;
+    if let Some(map) = maps
+        .iter()
+        .find(|map| map.meta.calibration_hash != first.meta.calibration_hash)
+    {
+        return Err(anyhow!(
+            "Calibration hash {} of {} differs from {} of {}",
+            map.meta.calibration_hash,
+            map.meta.name,
+            first.meta.calibration_hash,
+            first.meta.name
+        ));
+    }
+    if let Some(map) = maps
+        .iter()
+        .find(|map| map.depth_map.dimensions() != first.depth_map.dimensions())
+    {
+        return Err(anyhow!(
+            "Dimensions {:?} of {} differ from {:?} of {}",
+            map.depth_map.dimensions(),
+            map.meta.name,
+            first.depth_map.dimensions(),
+            first.meta.name
+        ));
+    }
+
+    let (width, height) = first.depth_map.dimensions();
+    let depth_map = ImageBuffer::from_fn(width, height, |x, y| {
+        let mut depths: Vec<f32> = maps
+            .iter()
+            .map(|map| map.depth_map.get_pixel(x, y).0[0])
+            .filter(|depth| depth.is_normal())
+            .collect();
+        if depths.is_empty() {
+            return Luma([0.0]);
+        }
+        match mode {
+            MergeMode::Max => Luma([depths.iter().copied().fold(0.0, f32::max)]),
+            MergeMode::Median => {
+                depths.sort_by(f32::total_cmp);
+                Luma([depths[depths.len() / 2]])
+            }
+        }
+    });
+
+    Ok(BackgroundMap {
+        meta: BackgroundMeta {
+            model: format!("{:?} of {} maps", mode, maps.len()),
+            ..first.meta.clone()
+        },
+        depth_map,
+    })
+}
+
+// FNV-1a over the calibration values, stable across runs and toolchains.
+pub fn calibration_hash(values: &[f32]) -> String {
+    let hash = values
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+    format!("{hash:016x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_approx_eq::assert_approx_eq;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_depth_histogram() {
+        let mut histogram = DepthHistogram::new(2, 2, 100.0);
+        // A robot parked at 3 m for a while, the wall at 8 m and a far outlier.
+        for depth in [3010.0, 3020.0, 8020.0, 8050.0, 8070.0, 8040.0, 25000.0] {
+            histogram.add(1, 0, depth);
+        }
+        histogram.add(0, 1, 5000.0);
+
+        let depth_map = histogram.percentile_map(0.5, 3);
+        assert_approx_eq!(depth_map.get_pixel(1, 0).0[0], 8050.0);
+        assert_eq!(depth_map.get_pixel(0, 1).0[0], 0.0);
+        assert_eq!(depth_map.get_pixel(0, 0).0[0], 0.0);
+
+        let depth_map = histogram.percentile_map(0.1, 1);
+        assert_approx_eq!(depth_map.get_pixel(1, 0).0[0], 3050.0);
+        assert_approx_eq!(depth_map.get_pixel(0, 1).0[0], 5050.0);
+    }
+
+    #[test]
+    fn test_background_map_merge() -> Result<()> {
+        let map = |name: &str, hash: &str, depths: [f32; 3]| BackgroundMap {
+            meta: BackgroundMeta {
+                name: name.to_string(),
+                calibration_hash: hash.to_string(),
+                width: 3,
+                height: 1,
+                model: "Max".to_string(),
+            },
+            depth_map: ImageBuffer::from_vec(3, 1, depths.to_vec()).unwrap(),
+        };
+
+        let dir = tempdir()?;
+        let saved = map("Left", "0a", [1000.0, 0.0, 5000.0]);
+        saved.save(dir.path())?;
+        let loaded = BackgroundMap::load(dir.path().join("Left.npy"))?;
+        assert_eq!(loaded.meta, saved.meta);
+        assert_eq!(loaded.depth_map, saved.depth_map);
+        let (coverage, min, _, max) = loaded.coverage();
+        assert_approx_eq!(coverage, 2.0 / 3.0);
+        assert_approx_eq!(min, 1000.0);
+        assert_approx_eq!(max, 5000.0);
+
+        let maps = [
+            saved,
+            map("Left", "0a", [1200.0, 3000.0, 0.0]),
+            map("Left", "0a", [1100.0, 0.0, 4000.0]),
+        ];
+        let merged = merge_background_maps(&maps, MergeMode::Median)?;
+        assert_eq!(merged.depth_map.as_raw(), &vec![1100.0, 3000.0, 5000.0]);
+        let merged = merge_background_maps(&maps, MergeMode::Max)?;
+        assert_eq!(merged.depth_map.as_raw(), &vec![1200.0, 3000.0, 5000.0]);
+
+        assert!(merge_background_maps(
+            &[maps[0].clone(), map("Left", "0b", [0.0; 3])],
+            MergeMode::Max
+        )
+        .is_err());
+        assert_ne!(calibration_hash(&[1.0, 2.0]), calibration_hash(&[2.0, 1.0]));
+
+        Ok(())
+    }
+}
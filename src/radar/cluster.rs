@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use dbscan::Classification;
+use nalgebra::Point3;
+use tracing::{span, trace, Level};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClusterMethod {
+    // `dbscan::Model`, every range query scans all points.
+    BruteForce,
+    // Same clustering with points hashed into epsilon-sized voxels, range queries only scan
+    // the 27 voxels around a point.
+    #[default]
+    VoxelHash,
+}
+
+impl TryFrom<&str> for ClusterMethod {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> std::result::Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "brute_force" => Ok(ClusterMethod::BruteForce),
+            "voxel_hash" => Ok(ClusterMethod::VoxelHash),
+            _ => Err(anyhow!("Failed to convert {value} to cluster method")),
+        }
+    }
+}
+
+// DBSCAN with the semantics of `dbscan::Model::run`, neighbours are strictly closer than
+// `epsilon` and include the point itself.
+pub fn dbscan(
+    points: &[Point3<f32>],
+    epsilon: f32,
+    min_points: usize,
+    method: ClusterMethod,
+) -> Vec<Classification> {
+    let span = span!(Level::TRACE, "dbscan");
+    let _enter = span.enter();
+
+    match method {
+        ClusterMethod::BruteForce => {
+            let population: Vec<_> = points
+                .iter()
+                .map(|point| vec![point.x, point.y, point.z])
+                .collect();
+            dbscan::Model::new(epsilon as f64, min_points).run(&population)
+        }
+        // Voxel coordinates need a positive finite size, brute force takes any epsilon.
+        ClusterMethod::VoxelHash if !(epsilon.is_finite() && epsilon > 0.0) => {
+            dbscan(points, epsilon, min_points, ClusterMethod::BruteForce)
+        }
+        ClusterMethod::VoxelHash => {
+            let index = VoxelIndex::new(points, epsilon);
+            expand_clusters(points.len(), min_points, |idx| index.neighbors(idx))
+        }
+    }
+}
+
+struct VoxelIndex<'a> {
+    points: &'a [Point3<f32>],
+    epsilon: f32,
+    voxels: HashMap<(i64, i64, i64), Vec<usize>>,
+}
+
+impl<'a> VoxelIndex<'a> {
+    fn new(points: &'a [Point3<f32>], epsilon: f32) -> Self {
+        let mut index = Self {
+            points,
+            epsilon,
+            voxels: HashMap::new(),
+        };
+        for (idx, point) in points.iter().enumerate() {
+            let voxel = index.voxel(point);
+            index.voxels.entry(voxel).or_default().push(idx);
+        }
+        trace!(
+            "Indexed {} points in {} voxels.",
+            points.len(),
+            index.voxels.len()
+        );
+        index
+    }
+
+    #[inline]
+    fn voxel(&self, point: &Point3<f32>) -> (i64, i64, i64) {
+        (
+            (point.x / self.epsilon).floor() as i64,
+            (point.y / self.epsilon).floor() as i64,
+            (point.z / self.epsilon).floor() as i64,
+        )
+    }
+
+    // Ascending like the linear scan of `dbscan::Model`, the visiting order decides edge points.
+    fn neighbors(&self, idx: usize) -> Vec<usize> {
+        let point = &self.points[idx];
+        let (x, y, z) = self.voxel(point);
+        let mut neighbors = Vec::new();
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    let Some(voxel) = self.voxels.get(&(x + dx, y + dy, z + dz)) else {
+                        continue;
+                    };
+                    neighbors.extend(voxel.iter().copied().filter(|&other| {
+                        distance(point, &self.points[other]) < self.epsilon as f64
+                    }));
+                }
+            }
+        }
+        neighbors.sort_unstable();
+        neighbors
+    }
+}
+
+// Distance in f64 as `dbscan::euclidean_distance` computes it, so borderline pairs agree.
+#[inline]
+fn distance(a: &Point3<f32>, b: &Point3<f32>) -> f64 {
+    ((a.x as f64 - b.x as f64).powi(2)
+        + (a.y as f64 - b.y as f64).powi(2)
+        + (a.z as f64 - b.z as f64).powi(2))
+    .sqrt()
+}
+
+fn expand_clusters(
+    size: usize,
+    min_points: usize,
+    neighbors: impl Fn(usize) -> Vec<usize>,
+) -> Vec<Classification> {
+    let mut classifications = vec![Classification::Noise; size];
+    let mut visited = vec![false; size];
+    let mut queue = Vec::new();
+    let mut cluster = 0;
+
+    for idx in 0..size {
+        if visited[idx] {
+            continue;
+        }
+        visited[idx] = true;
+        queue.push(idx);
+
+        let mut new_cluster = false;
+        while let Some(idx) = queue.pop() {
+            let neighbors = neighbors(idx);
+            if neighbors.len() < min_points {
+                continue;
+            }
+            new_cluster = true;
+            classifications[idx] = Classification::Core(cluster);
+            for neighbor in neighbors {
+                if classifications[neighbor] == Classification::Noise {
+                    classifications[neighbor] = Classification::Edge(cluster);
+                }
+                if !visited[neighbor] {
+                    visited[neighbor] = true;
+                    queue.push(neighbor);
+                }
+            }
+        }
+        if new_cluster {
+            cluster += 1;
+        }
+    }
+
+    classifications
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_voxel_hash_matches_brute_force() {
+        // Blobs of pseudo-random points with noise around them, some near voxel borders.
+        let mut seed = 42u32;
+        let mut random = move || {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            (seed >> 8) as f32 / (1 << 24) as f32
+        };
+        let centers = [
+            (5000.0, 0.0, 0.0),
+            (5600.0, 300.0, 100.0),
+            (9000.0, -2000.0, 0.0),
+        ];
+        let points: Vec<_> = (0..600)
+            .map(|idx| {
+                let (x, y, z) = centers[idx % centers.len()];
+                let spread = if idx % 7 == 0 { 3000.0 } else { 500.0 };
+                Point3::new(
+                    x + (random() - 0.5) * spread,
+                    y + (random() - 0.5) * spread,
+                    z + (random() - 0.5) * spread,
+                )
+            })
+            .collect();
+
+        for (epsilon, min_points) in [(100.0, 4), (200.0, 10), (400.0, 4)] {
+            let expected = dbscan(&points, epsilon, min_points, ClusterMethod::BruteForce);
+            let classifications = dbscan(&points, epsilon, min_points, ClusterMethod::VoxelHash);
+            assert_eq!(classifications, expected);
+            assert!(expected
+                .iter()
+                .any(|classification| matches!(classification, Classification::Core(_))));
+        }
+
+        for epsilon in [0.0, -1.0, f32::NAN] {
+            let expected = dbscan(&points, epsilon, 4, ClusterMethod::BruteForce);
+            assert_eq!(
+                dbscan(&points, epsilon, 4, ClusterMethod::VoxelHash),
+                expected
+            );
+        }
+    }
+}
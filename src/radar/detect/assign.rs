@@ -0,0 +1,354 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use tracing::{debug, span, trace, Level};
+
+use super::{BBox, RobotDetection, RobotLabel};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LabelAssignment {
+    // Keeps the most confident car of each label and drops the others.
+    Greedy,
+    // Maximizes the total armor confidence sum with one label per car, a car only takes a
+    // label whose sum reaches `min_reassign_share` of its own top sum.
+    #[default]
+    Hungarian,
+}
+
+impl TryFrom<&str> for LabelAssignment {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> std::result::Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "greedy" => Ok(LabelAssignment::Greedy),
+            "hungarian" => Ok(LabelAssignment::Hungarian),
+            _ => Err(anyhow!("Failed to convert {value} to label assignment")),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Reassignment {
+    pub bbox: BBox,
+    pub from: RobotLabel,
+    pub to: RobotLabel,
+}
+
+#[derive(Debug, Default)]
+pub struct Assignment {
+    pub robots: Vec<RobotDetection>,
+    pub dropped: Vec<RobotDetection>,
+    pub reassigned: Vec<Reassignment>,
+}
+
+pub fn assign_labels(
+    detections: Vec<RobotDetection>,
+    method: LabelAssignment,
+    min_reassign_share: f32,
+) -> Assignment {
+    let span = span!(Level::TRACE, "assign_labels");
+    let _enter = span.enter();
+
+    let assignment = match method {
+        LabelAssignment::Greedy => assign_greedy(detections),
+        LabelAssignment::Hungarian => assign_hungarian(detections, min_reassign_share),
+    };
+
+    for robot in assignment.dropped.iter() {
+        debug!(
+            "Dropped car {:?} labelled {} with confidence {}.",
+            robot.bbox(),
+            robot.label,
+            robot.confidence
+        );
+    }
+    for reassignment in assignment.reassigned.iter() {
+        debug!(
+            "Reassigned car {:?} from {} to {}.",
+            reassignment.bbox, reassignment.from, reassignment.to
+        );
+    }
+
+    assignment
+}
+
+fn assign_greedy(detections: Vec<RobotDetection>) -> Assignment {
+    let mut robots_map: HashMap<RobotLabel, RobotDetection> =
+        HashMap::with_capacity(detections.len());
+    let mut dropped = Vec::new();
+
+    for robot_det in detections {
+        match robots_map.get(&robot_det.label) {
+            Some(robot_det_exist) if robot_det_exist.confidence >= robot_det.confidence => {
+                dropped.push(robot_det);
+            }
+            _ => {
+                trace!(
+                    "Keeping label {:?} with confidence {}.",
+                    robot_det.label,
+                    robot_det.confidence
+                );
+                if let Some(robot_det_exist) = robots_map.insert(robot_det.label.clone(), robot_det)
+                {
+                    dropped.push(robot_det_exist);
+                }
+            }
+        }
+    }
+
+    Assignment {
+        robots: robots_map.into_values().collect(),
+        dropped,
+        reassigned: Vec::new(),
+    }
+}
+
+fn assign_hungarian(detections: Vec<RobotDetection>, min_reassign_share: f32) -> Assignment {
+    let mut labels: Vec<RobotLabel> = Vec::new();
+    for robot in detections.iter() {
+        for score in robot.label_scores.iter() {
+            if !labels.contains(&score.label) {
+                labels.push(score.label.clone());
+            }
+        }
+    }
+
+    // Runner-up votes below the share of the top vote count as zero, a car losing its label
+    // to a weak vote is dropped instead.
+    let scores: Vec<Vec<f32>> = detections
+        .iter()
+        .map(|robot| {
+            let min_sum = robot
+                .label_scores
+                .iter()
+                .find(|score| score.label == robot.label)
+                .map_or(0.0, |score| score.sum)
+                * min_reassign_share;
+            labels
+                .iter()
+                .map(|label| {
+                    robot
+                        .label_scores
+                        .iter()
+                        .find(|score| score.label == *label)
+                        .filter(|score| score.label == robot.label || score.sum >= min_sum)
+                        .map_or(0.0, |score| score.sum)
+                })
+                .collect()
+        })
+        .collect();
+    let max_score = scores.iter().flatten().copied().fold(0.0f32, f32::max) as f64;
+
+    // Square cost matrix, padded rows and columns cost the same as a zero score.
+    let size = detections.len().max(labels.len());
+    let cost: Vec<Vec<f64>> = (0..size)
+        .map(|row| {
+            (0..size)
+                .map(|col| {
+                    let score = scores
+                        .get(row)
+                        .and_then(|row| row.get(col))
+                        .copied()
+                        .unwrap_or(0.0);
+                    max_score - score as f64
+                })
+                .collect()
+        })
+        .collect();
+    let columns = hungarian(&cost);
+
+    let mut assignment = Assignment::default();
+    for ((mut robot, col), robot_scores) in detections.into_iter().zip(columns).zip(scores) {
+        let score = robot_scores.get(col).copied().unwrap_or(0.0);
+        if score <= 0.0 {
+            assignment.dropped.push(robot);
+            continue;
+        }
+
+        let label = &labels[col];
+        if robot.label != *label {
+            assignment.reassigned.push(Reassignment {
+                bbox: robot.bbox(),
+                from: robot.label.clone(),
+                to: label.clone(),
+            });
+            if let Some(label_score) = robot
+                .label_scores
+                .iter()
+                .find(|label_score| label_score.label == *label)
+            {
+                robot.confidence = label_score.confidence();
+            }
+            robot.label = label.clone();
+        }
+        assignment.robots.push(robot);
+    }
+
+    assignment
+}
+
+// Minimum-cost assignment on a square cost matrix (Kuhn-Munkres with potentials, O(n^3)).
+// Returns the column assigned to each row. Costs must be finite, a NaN row never finds a
+// column and the search does not end.
+pub(super) fn hungarian(cost: &[Vec<f64>]) -> Vec<usize> {
+    debug_assert!(cost.iter().flatten().all(|value| value.is_finite()));
+    let n = cost.len();
+    let mut u = vec![0.0; n + 1];
+    let mut v = vec![0.0; n + 1];
+    // row_of[col] is the row matched to column col, 1-based with 0 as the virtual column.
+    let mut row_of = vec![0usize; n + 1];
+    let mut way = vec![0usize; n + 1];
+
+    for row in 1..=n {
+        row_of[0] = row;
+        let mut col0 = 0;
+        let mut min_value = vec![f64::INFINITY; n + 1];
+        let mut used = vec![false; n + 1];
+
+        loop {
+            used[col0] = true;
+            let row0 = row_of[col0];
+            let mut delta = f64::INFINITY;
+            let mut col1 = 0;
+            for col in 1..=n {
+                if used[col] {
+                    continue;
+                }
+                let current = cost[row0 - 1][col - 1] - u[row0] - v[col];
+                if current < min_value[col] {
+                    min_value[col] = current;
+                    way[col] = col0;
+                }
+                if min_value[col] < delta {
+                    delta = min_value[col];
+                    col1 = col;
+                }
+            }
+            for col in 0..=n {
+                if used[col] {
+                    u[row_of[col]] += delta;
+                    v[col] -= delta;
+                } else {
+                    min_value[col] -= delta;
+                }
+            }
+            col0 = col1;
+            if row_of[col0] == 0 {
+                break;
+            }
+        }
+
+        loop {
+            let col1 = way[col0];
+            row_of[col0] = row_of[col1];
+            col0 = col1;
+            if col0 == 0 {
+                break;
+            }
+        }
+    }
+
+    let mut columns = vec![0; n];
+    for (col, &row) in row_of.iter().enumerate().skip(1) {
+        if row != 0 {
+            columns[row - 1] = col - 1;
+        }
+    }
+    columns
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_approx_eq::assert_approx_eq;
+
+    use super::*;
+    use crate::radar::detect::{yolo::Detection, LabelScore, LabelTaxonomy};
+
+    fn robot(
+        x_center: f32,
+        scores: &[(&str, f32, f32)],
+        taxonomy: &LabelTaxonomy,
+    ) -> RobotDetection {
+        let label_scores: Vec<_> = scores
+            .iter()
+            .map(|&(name, sum, weight)| LabelScore {
+                label: taxonomy.find(name).unwrap(),
+                sum,
+                weight,
+            })
+            .collect();
+        RobotDetection {
+            car_detection: Detection {
+                bbox: BBox {
+                    x_center,
+                    y_center: 0.0,
+                    width: 10.0,
+                    height: 10.0,
+                },
+                confidence: 0.9,
+                class_id: 0,
+            },
+            armor_detections: Vec::new(),
+            label: label_scores[0].label.clone(),
+            confidence: label_scores[0].confidence(),
+            label_scores,
+        }
+    }
+
+    #[test]
+    fn test_hungarian() {
+        let cost = vec![
+            vec![4.0, 1.0, 3.0],
+            vec![2.0, 0.0, 5.0],
+            vec![3.0, 2.0, 2.0],
+        ];
+        assert_eq!(hungarian(&cost), vec![1, 0, 2]);
+        assert!(hungarian(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_assign_hungarian() {
+        let taxonomy = LabelTaxonomy::default();
+        let detections = vec![
+            robot(0.0, &[("B3", 1.8, 2.0), ("B4", 1.2, 2.0)], &taxonomy),
+            robot(100.0, &[("B3", 1.9, 2.0)], &taxonomy),
+            robot(200.0, &[("R1", 0.9, 1.0)], &taxonomy),
+            robot(300.0, &[("R1", 0.5, 1.0)], &taxonomy),
+        ];
+
+        let assignment = assign_labels(detections.clone(), LabelAssignment::Hungarian, 0.5);
+        assert_eq!(assignment.robots.len(), 3);
+        let label_of = |x_center: f32| {
+            assignment
+                .robots
+                .iter()
+                .find(|robot| robot.bbox().x_center == x_center)
+                .map(|robot| robot.label.name_abbr().to_string())
+        };
+        assert_eq!(label_of(0.0).as_deref(), Some("B4"));
+        assert_eq!(label_of(100.0).as_deref(), Some("B3"));
+        assert_eq!(label_of(200.0).as_deref(), Some("R1"));
+        assert_approx_eq!(assignment.robots[0].confidence, 0.6);
+
+        assert_eq!(assignment.reassigned.len(), 1);
+        assert_eq!(assignment.reassigned[0].from.name_abbr(), "B3");
+        assert_eq!(assignment.reassigned[0].to.name_abbr(), "B4");
+        assert_eq!(assignment.dropped.len(), 1);
+        assert_eq!(assignment.dropped[0].bbox().x_center, 300.0);
+
+        // B4 is below 0.7 of the top B3 vote, the car loses B3 and is dropped.
+        let assignment = assign_labels(detections.clone(), LabelAssignment::Hungarian, 0.7);
+        assert_eq!(assignment.robots.len(), 2);
+        assert_eq!(assignment.dropped.len(), 2);
+        assert!(assignment
+            .dropped
+            .iter()
+            .any(|robot| robot.bbox().x_center == 0.0));
+        assert!(assignment.reassigned.is_empty());
+
+        let assignment = assign_labels(detections, LabelAssignment::Greedy, 0.5);
+        assert_eq!(assignment.robots.len(), 2);
+        assert_eq!(assignment.dropped.len(), 2);
+        assert!(assignment.reassigned.is_empty());
+    }
+}
@@ -0,0 +1,39 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(super) struct CocoDataset {
+    pub images: Vec<CocoImage>,
+    pub annotations: Vec<CocoAnnotation>,
+    pub categories: Vec<CocoCategory>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct CocoImage {
+    pub id: u64,
+    pub file_name: String,
+    #[serde(default)]
+    pub width: u32,
+    #[serde(default)]
+    pub height: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct CocoAnnotation {
+    #[serde(default)]
+    pub id: u64,
+    pub image_id: u64,
+    pub category_id: u64,
+    pub bbox: [f32; 4],
+    #[serde(default)]
+    pub area: f32,
+    #[serde(default)]
+    pub iscrowd: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score: Option<f32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct CocoCategory {
+    pub id: u64,
+    pub name: String,
+}
@@ -0,0 +1,203 @@
+use anyhow::anyhow;
+use image::{imageops::FilterType, DynamicImage, GenericImageView};
+use tracing::{debug, span, trace, Level};
+
+use super::{yolo::Detection, BBox};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmallCrop {
+    Skip,
+    Upscale,
+}
+
+impl TryFrom<&str> for SmallCrop {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> std::result::Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "skip" => Ok(SmallCrop::Skip),
+            "upscale" => Ok(SmallCrop::Upscale),
+            _ => Err(anyhow!("Failed to convert {value} to small crop policy")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CropOptions {
+    pub margin_ratio: f32,
+    pub min_size: (u32, u32),
+    pub small_crop: SmallCrop,
+}
+
+impl Default for CropOptions {
+    fn default() -> Self {
+        Self {
+            margin_ratio: 0.1,
+            min_size: (16, 16),
+            small_crop: SmallCrop::Upscale,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CarCrop {
+    pub image: DynamicImage,
+    pub offset: (u32, u32),
+    pub scale: (f32, f32),
+}
+
+impl CarCrop {
+    // Crops the car bbox grown by `margin_ratio` of its size on every side, clamped to the image.
+    pub fn new(image: &DynamicImage, bbox: &BBox, options: &CropOptions) -> Option<Self> {
+        let span = span!(Level::TRACE, "CarCrop::new");
+        let _enter = span.enter();
+
+        let (image_width, image_height) = image.dimensions();
+        let margin_x = bbox.width * options.margin_ratio;
+        let margin_y = bbox.height * options.margin_ratio;
+
+        let x_min = (bbox.x_center - bbox.width / 2.0 - margin_x)
+            .floor()
+            .max(0.0);
+        let y_min = (bbox.y_center - bbox.height / 2.0 - margin_y)
+            .floor()
+            .max(0.0);
+        let x_max = (bbox.x_center + bbox.width / 2.0 + margin_x)
+            .ceil()
+            .min(image_width as f32);
+        let y_max = (bbox.y_center + bbox.height / 2.0 + margin_y)
+            .ceil()
+            .min(image_height as f32);
+
+        if !(x_max > x_min && y_max > y_min) {
+            debug!("Car bbox {:?} is outside of the image, skipped.", bbox);
+            return None;
+        }
+
+        let offset = (x_min as u32, y_min as u32);
+        let (width, height) = ((x_max - x_min) as u32, (y_max - y_min) as u32);
+        trace!(
+            "Cropping car image at {:?} with size {width}x{height}.",
+            offset
+        );
+        let image = image.crop_imm(offset.0, offset.1, width, height);
+
+        let (min_width, min_height) = options.min_size;
+        if width >= min_width && height >= min_height {
+            return Some(Self {
+                image,
+                offset,
+                scale: (1.0, 1.0),
+            });
+        }
+
+        match options.small_crop {
+            SmallCrop::Skip => {
+                debug!(
+                    "Car crop {width}x{height} is smaller than {min_width}x{min_height}, skipped."
+                );
+                None
+            }
+            SmallCrop::Upscale => {
+                let ratio =
+                    (min_width as f32 / width as f32).max(min_height as f32 / height as f32);
+                let (new_width, new_height) = (
+                    (width as f32 * ratio).ceil() as u32,
+                    (height as f32 * ratio).ceil() as u32,
+                );
+                debug!("Upscaling car crop {width}x{height} to {new_width}x{new_height}.");
+                Some(Self {
+                    image: image.resize_exact(new_width, new_height, FilterType::CatmullRom),
+                    offset,
+                    scale: (
+                        new_width as f32 / width as f32,
+                        new_height as f32 / height as f32,
+                    ),
+                })
+            }
+        }
+    }
+
+    pub fn restore_bbox(&self, bbox: &BBox) -> BBox {
+        BBox {
+            x_center: bbox.x_center / self.scale.0 + self.offset.0 as f32,
+            y_center: bbox.y_center / self.scale.1 + self.offset.1 as f32,
+            width: bbox.width / self.scale.0,
+            height: bbox.height / self.scale.1,
+        }
+    }
+
+    pub fn restore_detections(&self, detections: &[Detection]) -> Vec<Detection> {
+        detections
+            .iter()
+            .map(|detection| Detection {
+                bbox: self.restore_bbox(&detection.bbox),
+                ..detection.clone()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_approx_eq::assert_approx_eq;
+    use image::RgbImage;
+
+    use super::*;
+
+    fn bbox(x_center: f32, y_center: f32, width: f32, height: f32) -> BBox {
+        BBox {
+            x_center,
+            y_center,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn test_crop_clamped_to_image() {
+        let image = DynamicImage::ImageRgb8(RgbImage::new(200, 100));
+        let options = CropOptions {
+            margin_ratio: 0.1,
+            ..Default::default()
+        };
+
+        let crop = CarCrop::new(&image, &bbox(10.0, 50.0, 40.0, 20.0), &options).unwrap();
+        assert_eq!(crop.offset, (0, 38));
+        assert_eq!(crop.image.dimensions(), (34, 24));
+
+        let crop = CarCrop::new(&image, &bbox(190.0, 95.0, 40.0, 20.0), &options).unwrap();
+        assert_eq!(crop.offset, (166, 83));
+        assert_eq!(crop.image.dimensions(), (34, 17));
+
+        assert!(CarCrop::new(&image, &bbox(-50.0, 50.0, 40.0, 20.0), &options).is_none());
+    }
+
+    #[test]
+    fn test_small_crop() {
+        let image = DynamicImage::ImageRgb8(RgbImage::new(200, 100));
+        let small = bbox(100.0, 50.0, 8.0, 4.0);
+
+        let options = CropOptions {
+            margin_ratio: 0.0,
+            small_crop: SmallCrop::Skip,
+            ..Default::default()
+        };
+        assert!(CarCrop::new(&image, &small, &options).is_none());
+
+        let options = CropOptions {
+            margin_ratio: 0.0,
+            ..Default::default()
+        };
+        let crop = CarCrop::new(&image, &small, &options).unwrap();
+        assert_eq!(crop.offset, (96, 48));
+        assert_eq!(crop.image.dimensions(), (32, 16));
+        assert_eq!(crop.scale, (4.0, 4.0));
+
+        let restored = crop.restore_bbox(&bbox(16.0, 8.0, 16.0, 8.0));
+        assert_approx_eq!(restored.x_center, 100.0);
+        assert_approx_eq!(restored.y_center, 50.0);
+        assert_approx_eq!(restored.width, 4.0);
+        assert_approx_eq!(restored.height, 2.0);
+    }
+}
@@ -0,0 +1,559 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use image::{DynamicImage, GenericImageView};
+use tracing::{debug, error, span, trace, Level};
+
+use super::{
+    coco::{CocoAnnotation, CocoCategory, CocoDataset, CocoImage},
+    yolo::Detection,
+    Assignment, DetectionFormat, LabelScore, LabelTaxonomy, RobotDetection, RobotLabel,
+};
+
+// Writes 2D car detections under <root>/detections, referencing the images saved in
+// <root>/images/images_<camera>/<frame:06>.png:
+//   yolo/images_<camera>/<frame:06>.txt with "<class_id> <x_center> <y_center> <width> <height> <confidence>"
+//   yolo/classes.txt with one label per class id
+//   coco.json with scores in the annotations and category ids from 1
+// Both layouts can be read back by `PrecomputedDetector`.
+#[derive(Debug)]
+pub struct DetectionExporter {
+    root_dir: PathBuf,
+    formats: Vec<DetectionFormat>,
+    coco: CocoDataset,
+}
+
+impl DetectionExporter {
+    pub fn new<P: AsRef<Path>>(
+        root_dir: P,
+        camera_num: usize,
+        formats: Vec<DetectionFormat>,
+        taxonomy: &LabelTaxonomy,
+    ) -> Result<Self> {
+        let span = span!(Level::TRACE, "DetectionExporter::new");
+        let _enter = span.enter();
+
+        let root_dir = root_dir.as_ref().to_path_buf();
+        let export_dir = root_dir.join("detections");
+        let labels = taxonomy.labels();
+
+        if formats.contains(&DetectionFormat::Yolo) {
+            for camera_idx in 0..camera_num {
+                let dir = export_dir.join(format!("yolo/images_{camera_idx}"));
+                fs::create_dir_all(&dir).map_err(|e| {
+                    error!("Failed to create directory {:?}: {e}", dir);
+                    anyhow!("Failed to create directory {:?}: {e}", dir)
+                })?;
+            }
+
+            let classes = labels
+                .iter()
+                .map(|label| format!("{}\n", label.name_abbr()))
+                .collect::<String>();
+            fs::write(export_dir.join("yolo/classes.txt"), classes)?;
+        } else {
+            fs::create_dir_all(&export_dir)?;
+        }
+
+        let coco = CocoDataset {
+            categories: labels
+                .iter()
+                .map(|label| CocoCategory {
+                    id: coco_category_id(label),
+                    name: label.name_abbr().to_string(),
+                })
+                .collect(),
+            ..Default::default()
+        };
+
+        debug!("Exporting detections to {:?} in {:?}", export_dir, formats);
+        Ok(Self {
+            root_dir,
+            formats,
+            coco,
+        })
+    }
+
+    pub fn add(
+        &mut self,
+        camera_idx: usize,
+        frame_idx: usize,
+        image_size: (u32, u32),
+        detections: &[RobotDetection],
+    ) -> Result<()> {
+        let span = span!(Level::TRACE, "DetectionExporter::add");
+        let _enter = span.enter();
+
+        trace!(
+            "Exporting {} detections of camera {camera_idx}, frame {frame_idx}",
+            detections.len()
+        );
+
+        if self.formats.contains(&DetectionFormat::Yolo) {
+            self.write_yolo(camera_idx, frame_idx, image_size, detections)?;
+        }
+
+        if self.formats.contains(&DetectionFormat::Coco) {
+            let image_id = self.coco.images.len() as u64 + 1;
+            self.coco.images.push(CocoImage {
+                id: image_id,
+                file_name: format!("images/images_{camera_idx}/{:06}.png", frame_idx),
+                width: image_size.0,
+                height: image_size.1,
+            });
+
+            for detection in detections {
+                let bbox = detection.bbox();
+                self.coco.annotations.push(CocoAnnotation {
+                    id: self.coco.annotations.len() as u64 + 1,
+                    image_id,
+                    category_id: coco_category_id(&detection.label),
+                    bbox: [
+                        bbox.x_center - bbox.width / 2.0,
+                        bbox.y_center - bbox.height / 2.0,
+                        bbox.width,
+                        bbox.height,
+                    ],
+                    area: bbox.width * bbox.height,
+                    iscrowd: 0,
+                    score: Some(detection.confidence),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(&self) -> Result<()> {
+        let span = span!(Level::TRACE, "DetectionExporter::finish");
+        let _enter = span.enter();
+
+        if self.formats.contains(&DetectionFormat::Coco) {
+            let file_path = self.root_dir.join("detections/coco.json");
+            let file = File::create(&file_path).map_err(|e| {
+                error!("Failed to create {:?}: {e}", file_path);
+                anyhow!("Failed to create {:?}: {e}", file_path)
+            })?;
+            let mut writer = BufWriter::new(file);
+            serde_json::to_writer(&mut writer, &self.coco)?;
+            writer.flush()?;
+
+            debug!(
+                "Exported {} images and {} annotations to {:?}",
+                self.coco.images.len(),
+                self.coco.annotations.len(),
+                file_path
+            );
+        }
+
+        Ok(())
+    }
+
+    fn write_yolo(
+        &self,
+        camera_idx: usize,
+        frame_idx: usize,
+        image_size: (u32, u32),
+        detections: &[RobotDetection],
+    ) -> Result<()> {
+        let (image_width, image_height) = (image_size.0 as f32, image_size.1 as f32);
+        let file_path = self.root_dir.join(format!(
+            "detections/yolo/images_{camera_idx}/{:06}.txt",
+            frame_idx
+        ));
+        let file = File::create(&file_path).map_err(|e| {
+            error!("Failed to create {:?}: {e}", file_path);
+            anyhow!("Failed to create {:?}: {e}", file_path)
+        })?;
+
+        let mut writer = BufWriter::new(file);
+        for detection in detections {
+            let bbox = detection.bbox();
+            writeln!(
+                writer,
+                "{} {:.6} {:.6} {:.6} {:.6} {:.4}",
+                detection.label.id(),
+                bbox.x_center / image_width,
+                bbox.y_center / image_height,
+                bbox.width / image_width,
+                bbox.height / image_height,
+                detection.confidence
+            )?;
+        }
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+// COCO category ids start at 1, tools commonly take 0 as the background.
+#[inline]
+fn coco_category_id(label: &RobotLabel) -> u64 {
+    label.id() as u64 + 1
+}
+
+// Writes every car crop seen by `RobotDetector` with its armor detections, grouped by the
+// final label of the car after label assignment:
+//   <label>/images/<camera>_<frame:06>_<car:02>.png
+//   <label>/labels/<camera>_<frame:06>_<car:02>.txt with "<class_id> <x_center> <y_center> <width> <height>"
+// Crops without a valid vote go to "unlabeled", cars dropped by the assignment are skipped.
+// A crop is listed in ambiguous.txt when the runner-up label confidence sum reaches
+// `ambiguity_ratio` of the winning one, armor classes are mapped to labels with the taxonomy.
+#[derive(Debug)]
+pub struct ArmorCropExporter {
+    export_dir: PathBuf,
+    ambiguity_ratio: f32,
+    taxonomy: LabelTaxonomy,
+}
+
+impl ArmorCropExporter {
+    pub fn new<P: AsRef<Path>>(root_dir: P, ambiguity_ratio: f32) -> Result<Self> {
+        let span = span!(Level::TRACE, "ArmorCropExporter::new");
+        let _enter = span.enter();
+
+        let export_dir = root_dir.as_ref().join("armor_crops");
+        fs::create_dir_all(&export_dir).map_err(|e| {
+            error!("Failed to create directory {:?}: {e}", export_dir);
+            anyhow!("Failed to create directory {:?}: {e}", export_dir)
+        })?;
+
+        debug!(
+            "Exporting armor crops to {:?} with ambiguity ratio {ambiguity_ratio}",
+            export_dir
+        );
+        Ok(Self {
+            export_dir,
+            ambiguity_ratio,
+            taxonomy: LabelTaxonomy::default(),
+        })
+    }
+
+    pub fn set_taxonomy(&mut self, taxonomy: LabelTaxonomy) {
+        self.taxonomy = taxonomy;
+    }
+
+    pub fn export(
+        &self,
+        camera_idx: usize,
+        frame_idx: usize,
+        car_idx: usize,
+        car_image: &DynamicImage,
+        armor_detections: &[Detection],
+        label: Option<RobotLabel>,
+    ) -> Result<()> {
+        let span = span!(Level::TRACE, "ArmorCropExporter::export");
+        let _enter = span.enter();
+
+        let group = label
+            .as_ref()
+            .map_or("unlabeled", |label| label.name_abbr());
+        let name = format!("{camera_idx}_{:06}_{:02}", frame_idx, car_idx);
+        let image_dir = self.export_dir.join(format!("{group}/images"));
+        let label_dir = self.export_dir.join(format!("{group}/labels"));
+        fs::create_dir_all(&image_dir)?;
+        fs::create_dir_all(&label_dir)?;
+
+        trace!("Exporting armor crop {group}/{name}");
+        car_image.save(image_dir.join(format!("{name}.png")))?;
+
+        let (crop_width, crop_height) = car_image.dimensions();
+        let (crop_width, crop_height) = (crop_width as f32, crop_height as f32);
+        let mut writer = BufWriter::new(File::create(label_dir.join(format!("{name}.txt")))?);
+        for detection in armor_detections {
+            let bbox = &detection.bbox;
+            writeln!(
+                writer,
+                "{} {:.6} {:.6} {:.6} {:.6}",
+                detection.class_id,
+                bbox.x_center / crop_width,
+                bbox.y_center / crop_height,
+                bbox.width / crop_width,
+                bbox.height / crop_height
+            )?;
+        }
+        writer.flush()?;
+
+        let scores = LabelScore::from_armor_detections(armor_detections, &self.taxonomy);
+        if let [first, second, ..] = &scores[..] {
+            if second.sum >= first.sum * self.ambiguity_ratio {
+                debug!(
+                    "Armor vote of {group}/{name} is ambiguous: {} {}, {} {}",
+                    first.label, first.sum, second.label, second.sum
+                );
+                let mut file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(self.export_dir.join("ambiguous.txt"))?;
+                writeln!(
+                    file,
+                    "{group}/{name} {} {:.4} {} {:.4}",
+                    first.label.name_abbr(),
+                    first.sum,
+                    second.label.name_abbr(),
+                    second.sum
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Records what the label assignment changed under <root>/assignments/<camera>_<frame:06>.txt,
+// one line per car, frames where it kept every label are skipped:
+//   dropped <label> <confidence> <x_center> <y_center> <width> <height>
+//   reassigned <from> <to> <x_center> <y_center> <width> <height>
+#[derive(Debug)]
+pub struct AssignmentExporter {
+    export_dir: PathBuf,
+}
+
+impl AssignmentExporter {
+    pub fn new<P: AsRef<Path>>(root_dir: P) -> Result<Self> {
+        let span = span!(Level::TRACE, "AssignmentExporter::new");
+        let _enter = span.enter();
+
+        let export_dir = root_dir.as_ref().join("assignments");
+        fs::create_dir_all(&export_dir).map_err(|e| {
+            error!("Failed to create directory {:?}: {e}", export_dir);
+            anyhow!("Failed to create directory {:?}: {e}", export_dir)
+        })?;
+
+        debug!("Exporting label assignment records to {:?}", export_dir);
+        Ok(Self { export_dir })
+    }
+
+    pub fn export(
+        &self,
+        camera_idx: usize,
+        frame_idx: usize,
+        assignment: &Assignment,
+    ) -> Result<()> {
+        let span = span!(Level::TRACE, "AssignmentExporter::export");
+        let _enter = span.enter();
+
+        if assignment.dropped.is_empty() && assignment.reassigned.is_empty() {
+            return Ok(());
+        }
+
+        let file_path = self
+            .export_dir
+            .join(format!("{camera_idx}_{:06}.txt", frame_idx));
+        trace!("Exporting label assignment record {:?}", file_path);
+        let mut writer = BufWriter::new(File::create(&file_path)?);
+        for robot in assignment.dropped.iter() {
+            let bbox = robot.bbox();
+            writeln!(
+                writer,
+                "dropped {} {:.4} {:.2} {:.2} {:.2} {:.2}",
+                robot.label.name_abbr(),
+                robot.confidence,
+                bbox.x_center,
+                bbox.y_center,
+                bbox.width,
+                bbox.height
+            )?;
+        }
+        for reassignment in assignment.reassigned.iter() {
+            let bbox = &reassignment.bbox;
+            writeln!(
+                writer,
+                "reassigned {} {} {:.2} {:.2} {:.2} {:.2}",
+                reassignment.from.name_abbr(),
+                reassignment.to.name_abbr(),
+                bbox.x_center,
+                bbox.y_center,
+                bbox.width,
+                bbox.height
+            )?;
+        }
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_approx_eq::assert_approx_eq;
+    use image::{DynamicImage, RgbImage};
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::radar::detect::{
+        yolo::Detection, BBox, Detector, PrecomputedDetector, Reassignment,
+    };
+
+    #[test]
+    fn test_export_round_trip() -> Result<()> {
+        let dir = tempdir()?;
+        let image = DynamicImage::ImageRgb8(RgbImage::new(200, 100));
+        let taxonomy = LabelTaxonomy::default();
+        let detections = vec![
+            RobotDetection::labelled(
+                BBox {
+                    x_center: 100.0,
+                    y_center: 25.0,
+                    width: 20.0,
+                    height: 20.0,
+                },
+                taxonomy.find("R7").unwrap(),
+                0.8,
+            ),
+            RobotDetection::labelled(
+                BBox {
+                    x_center: 40.0,
+                    y_center: 60.0,
+                    width: 20.0,
+                    height: 10.0,
+                },
+                taxonomy.find("B4").unwrap(),
+                0.5,
+            ),
+        ];
+
+        let mut exporter = DetectionExporter::new(
+            dir.path(),
+            2,
+            vec![DetectionFormat::Yolo, DetectionFormat::Coco],
+            &taxonomy,
+        )?;
+        exporter.add(1, 5, (200, 100), &detections)?;
+        exporter.add(0, 5, (200, 100), &[])?;
+        exporter.finish()?;
+
+        let coco: CocoDataset = serde_json::from_str(&fs::read_to_string(
+            dir.path().join("detections/coco.json"),
+        )?)?;
+        assert_eq!(coco.categories[0].id, 1);
+        assert!(coco
+            .annotations
+            .iter()
+            .all(|annotation| annotation.category_id >= 1));
+
+        let classes = fs::read_to_string(dir.path().join("detections/yolo/classes.txt"))?;
+        assert_eq!(classes.lines().count(), 12);
+        assert_eq!(classes.lines().nth(11), Some("R7"));
+        assert!(dir
+            .path()
+            .join("detections/yolo/images_0/000005.txt")
+            .exists());
+
+        for detector in [
+            PrecomputedDetector::new(
+                dir.path().join("detections/yolo"),
+                DetectionFormat::Yolo,
+                taxonomy.clone(),
+            )?,
+            PrecomputedDetector::new(
+                dir.path().join("detections/coco.json"),
+                DetectionFormat::Coco,
+                taxonomy.clone(),
+            )?,
+        ] {
+            let exported = detector.detect(&image, 1, 5)?;
+            assert_eq!(exported.len(), detections.len());
+            for (exported, detection) in exported.iter().zip(detections.iter()) {
+                assert_eq!(exported.label, detection.label);
+                assert_approx_eq!(exported.confidence, detection.confidence, 1e-4);
+                assert_approx_eq!(exported.bbox().x_center, detection.bbox().x_center, 1e-3);
+                assert_approx_eq!(exported.bbox().y_center, detection.bbox().y_center, 1e-3);
+                assert_approx_eq!(exported.bbox().width, detection.bbox().width, 1e-3);
+                assert_approx_eq!(exported.bbox().height, detection.bbox().height, 1e-3);
+            }
+            assert!(detector.detect(&image, 0, 5)?.is_empty());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_armor_crops() -> Result<()> {
+        let dir = tempdir()?;
+        let exporter = ArmorCropExporter::new(dir.path(), 0.8)?;
+        let taxonomy = LabelTaxonomy::default();
+        let car_image = DynamicImage::ImageRgb8(RgbImage::new(100, 50));
+        let armor = |class_id, confidence| Detection {
+            bbox: BBox {
+                x_center: 50.0,
+                y_center: 25.0,
+                width: 10.0,
+                height: 5.0,
+            },
+            confidence,
+            class_id,
+        };
+
+        exporter.export(0, 1, 0, &car_image, &[armor(3, 0.9)], taxonomy.find("B4"))?;
+        exporter.export(
+            0,
+            1,
+            1,
+            &car_image,
+            &[armor(5, 0.6), armor(6, 0.55)],
+            taxonomy.find("R1"),
+        )?;
+        exporter.export(2, 1, 0, &car_image, &[], None)?;
+
+        let crop_dir = dir.path().join("armor_crops");
+        assert!(crop_dir.join("B4/images/0_000001_00.png").exists());
+        assert_eq!(
+            fs::read_to_string(crop_dir.join("B4/labels/0_000001_00.txt"))?,
+            "3 0.500000 0.500000 0.100000 0.100000\n"
+        );
+        assert!(crop_dir.join("R1/images/0_000001_01.png").exists());
+        assert!(crop_dir.join("unlabeled/labels/2_000001_00.txt").exists());
+
+        let ambiguous = fs::read_to_string(crop_dir.join("ambiguous.txt"))?;
+        assert_eq!(ambiguous.lines().count(), 1);
+        assert!(ambiguous.starts_with("R1/0_000001_01 R1 0.6000 R2 0.5500"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_assignments() -> Result<()> {
+        let dir = tempdir()?;
+        let exporter = AssignmentExporter::new(dir.path())?;
+        let taxonomy = LabelTaxonomy::default();
+        let bbox = BBox {
+            x_center: 100.0,
+            y_center: 25.0,
+            width: 20.0,
+            height: 10.0,
+        };
+
+        exporter.export(0, 3, &Assignment::default())?;
+        exporter.export(
+            1,
+            3,
+            &Assignment {
+                robots: Vec::new(),
+                dropped: vec![RobotDetection::labelled(
+                    bbox,
+                    taxonomy.find("R1").unwrap(),
+                    0.5,
+                )],
+                reassigned: vec![Reassignment {
+                    bbox,
+                    from: taxonomy.find("B3").unwrap(),
+                    to: taxonomy.find("B4").unwrap(),
+                }],
+            },
+        )?;
+
+        let assignment_dir = dir.path().join("assignments");
+        assert!(!assignment_dir.join("0_000003.txt").exists());
+        assert_eq!(
+            fs::read_to_string(assignment_dir.join("1_000003.txt"))?,
+            "dropped R1 0.5000 100.00 25.00 20.00 10.00\n\
+            reassigned B3 B4 100.00 25.00 20.00 10.00\n"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,398 @@
+use std::{
+    collections::HashSet,
+    fmt::{self, Display},
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, Result};
+use tracing::{debug, span, Level};
+
+use crate::config::LabelConfig;
+
+pub const UNKNOWN_KIND: &str = "unknown";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Team {
+    Blue,
+    Red,
+    None,
+}
+
+impl TryFrom<&str> for Team {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> std::result::Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "blue" => Ok(Team::Blue),
+            "red" => Ok(Team::Red),
+            "none" | "" => Ok(Team::None),
+            _ => Err(anyhow!("Failed to convert {value} to team")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabelInfo {
+    pub name: String,
+    pub output_name: String,
+    pub team: Team,
+    pub kind: String,
+}
+
+impl LabelInfo {
+    pub fn new(name: &str, output_name: &str, team: Team, kind: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            output_name: output_name.to_string(),
+            team,
+            kind: kind.to_string(),
+        }
+    }
+}
+
+// A class of the label taxonomy, identified by its index in the class list.
+#[derive(Debug, Clone)]
+pub struct RobotLabel {
+    id: u32,
+    info: Arc<LabelInfo>,
+}
+
+impl RobotLabel {
+    #[inline]
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.info.name
+    }
+
+    #[inline]
+    pub fn name_abbr(&self) -> &str {
+        &self.info.output_name
+    }
+
+    #[inline]
+    pub fn team(&self) -> Team {
+        self.info.team
+    }
+
+    #[inline]
+    pub fn kind(&self) -> &str {
+        &self.info.kind
+    }
+
+    #[inline]
+    pub fn is_unknown(&self) -> bool {
+        self.kind() == UNKNOWN_KIND
+    }
+}
+
+impl PartialEq for RobotLabel {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for RobotLabel {}
+
+impl Hash for RobotLabel {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl Display for RobotLabel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LabelTaxonomy {
+    labels: Vec<RobotLabel>,
+    armor_labels: Vec<Option<RobotLabel>>,
+}
+
+impl LabelTaxonomy {
+    // `armor_classes[class_id]` is the name of the label an armor class votes for, an empty
+    // name ignores that armor class. Without `armor_classes`, armor class ids follow the class list.
+    pub fn new(classes: Vec<LabelInfo>, armor_classes: &[String]) -> Result<Self> {
+        let span = span!(Level::TRACE, "LabelTaxonomy::new");
+        let _enter = span.enter();
+
+        if classes.is_empty() {
+            return Err(anyhow!("Label class list is empty"));
+        }
+
+        let mut names = HashSet::with_capacity(classes.len() * 2);
+        for info in classes.iter() {
+            if !names.insert(info.name.to_lowercase()) {
+                return Err(anyhow!("Duplicated label name {}", info.name));
+            }
+            if info.output_name != info.name && !names.insert(info.output_name.to_lowercase()) {
+                return Err(anyhow!("Duplicated label name {}", info.output_name));
+            }
+        }
+
+        let labels: Vec<_> = classes
+            .into_iter()
+            .enumerate()
+            .map(|(id, info)| RobotLabel {
+                id: id as u32,
+                info: Arc::new(info),
+            })
+            .collect();
+
+        let armor_labels = if armor_classes.is_empty() {
+            labels.iter().cloned().map(Some).collect()
+        } else {
+            armor_classes
+                .iter()
+                .map(|name| {
+                    if name.is_empty() {
+                        return Ok(None);
+                    }
+                    Self::find_in(&labels, name)
+                        .map(Some)
+                        .ok_or_else(|| anyhow!("Armor class {name} is not in the label classes"))
+                })
+                .collect::<Result<Vec<_>>>()?
+        };
+
+        debug!(
+            "Label taxonomy: {:?}, armor classes: {:?}",
+            labels
+                .iter()
+                .map(|label| label.name_abbr())
+                .collect::<Vec<_>>(),
+            armor_labels
+                .iter()
+                .map(|label| label.as_ref().map(|label| label.name_abbr()))
+                .collect::<Vec<_>>()
+        );
+
+        Ok(Self {
+            labels,
+            armor_labels,
+        })
+    }
+
+    pub fn from_config(config: &LabelConfig) -> Result<Self> {
+        let classes = if config.classes.is_empty() {
+            Self::default_classes()
+        } else {
+            config
+                .classes
+                .iter()
+                .map(|class| {
+                    Ok(LabelInfo {
+                        name: class.name.clone(),
+                        output_name: class.output.clone().unwrap_or_else(|| class.name.clone()),
+                        team: Team::try_from(class.team.as_str())?,
+                        kind: class.kind.clone(),
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?
+        };
+
+        Self::new(classes, &config.armor_classes)
+    }
+
+    fn default_classes() -> Vec<LabelInfo> {
+        vec![
+            LabelInfo::new("Blue Hero", "B1", Team::Blue, "hero"),
+            LabelInfo::new("Blue Engineer", "B2", Team::Blue, "engineer"),
+            LabelInfo::new("Blue Infantry Three", "B3", Team::Blue, "infantry"),
+            LabelInfo::new("Blue Infantry Four", "B4", Team::Blue, "infantry"),
+            LabelInfo::new("Blue Infantry Five", "B5", Team::Blue, "infantry"),
+            LabelInfo::new("Red Hero", "R1", Team::Red, "hero"),
+            LabelInfo::new("Red Engineer", "R2", Team::Red, "engineer"),
+            LabelInfo::new("Red Infantry Three", "R3", Team::Red, "infantry"),
+            LabelInfo::new("Red Infantry Four", "R4", Team::Red, "infantry"),
+            LabelInfo::new("Red Infantry Five", "R5", Team::Red, "infantry"),
+            LabelInfo::new("Blue Sentry", "B7", Team::Blue, "sentry"),
+            LabelInfo::new("Red Sentry", "R7", Team::Red, "sentry"),
+        ]
+    }
+
+    #[inline]
+    pub fn labels(&self) -> &[RobotLabel] {
+        &self.labels
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    pub fn label(&self, id: u32) -> Option<RobotLabel> {
+        self.labels.get(id as usize).cloned()
+    }
+
+    pub fn armor_label(&self, class_id: u32) -> Option<RobotLabel> {
+        self.armor_labels.get(class_id as usize).cloned().flatten()
+    }
+
+    // Labels of kind "unknown" stand for robots whose armor could not be read, one per team.
+    pub fn unknown_label(&self, team: Team) -> Option<RobotLabel> {
+        self.labels
+            .iter()
+            .find(|label| label.is_unknown() && label.team() == team)
+            .cloned()
+    }
+
+    // Appends the unknown labels missing from the class list.
+    pub fn with_unknown_labels(self) -> Result<Self> {
+        let missing: Vec<_> = [
+            LabelInfo::new("Blue Unknown", "BU", Team::Blue, UNKNOWN_KIND),
+            LabelInfo::new("Red Unknown", "RU", Team::Red, UNKNOWN_KIND),
+            LabelInfo::new("Unknown", "U", Team::None, UNKNOWN_KIND),
+        ]
+        .into_iter()
+        .filter(|info| self.unknown_label(info.team).is_none())
+        .collect();
+        if missing.is_empty() {
+            return Ok(self);
+        }
+
+        let armor_classes: Vec<_> = self
+            .armor_labels
+            .iter()
+            .map(|label| {
+                label
+                    .as_ref()
+                    .map_or_else(String::new, |label| label.name().to_string())
+            })
+            .collect();
+        let classes = self
+            .labels
+            .iter()
+            .map(|label| label.info.as_ref().clone())
+            .chain(missing)
+            .collect();
+
+        Self::new(classes, &armor_classes)
+    }
+
+    // Looks a label up by its output name or display name, ignoring case.
+    pub fn find(&self, name: &str) -> Option<RobotLabel> {
+        Self::find_in(&self.labels, name)
+    }
+
+    fn find_in(labels: &[RobotLabel], name: &str) -> Option<RobotLabel> {
+        labels
+            .iter()
+            .find(|label| {
+                label.name_abbr().eq_ignore_ascii_case(name)
+                    || label.name().eq_ignore_ascii_case(name)
+            })
+            .cloned()
+    }
+}
+
+impl Default for LabelTaxonomy {
+    fn default() -> Self {
+        Self::new(Self::default_classes(), &[]).expect("Default label classes are valid")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LabelClassConfig;
+
+    #[test]
+    fn test_default_taxonomy() {
+        let taxonomy = LabelTaxonomy::default();
+        assert_eq!(taxonomy.len(), 12);
+
+        let label = taxonomy.armor_label(11).unwrap();
+        assert_eq!(label.id(), 11);
+        assert_eq!(label.name(), "Red Sentry");
+        assert_eq!(label.name_abbr(), "R7");
+        assert_eq!(label.team(), Team::Red);
+        assert_eq!(label.kind(), "sentry");
+        assert!(taxonomy.armor_label(12).is_none());
+
+        assert_eq!(taxonomy.find("b3"), taxonomy.label(2));
+        assert_eq!(taxonomy.find("Blue Infantry Three"), taxonomy.label(2));
+        assert!(taxonomy.find("B6").is_none());
+    }
+
+    #[test]
+    fn test_taxonomy_from_config() -> Result<()> {
+        let class = |name: &str, output: Option<&str>, team: &str| LabelClassConfig {
+            name: name.to_string(),
+            output: output.map(str::to_string),
+            team: team.to_string(),
+            kind: String::new(),
+        };
+        let config = LabelConfig {
+            classes: vec![
+                class("Blue Hero", Some("B1"), "blue"),
+                class("Red Hero", Some("R1"), "red"),
+                class("Outpost", None, "none"),
+            ],
+            armor_classes: vec![
+                "R1".to_string(),
+                String::new(),
+                "Blue Hero".to_string(),
+                "outpost".to_string(),
+            ],
+        };
+
+        let taxonomy = LabelTaxonomy::from_config(&config)?;
+        assert_eq!(taxonomy.len(), 3);
+        assert_eq!(taxonomy.armor_label(0).unwrap().name_abbr(), "R1");
+        assert!(taxonomy.armor_label(1).is_none());
+        assert_eq!(taxonomy.armor_label(2).unwrap().id(), 0);
+        assert_eq!(taxonomy.armor_label(3).unwrap().name_abbr(), "Outpost");
+        assert_eq!(taxonomy.armor_label(3).unwrap().team(), Team::None);
+
+        let config = LabelConfig {
+            armor_classes: vec!["B6".to_string()],
+            ..config
+        };
+        assert!(LabelTaxonomy::from_config(&config).is_err());
+
+        let config = LabelConfig {
+            classes: vec![
+                class("Blue Hero", Some("B1"), "blue"),
+                class("B1", None, "blue"),
+            ],
+            armor_classes: Vec::new(),
+        };
+        assert!(LabelTaxonomy::from_config(&config).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_labels() -> Result<()> {
+        let taxonomy = LabelTaxonomy::default();
+        assert!(taxonomy.unknown_label(Team::Blue).is_none());
+
+        let taxonomy = taxonomy.with_unknown_labels()?;
+        assert_eq!(taxonomy.len(), 15);
+        assert_eq!(
+            taxonomy.unknown_label(Team::Blue).unwrap().name_abbr(),
+            "BU"
+        );
+        assert_eq!(taxonomy.unknown_label(Team::Red).unwrap().id(), 13);
+        assert_eq!(taxonomy.unknown_label(Team::None).unwrap().name_abbr(), "U");
+        assert_eq!(taxonomy.armor_label(11).unwrap().name_abbr(), "R7");
+        assert!(taxonomy.armor_label(12).is_none());
+
+        assert_eq!(taxonomy.clone().with_unknown_labels()?.len(), 15);
+
+        Ok(())
+    }
+}
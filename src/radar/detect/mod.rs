@@ -1,169 +1,266 @@
+mod assign;
+mod coco;
+mod crop;
+mod export;
+mod label;
+mod precomputed;
+mod team;
+mod track;
 mod yolo;
 
-use std::{
-    cmp::Ordering,
-    collections::HashMap,
-    fmt::{self, Display},
-};
+use std::{cmp::Ordering, collections::HashMap, sync::Mutex};
 
 use anyhow::{anyhow, Result};
 use image::DynamicImage;
 use tracing::{debug, error, span, trace, Level};
 
-pub use yolo::{BBox, Execution};
-use yolo::{Detection, Yolo};
-
-use crate::config::DetectorConfig;
-
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
-pub enum RobotLabel {
-    BlueHero,
-    BlueEngineer,
-    BlueInfantryThree,
-    BlueInfantryFour,
-    BlueInfantryFive,
-    RedHero,
-    RedEngineer,
-    RedInfantryThree,
-    RedInfantryFour,
-    RedInfantryFive,
-    BlueSentry,
-    RedSentry,
-}
+pub use assign::{assign_labels, Assignment, LabelAssignment, Reassignment};
+pub use crop::{CarCrop, CropOptions, SmallCrop};
+pub use export::{ArmorCropExporter, AssignmentExporter, DetectionExporter};
+pub use label::{LabelInfo, LabelTaxonomy, RobotLabel, Team};
+pub use precomputed::{DetectionFormat, PrecomputedDetector};
+pub use team::{estimate_team, UnknownOptions};
+pub use track::{Tracker, TrackerOptions};
+use yolo::Yolo;
+pub use yolo::{
+    BBox, Detection, Execution, ExecutionProvider, ModelSpec, NmsMethod, OptimizationLevel,
+    OutputFormat, Preprocess, ProviderOptions, ResizeMode, SoftNmsDecay,
+};
 
-impl RobotLabel {
-    pub fn name(&self) -> &str {
-        match self {
-            RobotLabel::BlueHero => "Blue Hero",
-            RobotLabel::BlueEngineer => "Blue Engineer",
-            RobotLabel::BlueInfantryThree => "Blue Infantry Three",
-            RobotLabel::BlueInfantryFour => "Blue Infantry Four",
-            RobotLabel::BlueInfantryFive => "Blue Infantry Five",
-            RobotLabel::RedHero => "Red Hero",
-            RobotLabel::RedEngineer => "Red Engineer",
-            RobotLabel::RedInfantryThree => "Red Infantry Three",
-            RobotLabel::RedInfantryFour => "Red Infantry Four",
-            RobotLabel::RedInfantryFive => "Red Infantry Five",
-            RobotLabel::BlueSentry => "Blue Sentry",
-            RobotLabel::RedSentry => "Red Sentry",
-        }
-    }
+use crate::config::{DetectorConfig, ExecutionConfig, ModelConfig};
 
-    pub fn name_abbr(&self) -> &str {
-        match self {
-            RobotLabel::BlueHero => "B1",
-            RobotLabel::BlueEngineer => "B2",
-            RobotLabel::BlueInfantryThree => "B3",
-            RobotLabel::BlueInfantryFour => "B4",
-            RobotLabel::BlueInfantryFive => "B5",
-            RobotLabel::RedHero => "R1",
-            RobotLabel::RedEngineer => "R2",
-            RobotLabel::RedInfantryThree => "R3",
-            RobotLabel::RedInfantryFour => "R4",
-            RobotLabel::RedInfantryFive => "R5",
-            RobotLabel::BlueSentry => "B7",
-            RobotLabel::RedSentry => "R7",
-        }
-    }
+pub trait Detector {
+    fn detect(
+        &self,
+        image: &DynamicImage,
+        camera_idx: usize,
+        frame_idx: usize,
+    ) -> Result<Vec<RobotDetection>>;
 }
 
-impl Display for RobotLabel {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.name())
-    }
+#[derive(Debug, Clone)]
+pub struct RobotDetection {
+    pub car_detection: Detection,
+    pub armor_detections: Vec<Detection>,
+    pub label: RobotLabel,
+    pub confidence: f32,
+    pub label_scores: Vec<LabelScore>,
 }
 
-impl TryFrom<u32> for RobotLabel {
-    type Error = anyhow::Error;
+// Armor confidences voting for a label, summed over the armors of one car. `weight` counts
+// the armors, it is fractional once votes of older frames are decayed by the tracker.
+#[derive(Debug, Clone)]
+pub struct LabelScore {
+    pub label: RobotLabel,
+    pub sum: f32,
+    pub weight: f32,
+}
 
-    fn try_from(value: u32) -> std::result::Result<Self, Self::Error> {
-        match value {
-            0 => Ok(RobotLabel::BlueHero),
-            1 => Ok(RobotLabel::BlueEngineer),
-            2 => Ok(RobotLabel::BlueInfantryThree),
-            3 => Ok(RobotLabel::BlueInfantryFour),
-            4 => Ok(RobotLabel::BlueInfantryFive),
-            5 => Ok(RobotLabel::RedHero),
-            6 => Ok(RobotLabel::RedEngineer),
-            7 => Ok(RobotLabel::RedInfantryThree),
-            8 => Ok(RobotLabel::RedInfantryFour),
-            9 => Ok(RobotLabel::RedInfantryFive),
-            10 => Ok(RobotLabel::BlueSentry),
-            11 => Ok(RobotLabel::RedSentry),
-            _ => Err(anyhow!("Invalid value for RobotLabel")),
+impl LabelScore {
+    // Sorted from the highest sum to the lowest, armor classes without a label are left out.
+    pub fn from_armor_detections(
+        armor_detection: &[Detection],
+        taxonomy: &LabelTaxonomy,
+    ) -> Vec<Self> {
+        let mut scores: Vec<LabelScore> = Vec::new();
+        for det in armor_detection
+            .iter()
+            .filter(|det| !det.confidence.is_nan())
+        {
+            let Some(label) = taxonomy.armor_label(det.class_id) else {
+                continue;
+            };
+            match scores.iter_mut().find(|score| score.label == label) {
+                Some(score) => {
+                    score.sum += det.confidence;
+                    score.weight += 1.0;
+                }
+                None => scores.push(LabelScore {
+                    label,
+                    sum: det.confidence,
+                    weight: 1.0,
+                }),
+            }
         }
+
+        scores.sort_by(|a, b| b.sum.partial_cmp(&a.sum).unwrap_or(Ordering::Equal));
+        scores
     }
-}
 
-#[derive(Debug)]
-pub struct RobotDetection {
-    pub car_detection: Detection,
-    pub label: RobotLabel,
-    pub confidence: f32,
+    #[inline]
+    pub fn confidence(&self) -> f32 {
+        if self.weight > 0.0 {
+            self.sum / self.weight
+        } else {
+            0.0
+        }
+    }
 }
 
 impl RobotDetection {
-    pub fn new(car_detection: Detection, armor_detection: Vec<Detection>) -> Option<Self> {
+    pub fn new(
+        car_detection: Detection,
+        armor_detection: Vec<Detection>,
+        taxonomy: &LabelTaxonomy,
+    ) -> Option<Self> {
         let span = span!(Level::TRACE, "RobotDetection::new");
         let _enter = span.enter();
 
-        let mut classid_conf_map = HashMap::with_capacity(armor_detection.len());
-
-        trace!("Building class confidence map from armor detections.");
-        armor_detection
-            .iter()
-            .filter(|det| !det.confidence.is_nan())
-            .for_each(|det| {
-                trace!(
-                    "Adding confidence {} for class_id {}.",
-                    det.confidence,
-                    det.class_id
-                );
-                *classid_conf_map.entry(det.class_id).or_insert(0.0) += det.confidence
-            });
+        // Votes are summed per label, armor classes of the same label add up and ignored
+        // classes never win.
+        let label_scores = LabelScore::from_armor_detections(&armor_detection, taxonomy);
+        if label_scores.is_empty() {
+            trace!("No valid label found in armor detections.");
+            return None;
+        }
 
-        if let Some(class_id) = classid_conf_map
-            .into_iter()
-            .max_by(|&(_, a), &(_, b)| a.partial_cmp(&b).unwrap_or(Ordering::Equal))
-            .map(|(item, _)| item)
-        {
-            trace!("Selected class_id {} with highest confidence.", class_id);
+        Self::from_scores(car_detection, armor_detection, label_scores)
+    }
 
-            let (sum, count) = armor_detection
-                .iter()
-                .filter(|det| det.class_id == class_id)
-                .map(|det| det.confidence)
-                .fold((0.0, 0), |(sum, count), conf| (sum + conf, count + 1));
-            let confidence = if count > 0 { sum / (count as f32) } else { 0.0 };
+    // Takes the label with the highest vote sum, e.g. the votes of a track.
+    pub fn from_scores(
+        car_detection: Detection,
+        armor_detection: Vec<Detection>,
+        label_scores: Vec<LabelScore>,
+    ) -> Option<Self> {
+        let top = label_scores.first()?;
+        trace!(
+            "Selected label {} with vote sum {} over weight {}.",
+            top.label,
+            top.sum,
+            top.weight
+        );
 
-            trace!(
-                "Computed average confidence {} for class_id {} over {} detections.",
-                confidence,
-                class_id,
-                count
-            );
+        Some(Self {
+            label: top.label.clone(),
+            confidence: top.confidence(),
+            car_detection,
+            armor_detections: armor_detection,
+            label_scores,
+        })
+    }
 
-            Some(Self {
-                car_detection,
-                label: RobotLabel::try_from(class_id).ok()?,
+    // A car with a known label and no armor votes, e.g. read from precomputed detections.
+    pub fn labelled(bbox: BBox, label: RobotLabel, confidence: f32) -> Self {
+        Self {
+            car_detection: Detection {
+                bbox,
                 confidence,
-            })
-        } else {
-            trace!("No valid class_id found in armor detections.");
-            None
+                class_id: 0,
+            },
+            armor_detections: Vec::new(),
+            label,
+            confidence,
+            label_scores: Vec::new(),
         }
     }
 
+    pub fn unknown(
+        car_detection: Detection,
+        team: Team,
+        team_confidence: f32,
+        taxonomy: &LabelTaxonomy,
+    ) -> Option<Self> {
+        let confidence = car_detection.confidence * team_confidence;
+        trace!(
+            "Unknown robot of team {:?} with confidence {}.",
+            team,
+            confidence
+        );
+
+        Some(Self {
+            label: taxonomy.unknown_label(team)?,
+            car_detection,
+            armor_detections: Vec::new(),
+            confidence,
+            label_scores: Vec::new(),
+        })
+    }
+
     #[inline]
     pub fn bbox(&self) -> BBox {
         self.car_detection.bbox
     }
 }
 
+impl TryFrom<&ExecutionConfig> for Execution {
+    type Error = anyhow::Error;
+
+    fn try_from(config: &ExecutionConfig) -> std::result::Result<Self, Self::Error> {
+        let config = match config {
+            ExecutionConfig::Name(name) => return Execution::try_from(name.as_str()),
+            ExecutionConfig::Session(config) => config,
+        };
+
+        Ok(Self {
+            providers: config
+                .providers
+                .iter()
+                .map(|provider| {
+                    Ok(ProviderOptions {
+                        provider: ExecutionProvider::try_from(provider.name.as_str())?,
+                        device_id: provider.device_id,
+                        device_type: provider.device_type.clone(),
+                        fp16: provider.fp16,
+                        cache_dir: provider.cache_dir.clone(),
+                        timing_cache_dir: provider.timing_cache_dir.clone(),
+                    })
+                })
+                .collect::<Result<_>>()?,
+            intra_threads: config.intra_threads,
+            inter_threads: config.inter_threads,
+            parallel_execution: config.parallel_execution,
+            optimization_level: OptimizationLevel::try_from(config.optimization_level.as_str())?,
+            optimized_model_dir: config.optimized_model_dir.clone(),
+        })
+    }
+}
+
+impl TryFrom<&ModelConfig> for ModelSpec {
+    type Error = anyhow::Error;
+
+    fn try_from(config: &ModelConfig) -> std::result::Result<Self, Self::Error> {
+        Ok(Self {
+            input_name: config.input_name.clone(),
+            output_name: config.output_name.clone(),
+            input_size: config.input_size.map(|[width, height]| (width, height)),
+            class_names: config.class_names.clone(),
+            output_format: config
+                .output_format
+                .as_deref()
+                .map_or(Ok(OutputFormat::Auto), OutputFormat::try_from)?,
+            nms_class_agnostic: config.nms_class_agnostic,
+            nms_method: NmsMethod::new(
+                config.nms_method.as_deref().unwrap_or("hard"),
+                config.soft_nms_sigma.unwrap_or(0.5),
+            )?,
+            class_nms_thresholds: config
+                .class_nms_thresh
+                .iter()
+                .map(|(class_id, thresh)| {
+                    class_id
+                        .parse::<u32>()
+                        .map(|class_id| (class_id, *thresh))
+                        .map_err(|e| anyhow!("Invalid class id {class_id}: {e}"))
+                })
+                .collect::<Result<_>>()?,
+        })
+    }
+}
+
 pub struct RobotDetector {
     car_detector: Yolo,
     armor_detector: Yolo,
     execution: Execution,
+    crop_options: CropOptions,
+    crop_exporter: Option<ArmorCropExporter>,
+    assignment_exporter: Option<AssignmentExporter>,
+    taxonomy: LabelTaxonomy,
+    unknown_options: UnknownOptions,
+    label_assignment: LabelAssignment,
+    min_reassign_share: f32,
+    tracker_options: TrackerOptions,
+    trackers: Mutex<HashMap<usize, Tracker>>,
 }
 
 impl RobotDetector {
@@ -175,15 +272,30 @@ impl RobotDetector {
         car_nms_thresh: f32,
         armor_nms_thresh: f32,
         execution: Execution,
+        preprocess: Preprocess,
+        car_spec: ModelSpec,
+        armor_spec: ModelSpec,
     ) -> Self {
         let span = span!(Level::TRACE, "RobotDetector::new");
         let _enter = span.enter();
 
         trace!("Initializing car detector...");
-        let car_detector = Yolo::new(car_onnx, car_conf_thresh, car_nms_thresh, (640, 640));
+        let car_detector = Yolo::new(
+            car_onnx,
+            car_conf_thresh,
+            car_nms_thresh,
+            preprocess,
+            car_spec,
+        );
 
         trace!("Initializing armor detector...");
-        let armor_detector = Yolo::new(armor_onnx, armor_conf_thresh, armor_nms_thresh, (640, 640));
+        let armor_detector = Yolo::new(
+            armor_onnx,
+            armor_conf_thresh,
+            armor_nms_thresh,
+            preprocess,
+            armor_spec,
+        );
 
         debug!("Robot detector initialized.");
 
@@ -191,22 +303,81 @@ impl RobotDetector {
             car_detector,
             armor_detector,
             execution,
+            crop_options: CropOptions::default(),
+            crop_exporter: None,
+            assignment_exporter: None,
+            taxonomy: LabelTaxonomy::default(),
+            unknown_options: UnknownOptions::default(),
+            label_assignment: LabelAssignment::default(),
+            min_reassign_share: 0.5,
+            tracker_options: TrackerOptions::default(),
+            trackers: Mutex::new(HashMap::new()),
         }
     }
 
-    pub fn from_config(config: &DetectorConfig) -> Result<Self> {
-        Ok(RobotDetector::new(
+    pub fn from_config(config: &DetectorConfig, taxonomy: &LabelTaxonomy) -> Result<Self> {
+        let mut detector = RobotDetector::new(
             &config.car_onnx_path,
             &config.armor_onnx_path,
             config.car_conf_thresh,
             config.armor_conf_thresh,
             config.car_nms_thresh,
             config.armor_nms_thresh,
-            Execution::try_from(config.execution.as_str()).map_err(|e| {
-                error!("Invalid execution {}: {e}", config.execution);
-                anyhow!("Invalid execution {}: {e}", config.execution)
+            Execution::try_from(&config.execution).map_err(|e| {
+                error!("Invalid execution {:?}: {e}", config.execution);
+                anyhow!("Invalid execution {:?}: {e}", config.execution)
+            })?,
+            Preprocess::new(
+                &config.preprocess.resize_mode,
+                config.preprocess.pad_color,
+                &config.preprocess.filter,
+            )
+            .map_err(|e| {
+                error!("Invalid preprocess {:?}: {e}", config.preprocess);
+                anyhow!("Invalid preprocess {:?}: {e}", config.preprocess)
             })?,
-        ))
+            ModelSpec::try_from(&config.car_model).map_err(|e| {
+                error!("Invalid car model config {:?}: {e}", config.car_model);
+                anyhow!("Invalid car model config {:?}: {e}", config.car_model)
+            })?,
+            ModelSpec::try_from(&config.armor_model).map_err(|e| {
+                error!("Invalid armor model config {:?}: {e}", config.armor_model);
+                anyhow!("Invalid armor model config {:?}: {e}", config.armor_model)
+            })?,
+        );
+
+        detector.set_crop_options(CropOptions {
+            margin_ratio: config.crop.margin_ratio,
+            min_size: (config.crop.min_size[0], config.crop.min_size[1]),
+            small_crop: SmallCrop::try_from(config.crop.small_crop.as_str()).map_err(|e| {
+                error!("Invalid crop config {:?}: {e}", config.crop);
+                anyhow!("Invalid crop config {:?}: {e}", config.crop)
+            })?,
+        });
+        detector.set_taxonomy(taxonomy.clone());
+        detector.set_label_assignment(
+            LabelAssignment::try_from(config.label_assignment.as_str()).map_err(|e| {
+                error!("Invalid label assignment {}: {e}", config.label_assignment);
+                anyhow!("Invalid label assignment {}: {e}", config.label_assignment)
+            })?,
+        );
+        detector.set_min_reassign_share(config.min_reassign_share);
+        detector.set_unknown_options(UnknownOptions {
+            enabled: config.unknown.enabled,
+            min_brightness: config.unknown.min_brightness,
+            min_color_diff: config.unknown.min_color_diff,
+            min_pixels: config.unknown.min_pixels,
+            min_dominance: config.unknown.min_dominance,
+        });
+        detector.set_tracker_options(TrackerOptions {
+            enabled: config.tracking.enabled,
+            iou_threshold: config.tracking.iou_threshold,
+            high_confidence: config.tracking.high_confidence,
+            max_age: config.tracking.max_age,
+            vote_decay: config.tracking.vote_decay,
+        });
+
+        Ok(detector)
     }
 
     #[inline]
@@ -215,20 +386,71 @@ impl RobotDetector {
     }
 
     pub fn build_models(&mut self) -> Result<()> {
-        self.car_detector.build(self.execution).map_err(|e| {
+        self.car_detector.build(&self.execution).map_err(|e| {
             error!("Failed to build car detector model: {e}");
             anyhow!("Failed to build car detector model: {e}")
         })?;
 
-        self.armor_detector.build(self.execution).map_err(|e| {
+        self.armor_detector.build(&self.execution).map_err(|e| {
             error!("Failed to build armor detector model: {e}");
             anyhow!("Failed to build armor detector model: {e}")
         })?;
 
+        debug!(
+            "Car model input size: {:?}, classes: {:?}; armor model input size: {:?}, classes: {:?}",
+            self.car_detector.input_size(),
+            self.car_detector.class_names(),
+            self.armor_detector.input_size(),
+            self.armor_detector.class_names()
+        );
+
         Ok(())
     }
 
+    pub fn set_crop_options(&mut self, crop_options: CropOptions) {
+        self.crop_options = crop_options;
+    }
+
+    // Unknown robots need the unknown labels in the taxonomy, see `LabelTaxonomy::with_unknown_labels`.
+    pub fn set_unknown_options(&mut self, unknown_options: UnknownOptions) {
+        self.unknown_options = unknown_options;
+    }
+
+    pub fn set_label_assignment(&mut self, label_assignment: LabelAssignment) {
+        self.label_assignment = label_assignment;
+    }
+
+    pub fn set_min_reassign_share(&mut self, min_reassign_share: f32) {
+        self.min_reassign_share = min_reassign_share;
+    }
+
+    // Tracking needs the camera and frame indices, it only applies through `Detector::detect`.
+    pub fn set_tracker_options(&mut self, tracker_options: TrackerOptions) {
+        self.tracker_options = tracker_options;
+        self.trackers = Mutex::new(HashMap::new());
+    }
+
+    pub fn set_taxonomy(&mut self, taxonomy: LabelTaxonomy) {
+        self.taxonomy = taxonomy;
+    }
+
+    pub fn set_crop_exporter(&mut self, crop_exporter: ArmorCropExporter) {
+        self.crop_exporter = Some(crop_exporter);
+    }
+
+    pub fn set_assignment_exporter(&mut self, assignment_exporter: AssignmentExporter) {
+        self.assignment_exporter = Some(assignment_exporter);
+    }
+
     pub fn detect(&self, image: &DynamicImage) -> Result<Vec<RobotDetection>> {
+        self.detect_frame(image, None)
+    }
+
+    fn detect_frame(
+        &self,
+        image: &DynamicImage,
+        frame: Option<(usize, usize)>,
+    ) -> Result<Vec<RobotDetection>> {
         let span = span!(Level::TRACE, "RobotDetector::detect");
         let _enter = span.enter();
 
@@ -246,7 +468,7 @@ impl RobotDetector {
             car_detections.len()
         );
 
-        let car_images: Vec<_> = car_detections
+        let car_crops: Vec<_> = car_detections
             .iter()
             .map(|det| {
                 let bbox = &det.bbox;
@@ -257,19 +479,18 @@ impl RobotDetector {
                     bbox.width,
                     bbox.height
                 );
-                image.crop_imm(
-                    (bbox.x_center - bbox.width / 2.0) as u32,
-                    (bbox.y_center - bbox.height / 2.0) as u32,
-                    bbox.width as u32,
-                    bbox.height as u32,
-                )
+                CarCrop::new(image, bbox, &self.crop_options)
             })
             .collect();
 
         trace!("Running armor detector inference on cropped car images...");
-        let armor_detections = car_images
-            .into_iter()
-            .map(|car_image| self.armor_detector.infer(&car_image))
+        let armor_detections = car_crops
+            .iter()
+            .map(|car_crop| {
+                car_crop.as_ref().map_or(Ok(Vec::new()), |car_crop| {
+                    self.armor_detector.infer(&car_crop.image)
+                })
+            })
             .collect::<Result<Vec<_>, _>>()
             .map_err(|e| {
                 error!("Failed to infer car images in armor detector: {e}");
@@ -283,11 +504,44 @@ impl RobotDetector {
 
         assert_eq!(car_detections.len(), armor_detections.len());
 
-        let mut robots_map: HashMap<RobotLabel, RobotDetection> =
-            HashMap::with_capacity(car_detections.len());
-        for (i, (car_det, armor_det)) in car_detections
+        let image_armor_detections: Vec<_> = armor_detections
+            .iter()
+            .zip(car_crops.iter())
+            .map(|(armor_det, car_crop)| {
+                car_crop
+                    .as_ref()
+                    .map_or_else(Vec::new, |car_crop| car_crop.restore_detections(armor_det))
+            })
+            .collect();
+
+        let track_scores = match frame {
+            Some((camera_idx, frame_idx)) if self.tracker_options.enabled => {
+                let scores = image_armor_detections
+                    .iter()
+                    .map(|armor_det| LabelScore::from_armor_detections(armor_det, &self.taxonomy))
+                    .collect();
+                let mut trackers = self
+                    .trackers
+                    .lock()
+                    .map_err(|e| anyhow!("Tracker lock poisoned: {e}"))?;
+                let tracker = trackers
+                    .entry(camera_idx)
+                    .or_insert_with(|| Tracker::new(self.tracker_options));
+                Some(tracker.update(frame_idx, &car_detections, scores))
+            }
+            _ => None,
+        };
+
+        let car_bboxes: Vec<_> = car_detections.iter().map(|det| det.bbox).collect();
+        let mut labelled_cars = Vec::with_capacity(car_detections.len());
+        let mut labelled_robots = Vec::with_capacity(car_detections.len());
+        let mut unknown_robots = Vec::new();
+        let mut track_scores = track_scores.map(Vec::into_iter);
+        for (i, (((car_det, armor_det), image_armor_det), car_crop)) in car_detections
             .into_iter()
-            .zip(armor_detections.into_iter())
+            .zip(armor_detections.iter())
+            .zip(image_armor_detections)
+            .zip(car_crops.iter())
             .enumerate()
         {
             debug!(
@@ -299,38 +553,114 @@ impl RobotDetector {
                 "Car detection: {:?}, armor detection: {:?}",
                 car_det, armor_det
             );
-            if let Some(robot_det) = RobotDetection::new(car_det, armor_det) {
+            let labelled_det = match track_scores.as_mut() {
+                Some(track_scores) => RobotDetection::from_scores(
+                    car_det.clone(),
+                    image_armor_det,
+                    track_scores.next().unwrap_or_default(),
+                ),
+                None => RobotDetection::new(car_det.clone(), image_armor_det, &self.taxonomy),
+            };
+            let robot_det = if self.unknown_options.enabled {
+                labelled_det.or_else(|| {
+                    let (team, team_confidence) =
+                        car_crop.as_ref().map_or((Team::None, 1.0), |car_crop| {
+                            estimate_team(&car_crop.image, &self.unknown_options)
+                        });
+                    RobotDetection::unknown(car_det, team, team_confidence, &self.taxonomy)
+                })
+            } else {
+                labelled_det
+            };
+            labelled_cars.push(robot_det.is_some());
+            if let Some(robot_det) = robot_det {
                 debug!(
                     "Car {} classified as label {:?} with confidence {}.",
                     i, robot_det.label, robot_det.confidence
                 );
-                if let Some(robot_det_exist) = robots_map.get(&robot_det.label) {
-                    if robot_det_exist.confidence < robot_det.confidence {
-                        debug!(
-                            "Updating label {:?} with higher confidence: {} -> {}.",
-                            robot_det.label, robot_det_exist.confidence, robot_det.confidence
-                        );
-                        robots_map.insert(robot_det.label, robot_det);
-                    }
+                if robot_det.label.is_unknown() {
+                    unknown_robots.push(robot_det);
                 } else {
-                    debug!(
-                        "Inserting new detection for label {:?} with confidence {}.",
-                        robot_det.label, robot_det.confidence
-                    );
-                    robots_map.insert(robot_det.label, robot_det);
+                    labelled_robots.push(robot_det);
                 }
             } else {
                 trace!("No valid robot detection for car {}.", i);
             }
         }
 
-        let robots: Vec<_> = robots_map.into_iter().map(|(_k, v)| v).collect();
+        let assignment = assign_labels(
+            labelled_robots,
+            self.label_assignment,
+            self.min_reassign_share,
+        );
+        if let (Some(assignment_exporter), Some((camera_idx, frame_idx))) =
+            (&self.assignment_exporter, frame)
+        {
+            if let Err(e) = assignment_exporter.export(camera_idx, frame_idx, &assignment) {
+                error!("Failed to export label assignment of camera {camera_idx}, frame {frame_idx}: {e}");
+            }
+        }
+        if self.unknown_options.enabled {
+            unknown_robots.extend(assignment.dropped.into_iter().filter_map(|robot_det| {
+                let team = robot_det.label.team();
+                RobotDetection::unknown(robot_det.car_detection, team, 1.0, &self.taxonomy)
+            }));
+        }
+
+        let robots: Vec<_> = assignment
+            .robots
+            .into_iter()
+            .chain(unknown_robots)
+            .collect();
         debug!("Detection complete. Robots: {:?}.", robots);
 
+        if let (Some(crop_exporter), Some((camera_idx, frame_idx))) = (&self.crop_exporter, frame) {
+            // Crops are grouped by the label after assignment, cars it dropped are skipped.
+            for (i, ((car_crop, armor_det), (car_bbox, labelled))) in car_crops
+                .iter()
+                .zip(armor_detections.iter())
+                .zip(car_bboxes.iter().zip(labelled_cars))
+                .enumerate()
+            {
+                let Some(car_crop) = car_crop else {
+                    continue;
+                };
+                let label = robots
+                    .iter()
+                    .find(|robot| robot.bbox() == *car_bbox)
+                    .map(|robot| robot.label.clone());
+                if label.is_none() && labelled {
+                    trace!("Skipping armor crop of dropped car {i}.");
+                    continue;
+                }
+                if let Err(e) = crop_exporter.export(
+                    camera_idx,
+                    frame_idx,
+                    i,
+                    &car_crop.image,
+                    armor_det,
+                    label,
+                ) {
+                    error!("Failed to export armor crop {i} of camera {camera_idx}, frame {frame_idx}: {e}");
+                }
+            }
+        }
+
         Ok(robots)
     }
 }
 
+impl Detector for RobotDetector {
+    fn detect(
+        &self,
+        image: &DynamicImage,
+        camera_idx: usize,
+        frame_idx: usize,
+    ) -> Result<Vec<RobotDetection>> {
+        self.detect_frame(image, Some((camera_idx, frame_idx)))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::path::PathBuf;
@@ -348,7 +678,10 @@ mod tests {
             0.45,
             0.50,
             0.75,
-            Execution::CPU,
+            Execution::try_from("CPU")?,
+            Preprocess::default(),
+            ModelSpec::default(),
+            ModelSpec::default(),
         );
 
         robot_detector.build_models()?;
@@ -359,29 +692,102 @@ mod tests {
         assert_eq!(detections.len(), 6);
         assert!(detections
             .iter()
-            .find(|det| det.label == RobotLabel::RedSentry)
+            .find(|det| det.label.name_abbr() == "R7")
             .is_some());
         assert!(detections
             .iter()
-            .find(|det| det.label == RobotLabel::RedEngineer)
+            .find(|det| det.label.name_abbr() == "R2")
             .is_some());
         assert!(detections
             .iter()
-            .find(|det| det.label == RobotLabel::RedInfantryFive)
+            .find(|det| det.label.name_abbr() == "R5")
             .is_some());
         assert!(detections
             .iter()
-            .find(|det| det.label == RobotLabel::BlueSentry)
+            .find(|det| det.label.name_abbr() == "B7")
             .is_some());
         assert!(detections
             .iter()
-            .find(|det| det.label == RobotLabel::BlueEngineer)
+            .find(|det| det.label.name_abbr() == "B2")
             .is_some());
         assert!(detections
             .iter()
-            .find(|det| det.label == RobotLabel::BlueInfantryFive)
+            .find(|det| det.label.name_abbr() == "B5")
             .is_some());
 
         Ok(())
     }
+
+    #[test]
+    fn test_execution_from_config() -> Result<()> {
+        #[derive(serde::Deserialize)]
+        struct Wrapper {
+            execution: ExecutionConfig,
+        }
+        let parse =
+            |content: &str| toml::from_str::<Wrapper>(content).map(|wrapper| wrapper.execution);
+
+        let execution = Execution::try_from(&parse(
+            r#"
+            [execution]
+            inter_threads = 2
+            [[execution.providers]]
+            name = "TensorRT"
+            cache_dir = "engines"
+            timing_cache_dir = "timing"
+            "#,
+        )?)?;
+        assert_eq!(execution.inter_threads, Some(2));
+        assert_eq!(execution.providers[0].cache_dir.as_deref(), Some("engines"));
+        assert_eq!(
+            execution.providers[0].timing_cache_dir.as_deref(),
+            Some("timing")
+        );
+
+        // Misspelled keys are rejected instead of leaving the defaults.
+        assert!(parse("[execution]\ninter_thread = 2").is_err());
+        assert!(parse("[[execution.providers]]\nname = \"CUDA\"\ndevice = 1").is_err());
+        assert!(parse(r#"execution = "CUDA""#).is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_robot_detection_votes_per_label() -> Result<()> {
+        // Armor classes 0 and 1 both vote for B1, class 2 is ignored.
+        let classes = vec![
+            LabelInfo::new("Blue Hero", "B1", Team::Blue, "hero"),
+            LabelInfo::new("Red Hero", "R1", Team::Red, "hero"),
+        ];
+        let armor_classes = ["B1", "B1", "", "R1"].map(str::to_string);
+        let taxonomy = LabelTaxonomy::new(classes, &armor_classes)?;
+        let detection = |class_id, confidence| Detection {
+            bbox: BBox {
+                x_center: 50.0,
+                y_center: 50.0,
+                width: 20.0,
+                height: 10.0,
+            },
+            confidence,
+            class_id,
+        };
+
+        let armors = vec![
+            detection(2, 0.95),
+            detection(2, 0.9),
+            detection(3, 0.7),
+            detection(0, 0.5),
+            detection(1, 0.4),
+        ];
+        let robot = RobotDetection::new(detection(0, 0.9), armors, &taxonomy).unwrap();
+        assert_eq!(robot.label.name_abbr(), "B1");
+        assert!((robot.confidence - 0.45).abs() < 1e-6);
+        assert_eq!(robot.label_scores.len(), 2);
+        assert_eq!(robot.label_scores[1].label.name_abbr(), "R1");
+
+        let armors = vec![detection(2, 0.95)];
+        assert!(RobotDetection::new(detection(0, 0.9), armors, &taxonomy).is_none());
+
+        Ok(())
+    }
 }
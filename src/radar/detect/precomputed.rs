@@ -0,0 +1,354 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use image::{DynamicImage, GenericImageView};
+use tracing::{debug, error, span, trace, warn, Level};
+
+use super::{coco::CocoDataset, BBox, Detector, LabelTaxonomy, RobotDetection};
+use crate::config::PrecomputedConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DetectionFormat {
+    Yolo,
+    Coco,
+}
+
+impl TryFrom<&str> for DetectionFormat {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> std::result::Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "yolo" => Ok(DetectionFormat::Yolo),
+            "coco" => Ok(DetectionFormat::Coco),
+            _ => Err(anyhow!("Failed to convert {value} to detection format")),
+        }
+    }
+}
+
+// YOLO layout: <path>/images_<camera>/<frame:06>.txt, each line is
+// "<class_id> <x_center> <y_center> <width> <height> [confidence]" normalized to image size,
+// where class ids index the label taxonomy.
+// COCO layout: a single json file whose image file names end with "images_<camera>/<frame>.png",
+// categories are matched to labels by name.
+#[derive(Debug)]
+pub struct PrecomputedDetector {
+    format: DetectionFormat,
+    path: PathBuf,
+    coco_detections: HashMap<(usize, usize), Vec<RobotDetection>>,
+    taxonomy: LabelTaxonomy,
+}
+
+impl PrecomputedDetector {
+    pub fn new<P: AsRef<Path>>(
+        path: P,
+        format: DetectionFormat,
+        taxonomy: LabelTaxonomy,
+    ) -> Result<Self> {
+        let span = span!(Level::TRACE, "PrecomputedDetector::new");
+        let _enter = span.enter();
+
+        let path = path.as_ref().to_path_buf();
+        debug!(
+            "Initializing precomputed detector from {:?} in {:?} format",
+            path, format
+        );
+
+        let coco_detections = match format {
+            DetectionFormat::Coco => Self::read_coco(&path, &taxonomy).map_err(|e| {
+                error!("Failed to read coco detections from {:?}: {e}", path);
+                anyhow!("Failed to read coco detections from {:?}: {e}", path)
+            })?,
+            DetectionFormat::Yolo => {
+                if !path.is_dir() {
+                    return Err(anyhow!("Detection directory {:?} does not exist", path));
+                }
+                HashMap::new()
+            }
+        };
+
+        Ok(Self {
+            format,
+            path,
+            coco_detections,
+            taxonomy,
+        })
+    }
+
+    pub fn from_config(config: &PrecomputedConfig, taxonomy: &LabelTaxonomy) -> Result<Self> {
+        Self::new(
+            &config.path,
+            DetectionFormat::try_from(config.format.as_str()).map_err(|e| {
+                error!("Invalid detection format {}: {e}", config.format);
+                anyhow!("Invalid detection format {}: {e}", config.format)
+            })?,
+            taxonomy.clone(),
+        )
+    }
+
+    fn read_coco(
+        path: &Path,
+        taxonomy: &LabelTaxonomy,
+    ) -> Result<HashMap<(usize, usize), Vec<RobotDetection>>> {
+        let span = span!(Level::TRACE, "PrecomputedDetector::read_coco");
+        let _enter = span.enter();
+
+        let file = File::open(path)?;
+        let dataset: CocoDataset = serde_json::from_reader(BufReader::new(file))?;
+        debug!(
+            "Read {} images, {} annotations and {} categories",
+            dataset.images.len(),
+            dataset.annotations.len(),
+            dataset.categories.len()
+        );
+
+        let labels = dataset
+            .categories
+            .iter()
+            .map(|category| {
+                taxonomy
+                    .find(&category.name)
+                    .map(|label| (category.id, label))
+                    .ok_or_else(|| anyhow!("Invalid category {}", category.name))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+
+        let frames = dataset
+            .images
+            .iter()
+            .map(|image| {
+                parse_frame_path(&image.file_name)
+                    .map(|frame| (image.id, frame))
+                    .ok_or_else(|| anyhow!("Invalid image file name {}", image.file_name))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+
+        let mut detections: HashMap<(usize, usize), Vec<RobotDetection>> = HashMap::new();
+        for annotation in dataset.annotations {
+            let frame = frames
+                .get(&annotation.image_id)
+                .ok_or_else(|| anyhow!("Image {} not found", annotation.image_id))?;
+            let label = labels
+                .get(&annotation.category_id)
+                .ok_or_else(|| anyhow!("Category {} not found", annotation.category_id))?;
+            let [x_min, y_min, width, height] = annotation.bbox;
+
+            detections
+                .entry(*frame)
+                .or_default()
+                .push(RobotDetection::labelled(
+                    BBox {
+                        x_center: x_min + width / 2.0,
+                        y_center: y_min + height / 2.0,
+                        width,
+                        height,
+                    },
+                    label.clone(),
+                    annotation.score.unwrap_or(1.0),
+                ));
+        }
+
+        Ok(detections)
+    }
+
+    fn read_yolo(&self, path: &Path, image_size: (u32, u32)) -> Result<Vec<RobotDetection>> {
+        let span = span!(Level::TRACE, "PrecomputedDetector::read_yolo");
+        let _enter = span.enter();
+
+        let content = fs::read_to_string(path)?;
+        let (image_width, image_height) = (image_size.0 as f32, image_size.1 as f32);
+
+        let detections = content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .filter_map(|(line_idx, line)| {
+                let result = || -> Result<RobotDetection> {
+                    let values = line
+                        .split_whitespace()
+                        .map(|value| value.parse::<f32>())
+                        .collect::<Result<Vec<_>, _>>()?;
+                    if values.len() != 5 && values.len() != 6 {
+                        return Err(anyhow!("Expected 5 or 6 columns, got {}", values.len()));
+                    }
+                    if values[0].fract() != 0.0 || values[0] < 0.0 {
+                        return Err(anyhow!("Invalid class id {}", values[0]));
+                    }
+
+                    Ok(RobotDetection::labelled(
+                        BBox {
+                            x_center: values[1] * image_width,
+                            y_center: values[2] * image_height,
+                            width: values[3] * image_width,
+                            height: values[4] * image_height,
+                        },
+                        self.taxonomy
+                            .label(values[0] as u32)
+                            .ok_or_else(|| anyhow!("Invalid class id {}", values[0]))?,
+                        values.get(5).copied().unwrap_or(1.0),
+                    ))
+                }();
+                result
+                    .map_err(|e| {
+                        warn!("Skipped line {} of {:?}: {e}", line_idx + 1, path);
+                    })
+                    .ok()
+            })
+            .collect();
+
+        Ok(detections)
+    }
+}
+
+impl Detector for PrecomputedDetector {
+    fn detect(
+        &self,
+        image: &DynamicImage,
+        camera_idx: usize,
+        frame_idx: usize,
+    ) -> Result<Vec<RobotDetection>> {
+        let span = span!(Level::TRACE, "PrecomputedDetector::detect");
+        let _enter = span.enter();
+
+        let detections = match self.format {
+            DetectionFormat::Coco => self
+                .coco_detections
+                .get(&(camera_idx, frame_idx))
+                .cloned()
+                .unwrap_or_default(),
+            DetectionFormat::Yolo => {
+                let path = self
+                    .path
+                    .join(format!("images_{camera_idx}/{:06}.txt", frame_idx));
+                if !path.exists() {
+                    trace!("Detection file {:?} does not exist.", path);
+                    return Ok(Vec::new());
+                }
+                self.read_yolo(&path, image.dimensions()).map_err(|e| {
+                    error!("Failed to read detections from {:?}: {e}", path);
+                    anyhow!("Failed to read detections from {:?}: {e}", path)
+                })?
+            }
+        };
+
+        debug!(
+            "Precomputed detections of camera {camera_idx}, frame {frame_idx}: {:?}",
+            detections
+        );
+        Ok(detections)
+    }
+}
+
+// Extracts (camera, frame) from paths like "images/images_1/000042.png".
+fn parse_frame_path(file_name: &str) -> Option<(usize, usize)> {
+    let path = Path::new(file_name);
+    let frame_idx = path.file_stem()?.to_str()?.parse().ok()?;
+    let camera_idx = path
+        .parent()?
+        .file_name()?
+        .to_str()?
+        .strip_prefix("images_")?
+        .parse()
+        .ok()?;
+
+    Some((camera_idx, frame_idx))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use assert_approx_eq::assert_approx_eq;
+    use image::RgbImage;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_frame_path() {
+        assert_eq!(
+            parse_frame_path("images/images_1/000042.png"),
+            Some((1, 42))
+        );
+        assert_eq!(parse_frame_path("images_0/000000.png"), Some((0, 0)));
+        assert_eq!(parse_frame_path("000042.png"), None);
+        assert_eq!(parse_frame_path("images_a/000042.png"), None);
+    }
+
+    #[test]
+    fn test_precomputed_yolo() -> Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir_all(dir.path().join("images_1"))?;
+        let mut file = File::create(dir.path().join("images_1/000003.txt"))?;
+        writeln!(file, "11 0.5 0.25 0.1 0.2")?;
+        writeln!(file, "2 0.1 0.1 0.1 0.1 0.6")?;
+        writeln!(file, "99 0.1 0.1 0.1 0.1")?;
+        writeln!(file, "-1 0.1 0.1 0.1 0.1")?;
+        writeln!(file, "2.7 0.1 0.1 0.1 0.1")?;
+
+        let detector =
+            PrecomputedDetector::new(dir.path(), DetectionFormat::Yolo, LabelTaxonomy::default())?;
+        let image = DynamicImage::ImageRgb8(RgbImage::new(200, 100));
+
+        let detections = detector.detect(&image, 1, 3)?;
+        assert_eq!(detections.len(), 2);
+        assert_eq!(detections[0].label.name_abbr(), "R7");
+        assert_approx_eq!(detections[0].confidence, 1.0);
+        assert_approx_eq!(detections[0].bbox().x_center, 100.0);
+        assert_approx_eq!(detections[0].bbox().y_center, 25.0);
+        assert_approx_eq!(detections[0].bbox().width, 20.0);
+        assert_approx_eq!(detections[0].bbox().height, 20.0);
+        assert_eq!(detections[1].label.name_abbr(), "B3");
+        assert_approx_eq!(detections[1].confidence, 0.6);
+
+        assert!(detector.detect(&image, 0, 3)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_precomputed_coco() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("annotations.json");
+        fs::write(
+            &path,
+            r#"{
+                "images": [
+                    {"id": 1, "file_name": "images/images_0/000007.png", "width": 200, "height": 100},
+                    {"id": 2, "file_name": "images/images_2/000007.png", "width": 200, "height": 100}
+                ],
+                "annotations": [
+                    {"id": 1, "image_id": 1, "category_id": 3, "bbox": [10, 20, 30, 40]},
+                    {"id": 2, "image_id": 2, "category_id": 7, "bbox": [0, 0, 10, 10], "score": 0.7}
+                ],
+                "categories": [
+                    {"id": 3, "name": "R1"},
+                    {"id": 7, "name": "Blue Sentry"}
+                ]
+            }"#,
+        )?;
+
+        let detector =
+            PrecomputedDetector::new(&path, DetectionFormat::Coco, LabelTaxonomy::default())?;
+        let image = DynamicImage::ImageRgb8(RgbImage::new(200, 100));
+
+        let detections = detector.detect(&image, 0, 7)?;
+        assert_eq!(detections.len(), 1);
+        assert_eq!(detections[0].label.name_abbr(), "R1");
+        assert_approx_eq!(detections[0].bbox().x_center, 25.0);
+        assert_approx_eq!(detections[0].bbox().y_center, 40.0);
+
+        let detections = detector.detect(&image, 2, 7)?;
+        assert_eq!(detections.len(), 1);
+        assert_eq!(detections[0].label.name_abbr(), "B7");
+        assert_approx_eq!(detections[0].confidence, 0.7);
+
+        assert!(detector.detect(&image, 1, 7)?.is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,98 @@
+use image::DynamicImage;
+use tracing::{span, trace, Level};
+
+use super::Team;
+
+#[derive(Debug, Clone, Copy)]
+pub struct UnknownOptions {
+    pub enabled: bool,
+    pub min_brightness: u8,
+    pub min_color_diff: u8,
+    pub min_pixels: u32,
+    pub min_dominance: f32,
+}
+
+impl Default for UnknownOptions {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_brightness: 200,
+            min_color_diff: 60,
+            min_pixels: 20,
+            min_dominance: 0.7,
+        }
+    }
+}
+
+// Guesses the team from the armor light bars: bright pixels whose red or blue channel clearly
+// dominates the other. Returns the team and the share of pixels voting for it, or `Team::None`
+// with a share of 1.0 when there are too few such pixels or neither colour dominates.
+pub fn estimate_team(image: &DynamicImage, options: &UnknownOptions) -> (Team, f32) {
+    let span = span!(Level::TRACE, "estimate_team");
+    let _enter = span.enter();
+
+    let (mut red, mut blue) = (0u32, 0u32);
+    for pixel in image.to_rgb8().pixels() {
+        let [r, g, b] = pixel.0;
+        if r.max(b) < options.min_brightness {
+            continue;
+        }
+        let [r, g, b, diff] = [r, g, b, options.min_color_diff].map(u16::from);
+        if r >= b.max(g) + diff {
+            red += 1;
+        } else if b >= r.max(g) + diff {
+            blue += 1;
+        }
+    }
+    trace!("Light bar pixels: red {red}, blue {blue}.");
+
+    let total = red + blue;
+    if total < options.min_pixels {
+        return (Team::None, 1.0);
+    }
+
+    let (team, count) = if red >= blue {
+        (Team::Red, red)
+    } else {
+        (Team::Blue, blue)
+    };
+    let dominance = count as f32 / total as f32;
+    if dominance < options.min_dominance {
+        return (Team::None, 1.0);
+    }
+
+    (team, dominance)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    fn image_with_bars(bar_color: Rgb<u8>, bar_pixels: u32) -> DynamicImage {
+        let mut image = RgbImage::from_pixel(40, 20, Rgb([60, 60, 60]));
+        for idx in 0..bar_pixels {
+            image.put_pixel(idx % 40, idx / 40, bar_color);
+        }
+        DynamicImage::ImageRgb8(image)
+    }
+
+    #[test]
+    fn test_estimate_team() {
+        let options = UnknownOptions::default();
+
+        let (team, dominance) = estimate_team(&image_with_bars(Rgb([250, 80, 90]), 50), &options);
+        assert_eq!(team, Team::Red);
+        assert_eq!(dominance, 1.0);
+
+        let (team, _) = estimate_team(&image_with_bars(Rgb([90, 120, 255]), 50), &options);
+        assert_eq!(team, Team::Blue);
+
+        let (team, _) = estimate_team(&image_with_bars(Rgb([250, 80, 90]), 10), &options);
+        assert_eq!(team, Team::None);
+
+        let (team, _) = estimate_team(&image_with_bars(Rgb([255, 255, 255]), 50), &options);
+        assert_eq!(team, Team::None);
+    }
+}
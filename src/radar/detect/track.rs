@@ -0,0 +1,344 @@
+use std::cmp::Ordering;
+
+use tracing::{debug, span, trace, Level};
+
+use super::{
+    assign::hungarian,
+    yolo::{Detection, Yolo},
+    BBox, LabelScore,
+};
+
+const VELOCITY_SMOOTHING: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy)]
+pub struct TrackerOptions {
+    pub enabled: bool,
+    pub iou_threshold: f32,
+    pub high_confidence: f32,
+    pub max_age: usize,
+    pub vote_decay: f32,
+}
+
+impl Default for TrackerOptions {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            iou_threshold: 0.3,
+            high_confidence: 0.6,
+            max_age: 10,
+            vote_decay: 0.9,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Track {
+    id: u64,
+    bbox: BBox,
+    velocity: (f32, f32),
+    last_frame: usize,
+    votes: Vec<LabelScore>,
+}
+
+impl Track {
+    fn predict(&self, frame_idx: usize) -> BBox {
+        let dt = frame_idx.saturating_sub(self.last_frame) as f32;
+        BBox {
+            x_center: self.bbox.x_center + self.velocity.0 * dt,
+            y_center: self.bbox.y_center + self.velocity.1 * dt,
+            ..self.bbox
+        }
+    }
+
+    fn update(&mut self, frame_idx: usize, bbox: BBox, scores: &[LabelScore], vote_decay: f32) {
+        let dt = frame_idx.saturating_sub(self.last_frame).max(1) as f32;
+        let velocity = (
+            (bbox.x_center - self.bbox.x_center) / dt,
+            (bbox.y_center - self.bbox.y_center) / dt,
+        );
+        self.velocity = (
+            VELOCITY_SMOOTHING * velocity.0 + (1.0 - VELOCITY_SMOOTHING) * self.velocity.0,
+            VELOCITY_SMOOTHING * velocity.1 + (1.0 - VELOCITY_SMOOTHING) * self.velocity.1,
+        );
+        self.bbox = bbox;
+
+        let decay = vote_decay.powf(dt);
+        for vote in self.votes.iter_mut() {
+            vote.sum *= decay;
+            vote.weight *= decay;
+        }
+        self.last_frame = frame_idx;
+        self.add_votes(scores);
+    }
+
+    fn add_votes(&mut self, scores: &[LabelScore]) {
+        for score in scores {
+            match self.votes.iter_mut().find(|vote| vote.label == score.label) {
+                Some(vote) => {
+                    vote.sum += score.sum;
+                    vote.weight += score.weight;
+                }
+                None => self.votes.push(score.clone()),
+            }
+        }
+        self.votes
+            .sort_by(|a, b| b.sum.partial_cmp(&a.sum).unwrap_or(Ordering::Equal));
+    }
+}
+
+// SORT/ByteTrack style tracker over the car detections of one camera. Tracks are matched by
+// IoU against their constant-velocity prediction, confident cars first, and collect the label
+// votes of every matched frame, so a car keeps the label of its track while its armor is unreadable.
+#[derive(Debug)]
+pub struct Tracker {
+    options: TrackerOptions,
+    tracks: Vec<Track>,
+    next_id: u64,
+}
+
+impl Tracker {
+    pub fn new(options: TrackerOptions) -> Self {
+        Self {
+            options,
+            tracks: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    // Returns the label votes of the track each car belongs to, or its own votes when the car
+    // is neither matched nor confident enough to start a track.
+    pub fn update(
+        &mut self,
+        frame_idx: usize,
+        cars: &[Detection],
+        scores: Vec<Vec<LabelScore>>,
+    ) -> Vec<Vec<LabelScore>> {
+        let span = span!(Level::TRACE, "Tracker::update");
+        let _enter = span.enter();
+
+        let max_age = self.options.max_age;
+        self.tracks
+            .retain(|track| frame_idx.saturating_sub(track.last_frame) <= max_age);
+
+        let (high, low): (Vec<usize>, Vec<usize>) =
+            (0..cars.len()).partition(|&idx| cars[idx].confidence >= self.options.high_confidence);
+
+        let mut track_of = vec![None; cars.len()];
+        let mut free_tracks: Vec<usize> = (0..self.tracks.len()).collect();
+        for stage in [&high, &low] {
+            for (car_idx, track_idx) in self.match_cars(frame_idx, cars, stage, &free_tracks) {
+                track_of[car_idx] = Some(track_idx);
+                free_tracks.retain(|&idx| idx != track_idx);
+            }
+        }
+
+        let mut results = scores;
+        for (car_idx, car) in cars.iter().enumerate() {
+            let track_idx = match track_of[car_idx] {
+                Some(track_idx) => {
+                    self.tracks[track_idx].update(
+                        frame_idx,
+                        car.bbox,
+                        &results[car_idx],
+                        self.options.vote_decay,
+                    );
+                    track_idx
+                }
+                None if car.confidence >= self.options.high_confidence => {
+                    let mut track = Track {
+                        id: self.next_id,
+                        bbox: car.bbox,
+                        velocity: (0.0, 0.0),
+                        last_frame: frame_idx,
+                        votes: Vec::new(),
+                    };
+                    track.add_votes(&results[car_idx]);
+                    self.next_id += 1;
+                    self.tracks.push(track);
+                    self.tracks.len() - 1
+                }
+                None => {
+                    trace!("Car {car_idx} is not tracked.");
+                    continue;
+                }
+            };
+
+            let track = &self.tracks[track_idx];
+            debug!(
+                "Car {car_idx} belongs to track {} with votes {:?}.",
+                track.id,
+                track
+                    .votes
+                    .iter()
+                    .map(|vote| (vote.label.name_abbr(), vote.sum))
+                    .collect::<Vec<_>>()
+            );
+            results[car_idx] = track.votes.clone();
+        }
+
+        results
+    }
+
+    fn match_cars(
+        &self,
+        frame_idx: usize,
+        cars: &[Detection],
+        car_indices: &[usize],
+        track_indices: &[usize],
+    ) -> Vec<(usize, usize)> {
+        if car_indices.is_empty() || track_indices.is_empty() {
+            return Vec::new();
+        }
+
+        let predictions: Vec<_> = track_indices
+            .iter()
+            .map(|&idx| self.tracks[idx].predict(frame_idx))
+            .collect();
+        let ious: Vec<Vec<f32>> = car_indices
+            .iter()
+            .map(|&car_idx| {
+                predictions
+                    .iter()
+                    .map(|prediction| Yolo::compute_iou(&cars[car_idx].bbox, prediction))
+                    // Two empty boxes, e.g. a prediction shrunk to nothing, give NaN.
+                    .map(|iou| if iou.is_finite() { iou } else { 0.0 })
+                    .collect()
+            })
+            .collect();
+
+        let size = car_indices.len().max(track_indices.len());
+        let cost: Vec<Vec<f64>> = (0..size)
+            .map(|row| {
+                (0..size)
+                    .map(|col| {
+                        let iou = ious
+                            .get(row)
+                            .and_then(|row| row.get(col))
+                            .copied()
+                            .unwrap_or(0.0);
+                        1.0 - iou as f64
+                    })
+                    .collect()
+            })
+            .collect();
+
+        hungarian(&cost)
+            .into_iter()
+            .zip(ious)
+            .enumerate()
+            .filter_map(|(row, (col, car_ious))| {
+                let iou = car_ious.get(col).copied().unwrap_or(0.0);
+                (iou >= self.options.iou_threshold).then(|| (car_indices[row], track_indices[col]))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::radar::detect::LabelTaxonomy;
+
+    fn car(x_center: f32, confidence: f32) -> Detection {
+        Detection {
+            bbox: BBox {
+                x_center,
+                y_center: 50.0,
+                width: 40.0,
+                height: 40.0,
+            },
+            confidence,
+            class_id: 0,
+        }
+    }
+
+    fn scores(taxonomy: &LabelTaxonomy, name: &str, sum: f32) -> Vec<LabelScore> {
+        vec![LabelScore {
+            label: taxonomy.find(name).unwrap(),
+            sum,
+            weight: 1.0,
+        }]
+    }
+
+    fn top_label(scores: &[LabelScore]) -> Option<&str> {
+        scores.first().map(|score| score.label.name_abbr())
+    }
+
+    #[test]
+    fn test_tracker_keeps_label() {
+        let taxonomy = LabelTaxonomy::default();
+        let mut tracker = Tracker::new(TrackerOptions {
+            enabled: true,
+            ..Default::default()
+        });
+
+        // Two cars moving right, 10 pixels per frame.
+        for frame_idx in 0..3 {
+            let shift = frame_idx as f32 * 10.0;
+            let results = tracker.update(
+                frame_idx,
+                &[car(100.0 + shift, 0.9), car(300.0 - shift, 0.9)],
+                vec![scores(&taxonomy, "B3", 0.9), scores(&taxonomy, "R4", 0.8)],
+            );
+            assert_eq!(top_label(&results[0]), Some("B3"));
+            assert_eq!(top_label(&results[1]), Some("R4"));
+        }
+
+        // Armors unreadable or misread, the low confidence car is matched in the second stage.
+        let results = tracker.update(
+            3,
+            &[car(130.0, 0.9), car(270.0, 0.4)],
+            vec![scores(&taxonomy, "B4", 0.5), Vec::new()],
+        );
+        assert_eq!(top_label(&results[0]), Some("B3"));
+        assert_eq!(top_label(&results[1]), Some("R4"));
+
+        // A missed frame is bridged by the motion prediction.
+        let results = tracker.update(5, &[car(150.0, 0.9)], vec![Vec::new()]);
+        assert_eq!(top_label(&results[0]), Some("B3"));
+        assert_eq!(tracker.tracks.len(), 2);
+    }
+
+    #[test]
+    fn test_tracker_drops_old_tracks() {
+        let taxonomy = LabelTaxonomy::default();
+        let mut tracker = Tracker::new(TrackerOptions {
+            enabled: true,
+            max_age: 2,
+            ..Default::default()
+        });
+
+        tracker.update(0, &[car(100.0, 0.9)], vec![scores(&taxonomy, "B3", 0.9)]);
+        let results = tracker.update(3, &[car(100.0, 0.9)], vec![Vec::new()]);
+        assert!(results[0].is_empty());
+
+        // An unmatched low confidence car keeps its own votes without starting a track.
+        let results = tracker.update(4, &[car(300.0, 0.3)], vec![scores(&taxonomy, "R1", 0.5)]);
+        assert_eq!(top_label(&results[0]), Some("R1"));
+        assert_eq!(tracker.tracks.len(), 1);
+    }
+
+    #[test]
+    fn test_tracker_empty_boxes() {
+        let taxonomy = LabelTaxonomy::default();
+        let mut tracker = Tracker::new(TrackerOptions {
+            enabled: true,
+            ..Default::default()
+        });
+        let empty = [Detection {
+            bbox: BBox {
+                x_center: 100.0,
+                y_center: 50.0,
+                width: 0.0,
+                height: 0.0,
+            },
+            confidence: 0.9,
+            class_id: 0,
+        }];
+
+        // Empty boxes have a NaN IoU with each other, they are simply not matched.
+        tracker.update(0, &empty, vec![scores(&taxonomy, "B3", 0.9)]);
+        let results = tracker.update(1, &empty, vec![Vec::new()]);
+        assert!(results[0].is_empty());
+    }
+}
@@ -1,11 +1,12 @@
-use std::fmt::Debug;
+use std::{collections::HashMap, fmt::Debug, fs, path::Path};
 
 use anyhow::{anyhow, Result};
 use image::{imageops::FilterType, DynamicImage, GenericImageView};
-use ndarray::{s, Array2, Array4, ArrayView4, Axis};
+use ndarray::{s, Array2, Array4, ArrayView2, ArrayView4, Axis};
 use ort::{
-    inputs, CUDAExecutionProvider, GraphOptimizationLevel, OpenVINOExecutionProvider, Session,
-    TensorRTExecutionProvider,
+    inputs, CPUExecutionProvider, CUDAExecutionProvider, ExecutionProviderDispatch,
+    GraphOptimizationLevel, OpenVINOExecutionProvider, Session, TensorElementType,
+    TensorRTExecutionProvider, ValueType,
 };
 use tracing::{debug, error, span, trace, warn, Level};
 
@@ -24,34 +25,351 @@ pub struct Detection {
     pub class_id: u32,
 }
 
-#[derive(Debug, Clone, Copy)]
-pub enum Execution {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionProvider {
     TensorRT,
     CUDA,
     OpenVINO,
     CPU,
-    Default,
 }
 
-impl TryFrom<&str> for Execution {
+impl TryFrom<&str> for ExecutionProvider {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> std::result::Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "tensorrt" => Ok(ExecutionProvider::TensorRT),
+            "cuda" => Ok(ExecutionProvider::CUDA),
+            "openvino" => Ok(ExecutionProvider::OpenVINO),
+            "cpu" => Ok(ExecutionProvider::CPU),
+            _ => Err(anyhow!("Failed to convert {value} to execution provider")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProviderOptions {
+    pub provider: ExecutionProvider,
+    pub device_id: Option<i32>,
+    pub device_type: Option<String>,
+    pub fp16: bool,
+    pub cache_dir: Option<String>,
+    // TensorRT only, the timing cache can be shared by engines built for other models.
+    pub timing_cache_dir: Option<String>,
+}
+
+impl From<ExecutionProvider> for ProviderOptions {
+    fn from(provider: ExecutionProvider) -> Self {
+        Self {
+            provider,
+            device_id: None,
+            device_type: None,
+            fp16: false,
+            cache_dir: None,
+            timing_cache_dir: None,
+        }
+    }
+}
+
+impl ProviderOptions {
+    fn build(&self) -> ExecutionProviderDispatch {
+        match self.provider {
+            ExecutionProvider::TensorRT => {
+                let mut provider = TensorRTExecutionProvider::default().with_fp16(self.fp16);
+                if let Some(device_id) = self.device_id {
+                    provider = provider.with_device_id(device_id);
+                }
+                if let Some(cache_dir) = &self.cache_dir {
+                    provider = provider
+                        .with_engine_cache(true)
+                        .with_engine_cache_path(cache_dir);
+                }
+                if let Some(timing_cache_dir) = &self.timing_cache_dir {
+                    provider = provider
+                        .with_timing_cache(true)
+                        .with_timing_cache_path(timing_cache_dir);
+                }
+                provider.build()
+            }
+            ExecutionProvider::CUDA => {
+                let mut provider = CUDAExecutionProvider::default();
+                if let Some(device_id) = self.device_id {
+                    provider = provider.with_device_id(device_id);
+                }
+                provider.build()
+            }
+            ExecutionProvider::OpenVINO => {
+                let mut provider = OpenVINOExecutionProvider::default();
+                if let Some(device_type) = &self.device_type {
+                    provider = provider.with_device_type(device_type);
+                }
+                if let Some(device_id) = self.device_id {
+                    provider = provider.with_device_id(device_id);
+                }
+                if let Some(cache_dir) = &self.cache_dir {
+                    provider = provider.with_cache_dir(cache_dir);
+                }
+                provider.build()
+            }
+            ExecutionProvider::CPU => CPUExecutionProvider::default().build(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptimizationLevel {
+    Disable,
+    Level1,
+    Level2,
+    Level3,
+}
+
+impl TryFrom<&str> for OptimizationLevel {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> std::result::Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "disable" => Ok(OptimizationLevel::Disable),
+            "level1" | "basic" => Ok(OptimizationLevel::Level1),
+            "level2" | "extended" => Ok(OptimizationLevel::Level2),
+            "level3" | "all" => Ok(OptimizationLevel::Level3),
+            _ => Err(anyhow!("Failed to convert {value} to optimization level")),
+        }
+    }
+}
+
+impl From<OptimizationLevel> for GraphOptimizationLevel {
+    fn from(level: OptimizationLevel) -> Self {
+        match level {
+            OptimizationLevel::Disable => GraphOptimizationLevel::Disable,
+            OptimizationLevel::Level1 => GraphOptimizationLevel::Level1,
+            OptimizationLevel::Level2 => GraphOptimizationLevel::Level2,
+            OptimizationLevel::Level3 => GraphOptimizationLevel::Level3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Execution {
+    pub providers: Vec<ProviderOptions>,
+    pub intra_threads: Option<usize>,
+    pub inter_threads: Option<usize>,
+    pub parallel_execution: bool,
+    pub optimization_level: OptimizationLevel,
+    pub optimized_model_dir: Option<String>,
+}
+
+impl Default for Execution {
+    fn default() -> Self {
+        Self {
+            providers: vec![
+                ExecutionProvider::CUDA.into(),
+                ExecutionProvider::OpenVINO.into(),
+                ExecutionProvider::TensorRT.into(),
+            ],
+            intra_threads: None,
+            inter_threads: Some(4),
+            parallel_execution: true,
+            optimization_level: OptimizationLevel::Level3,
+            optimized_model_dir: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResizeMode {
+    Letterbox { pad_color: [u8; 3] },
+    Stretch,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Preprocess {
+    pub resize_mode: ResizeMode,
+    pub filter: FilterType,
+}
+
+impl Default for Preprocess {
+    fn default() -> Self {
+        Self {
+            resize_mode: ResizeMode::Letterbox {
+                pad_color: [114, 114, 114],
+            },
+            filter: FilterType::Triangle,
+        }
+    }
+}
+
+impl Preprocess {
+    pub fn new(resize_mode: &str, pad_color: [u8; 3], filter: &str) -> Result<Self> {
+        let resize_mode = match resize_mode.to_lowercase().as_str() {
+            "letterbox" => ResizeMode::Letterbox { pad_color },
+            "stretch" => ResizeMode::Stretch,
+            _ => return Err(anyhow!("Failed to convert {resize_mode} to resize mode")),
+        };
+        let filter = match filter.to_lowercase().as_str() {
+            "nearest" => FilterType::Nearest,
+            "triangle" | "linear" | "bilinear" => FilterType::Triangle,
+            "catmullrom" | "cubic" | "bicubic" => FilterType::CatmullRom,
+            "gaussian" => FilterType::Gaussian,
+            "lanczos3" | "lanczos" => FilterType::Lanczos3,
+            _ => return Err(anyhow!("Failed to convert {filter} to resize filter")),
+        };
+
+        Ok(Self {
+            resize_mode,
+            filter,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ImageTransform {
+    resized: (u32, u32),
+    pad: (u32, u32),
+    scale: (f32, f32),
+}
+
+impl ImageTransform {
+    fn restore_bbox(&self, bbox: &BBox) -> BBox {
+        let (pad_x, pad_y) = self.pad;
+        let (scale_x, scale_y) = self.scale;
+        BBox {
+            x_center: (bbox.x_center - pad_x as f32) / scale_x,
+            y_center: (bbox.y_center - pad_y as f32) / scale_y,
+            width: bbox.width / scale_x,
+            height: bbox.height / scale_y,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Auto,
+    YoloV5,
+    YoloV8,
+    EndToEnd,
+}
+
+impl TryFrom<&str> for OutputFormat {
     type Error = anyhow::Error;
 
     fn try_from(value: &str) -> std::result::Result<Self, Self::Error> {
         match value.to_lowercase().as_str() {
-            "tensorrt" => Ok(Execution::TensorRT),
-            "cuda" => Ok(Execution::CUDA),
-            "openvino" => Ok(Execution::OpenVINO),
-            "cpu" => Ok(Execution::CPU),
-            "default" => Ok(Execution::Default),
-            _ => Err(anyhow!("Failed to convert {value} to execution")),
+            "auto" => Ok(OutputFormat::Auto),
+            "yolov5" => Ok(OutputFormat::YoloV5),
+            "yolov8" | "yolov11" => Ok(OutputFormat::YoloV8),
+            "yolov10" | "end2end" => Ok(OutputFormat::EndToEnd),
+            _ => Err(anyhow!("Failed to convert {value} to output format")),
+        }
+    }
+}
+
+impl OutputFormat {
+    #[inline]
+    fn layout(self) -> Option<OutputLayout> {
+        match self {
+            OutputFormat::Auto => None,
+            OutputFormat::YoloV5 => Some(OutputLayout::YoloV5),
+            OutputFormat::YoloV8 => Some(OutputLayout::YoloV8),
+            OutputFormat::EndToEnd => Some(OutputLayout::EndToEnd),
+        }
+    }
+}
+
+// Output format the decoders work with, `OutputFormat::Auto` resolves to one from the output
+// shape when the model is built, or per output when its dimensions are dynamic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputLayout {
+    YoloV5,
+    YoloV8,
+    EndToEnd,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum NmsMethod {
+    #[default]
+    Hard,
+    Soft(SoftNmsDecay),
+}
+
+// How Soft-NMS lowers the confidence of a box overlapping a kept one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SoftNmsDecay {
+    Linear,
+    Gaussian { sigma: f32 },
+}
+
+impl NmsMethod {
+    pub fn new(method: &str, sigma: f32) -> Result<Self> {
+        match method.to_lowercase().as_str() {
+            "hard" => Ok(NmsMethod::Hard),
+            "linear" => Ok(NmsMethod::Soft(SoftNmsDecay::Linear)),
+            "gaussian" if sigma > 0.0 => Ok(NmsMethod::Soft(SoftNmsDecay::Gaussian { sigma })),
+            "gaussian" => Err(anyhow!(
+                "Sigma {sigma} of gaussian soft-NMS is not positive"
+            )),
+            _ => Err(anyhow!("Failed to convert {method} to NMS method")),
         }
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelSpec {
+    pub input_name: Option<String>,
+    pub output_name: Option<String>,
+    pub input_size: Option<(u32, u32)>,
+    pub class_names: Option<Vec<String>>,
+    pub output_format: OutputFormat,
+    pub nms_class_agnostic: bool,
+    pub nms_method: NmsMethod,
+    pub class_nms_thresholds: HashMap<u32, f32>,
+}
+
+impl Default for ModelSpec {
+    fn default() -> Self {
+        Self {
+            input_name: None,
+            output_name: None,
+            input_size: None,
+            class_names: None,
+            output_format: OutputFormat::default(),
+            nms_class_agnostic: true,
+            nms_method: NmsMethod::default(),
+            class_nms_thresholds: HashMap::new(),
+        }
+    }
+}
+
+impl TryFrom<&str> for Execution {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> std::result::Result<Self, Self::Error> {
+        let providers = match value.to_lowercase().as_str() {
+            "default" => return Ok(Execution::default()),
+            "cpu" => Vec::new(),
+            _ => vec![ExecutionProvider::try_from(value)
+                .map_err(|e| anyhow!("Failed to convert {value} to execution: {e}"))?
+                .into()],
+        };
+
+        Ok(Self {
+            providers,
+            ..Default::default()
+        })
+    }
+}
+
 pub struct Yolo {
     conf_threshold: f32,
     nms_threshold: f32,
     input_size: (u32, u32),
+    input_name: String,
+    output_name: String,
+    output_layout: Option<OutputLayout>,
+    class_names: Vec<String>,
+    preprocess: Preprocess,
+    spec: ModelSpec,
     onnx_path: String,
     model: Option<Session>,
 }
@@ -62,6 +380,12 @@ impl Debug for Yolo {
             .field("conf_threshold", &self.conf_threshold)
             .field("nms_threshold", &self.nms_threshold)
             .field("input_size", &self.input_size)
+            .field("input_name", &self.input_name)
+            .field("output_name", &self.output_name)
+            .field("output_layout", &self.output_layout)
+            .field("class_names", &self.class_names)
+            .field("preprocess", &self.preprocess)
+            .field("spec", &self.spec)
             .field("onnx_path", &self.onnx_path)
             .field(
                 "model",
@@ -80,18 +404,25 @@ impl Yolo {
         onnx_path: &str,
         conf_threshold: f32,
         nms_threshold: f32,
-        input_size: (u32, u32),
+        preprocess: Preprocess,
+        spec: ModelSpec,
     ) -> Self {
         let span = span!(Level::TRACE, "Yolo::new");
         let _enter = span.enter();
 
-        debug!("Initializing YOLO with config: conf_threshold={}, nms_threshold={}, input_size={:?}, onnx_path={}", 
-              conf_threshold, nms_threshold, input_size, onnx_path);
+        debug!("Initializing YOLO with config: conf_threshold={}, nms_threshold={}, preprocess={:?}, spec={:?}, onnx_path={}", 
+              conf_threshold, nms_threshold, preprocess, spec, onnx_path);
 
         Self {
             conf_threshold,
             nms_threshold,
-            input_size,
+            input_size: spec.input_size.unwrap_or((640, 640)),
+            input_name: spec.input_name.clone().unwrap_or("images".to_string()),
+            output_name: spec.output_name.clone().unwrap_or("output0".to_string()),
+            output_layout: spec.output_format.layout(),
+            class_names: spec.class_names.clone().unwrap_or_default(),
+            preprocess,
+            spec,
             onnx_path: onnx_path.to_string(),
             model: None,
         }
@@ -102,7 +433,7 @@ impl Yolo {
         self.model.is_some()
     }
 
-    pub fn build(&mut self, execution: Execution) -> Result<()> {
+    pub fn build(&mut self, execution: &Execution) -> Result<()> {
         let span = span!(Level::TRACE, "Yolo::build");
         let _enter = span.enter();
 
@@ -115,32 +446,192 @@ impl Yolo {
             "Building the ONNX model from onnx: {} and execution: {:?}",
             self.onnx_path, execution
         );
-        let providers = match execution {
-            Execution::TensorRT => vec![TensorRTExecutionProvider::default().build()],
-            Execution::CUDA => vec![CUDAExecutionProvider::default().build()],
-            Execution::OpenVINO => vec![OpenVINOExecutionProvider::default().build()],
-            Execution::CPU => vec![],
-            _ => vec![
-                CUDAExecutionProvider::default().build(),
-                OpenVINOExecutionProvider::default().build(),
-                TensorRTExecutionProvider::default().build(),
-            ],
-        };
+        let providers: Vec<_> = execution
+            .providers
+            .iter()
+            .map(|provider| provider.build())
+            .collect();
 
-        let session = Session::builder()?
+        let mut builder = Session::builder()?
             .with_execution_providers(providers)?
-            .with_optimization_level(GraphOptimizationLevel::Level3)?
-            .with_parallel_execution(true)?
-            .with_inter_threads(4)?
-            .with_memory_pattern(true)?
-            .commit_from_file(&self.onnx_path)?;
+            .with_optimization_level(execution.optimization_level.into())?
+            .with_parallel_execution(execution.parallel_execution)?
+            .with_memory_pattern(true)?;
+        if let Some(intra_threads) = execution.intra_threads {
+            builder = builder.with_intra_threads(intra_threads)?;
+        }
+        if let Some(inter_threads) = execution.inter_threads {
+            builder = builder.with_inter_threads(inter_threads)?;
+        }
+        if let Some(optimized_model_dir) = &execution.optimized_model_dir {
+            fs::create_dir_all(optimized_model_dir).map_err(|e| {
+                error!("Failed to create directory {optimized_model_dir}: {e}");
+                e
+            })?;
+            let file_stem = Path::new(&self.onnx_path)
+                .file_stem()
+                .ok_or_else(|| anyhow!("Invalid onnx path {}", self.onnx_path))?
+                .to_string_lossy();
+            let optimized_model_path =
+                Path::new(optimized_model_dir).join(format!("{file_stem}.optimized.onnx"));
+            debug!("Saving optimized model to {:?}", optimized_model_path);
+            builder = builder.with_optimized_model_path(optimized_model_path)?;
+        }
 
+        let session = builder.commit_from_file(&self.onnx_path)?;
+
+        self.resolve_model_io(&session).map_err(|e| {
+            error!("Invalid model {}: {e}", self.onnx_path);
+            anyhow!("Invalid model {}: {e}", self.onnx_path)
+        })?;
         self.model = Some(session);
 
         trace!("ONNX model successfully built.");
         Ok(())
     }
 
+    #[inline]
+    pub fn input_size(&self) -> (u32, u32) {
+        self.input_size
+    }
+
+    #[inline]
+    pub fn class_names(&self) -> &[String] {
+        &self.class_names
+    }
+
+    fn resolve_model_io(&mut self, session: &Session) -> Result<()> {
+        let span = span!(Level::TRACE, "Yolo::resolve_model_io");
+        let _enter = span.enter();
+
+        let metadata = session.metadata()?;
+
+        let input = match &self.spec.input_name {
+            Some(name) => session
+                .inputs
+                .iter()
+                .find(|input| &input.name == name)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Input {name} not found in model inputs {:?}",
+                        session
+                            .inputs
+                            .iter()
+                            .map(|input| &input.name)
+                            .collect::<Vec<_>>()
+                    )
+                })?,
+            None => session
+                .inputs
+                .first()
+                .ok_or_else(|| anyhow!("Model has no input"))?,
+        };
+        let input_dimensions = match &input.input_type {
+            ValueType::Tensor {
+                ty: TensorElementType::Float32,
+                dimensions,
+            } => dimensions,
+            ty => {
+                return Err(anyhow!(
+                    "Input {} is {ty:?}, expected a float32 tensor",
+                    input.name
+                ))
+            }
+        };
+        let imgsz = metadata.custom("imgsz")?;
+        self.input_size =
+            resolve_input_size(input_dimensions, self.spec.input_size, imgsz.as_deref())
+                .map_err(|e| anyhow!("Invalid input {}: {e}", input.name))?;
+        self.input_name = input.name.clone();
+
+        let output = match &self.spec.output_name {
+            Some(name) => session
+                .outputs
+                .iter()
+                .find(|output| &output.name == name)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Output {name} not found in model outputs {:?}",
+                        session
+                            .outputs
+                            .iter()
+                            .map(|output| &output.name)
+                            .collect::<Vec<_>>()
+                    )
+                })?,
+            None => session
+                .outputs
+                .first()
+                .ok_or_else(|| anyhow!("Model has no output"))?,
+        };
+        let output_dimensions = match &output.output_type {
+            ValueType::Tensor {
+                ty: TensorElementType::Float32,
+                dimensions,
+            } => dimensions,
+            ty => {
+                return Err(anyhow!(
+                    "Output {} is {ty:?}, expected a float32 tensor",
+                    output.name
+                ))
+            }
+        };
+        if output_dimensions.len() != 3 {
+            return Err(anyhow!(
+                "Output {} has dimensions {:?}, expected [batch, rows, columns]",
+                output.name,
+                output_dimensions
+            ));
+        }
+        self.output_name = output.name.clone();
+
+        self.class_names = match &self.spec.class_names {
+            Some(names) => names.clone(),
+            None => match metadata.custom("names")? {
+                Some(names) => parse_ultralytics_names(&names)
+                    .map_err(|e| anyhow!("Failed to parse class names {names}: {e}"))?,
+                None => {
+                    warn!("No class names found in model {}.", self.onnx_path);
+                    Vec::new()
+                }
+            },
+        };
+
+        if output_dimensions[1] > 0 && output_dimensions[2] > 0 {
+            let shape = (output_dimensions[1] as usize, output_dimensions[2] as usize);
+            let output_layout = match self.output_layout {
+                Some(output_layout) => output_layout,
+                None => detect_output_format(shape, self.class_names.len())?,
+            };
+            self.output_layout = Some(output_layout);
+            check_output_shape(output_layout, shape)
+                .map_err(|e| anyhow!("Invalid output {}: {e}", output.name))?;
+            if let Some(class_num) = class_num_of_output(output_layout, shape) {
+                if !self.class_names.is_empty() && class_num != self.class_names.len() {
+                    return Err(anyhow!(
+                        "Output {} of shape {:?} has {} classes in {:?} format, which does not match {} class names",
+                        output.name,
+                        output_dimensions,
+                        class_num,
+                        output_layout,
+                        self.class_names.len()
+                    ));
+                }
+            }
+        }
+
+        debug!(
+            "Model input: {} {:?}, output: {} {:?} in {:?} format, class names: {:?}",
+            self.input_name,
+            self.input_size,
+            self.output_name,
+            output_dimensions,
+            self.output_layout,
+            self.class_names
+        );
+        Ok(())
+    }
+
     pub fn infer(&self, image: &DynamicImage) -> Result<Vec<Detection>> {
         let span = span!(Level::TRACE, "Yolo::infer");
         let _enter = span.enter();
@@ -162,35 +653,94 @@ impl Yolo {
         })?;
         trace!("Inference completed, raw model output received.");
 
-        let detections = self.process_yolov8_output(model_output, original_dims);
+        let detections = self
+            .process_output(model_output, original_dims)
+            .map_err(|e| {
+                error!("Failed to process output: {e}");
+                anyhow!("Failed to process output: {e}")
+            })?;
         trace!(
-            "Processed YOLOv8 output, number of detections: {}",
+            "Processed output, number of detections: {}",
             detections.len()
         );
 
         Ok(detections)
     }
 
+    fn image_transform(&self, image_size: (u32, u32)) -> ImageTransform {
+        let (input_width, input_height) = self.input_size;
+        let (image_width, image_height) = (image_size.0.max(1), image_size.1.max(1));
+
+        match self.preprocess.resize_mode {
+            ResizeMode::Stretch => ImageTransform {
+                resized: (input_width, input_height),
+                pad: (0, 0),
+                scale: (
+                    input_width as f32 / image_width as f32,
+                    input_height as f32 / image_height as f32,
+                ),
+            },
+            ResizeMode::Letterbox { .. } => {
+                let gain = (input_width as f32 / image_width as f32)
+                    .min(input_height as f32 / image_height as f32);
+                let resized_width =
+                    ((image_width as f32 * gain).round() as u32).clamp(1, input_width.max(1));
+                let resized_height =
+                    ((image_height as f32 * gain).round() as u32).clamp(1, input_height.max(1));
+
+                ImageTransform {
+                    resized: (resized_width, resized_height),
+                    pad: (
+                        input_width.saturating_sub(resized_width) / 2,
+                        input_height.saturating_sub(resized_height) / 2,
+                    ),
+                    scale: (
+                        resized_width as f32 / image_width as f32,
+                        resized_height as f32 / image_height as f32,
+                    ),
+                }
+            }
+        }
+    }
+
     fn preprocess_image(&self, image: &DynamicImage) -> Result<(Array4<f32>, (u32, u32))> {
         let span = span!(Level::TRACE, "Yolo::preprocess_image");
         let _enter = span.enter();
 
         let original_dims = image.dimensions();
+        if original_dims.0 == 0 || original_dims.1 == 0 {
+            return Err(anyhow!("Image of dimensions {:?} is empty", original_dims));
+        }
+
         let (width, height) = self.input_size;
+        let transform = self.image_transform(original_dims);
+        let (resized_width, resized_height) = transform.resized;
+        let (pad_x, pad_y) = transform.pad;
 
-        trace!("Resizing image to {}x{}", width, height);
-        let resized_img = image.resize_exact(width, height, FilterType::Nearest);
+        trace!(
+            "Resizing image to {}x{} with {:?}, padding ({}, {})",
+            resized_width,
+            resized_height,
+            self.preprocess.filter,
+            pad_x,
+            pad_y
+        );
+        let resized_img = image
+            .resize_exact(resized_width, resized_height, self.preprocess.filter)
+            .into_rgb8();
 
         let mut input = Array4::<f32>::zeros((1, 3, height as usize, width as usize));
-        for (i, pixel) in resized_img
-            .as_flat_samples_u8()
-            .unwrap()
-            .as_slice()
-            .chunks(3)
-            .enumerate()
-        {
-            let x = i % width as usize;
-            let y = i / width as usize;
+        if let ResizeMode::Letterbox { pad_color } = self.preprocess.resize_mode {
+            for (channel, value) in pad_color.iter().enumerate() {
+                input
+                    .slice_mut(s![0, channel, .., ..])
+                    .fill(*value as f32 / 255.0);
+            }
+        }
+
+        for (x, y, pixel) in resized_img.enumerate_pixels() {
+            let x = (x + pad_x) as usize;
+            let y = (y + pad_y) as usize;
             input[[0, 0, y, x]] = pixel[0] as f32 / 255.0; // Red channel
             input[[0, 1, y, x]] = pixel[1] as f32 / 255.0; // Green channel
             input[[0, 2, y, x]] = pixel[2] as f32 / 255.0; // Blue channel
@@ -204,12 +754,19 @@ impl Yolo {
         let _enter = span.enter();
 
         if let Some(model) = &self.model {
-            let outputs = model.run(inputs!["images" => input_tensor.view()]?)?;
-            let output = outputs["output0"]
-                .try_extract_tensor::<f32>()?
-                .t()
-                .slice(s![.., .., 0])
-                .into_owned();
+            let outputs = model.run(inputs![self.input_name.as_str() => input_tensor.view()]?)?;
+            let output = outputs
+                .get(self.output_name.as_str())
+                .ok_or_else(|| anyhow!("Output {} not found", self.output_name))?
+                .try_extract_tensor::<f32>()?;
+            if output.ndim() != 3 {
+                return Err(anyhow!(
+                    "Output {} has shape {:?}, expected 3 dimensions",
+                    self.output_name,
+                    output.shape()
+                ));
+            }
+            let output = output.slice(s![0, .., ..]).into_owned();
 
             trace!("Inference completed successfully.");
             Ok(output)
@@ -219,24 +776,57 @@ impl Yolo {
         }
     }
 
-    fn process_yolov8_output(&self, output: Array2<f32>, image_size: (u32, u32)) -> Vec<Detection> {
-        let span = span!(Level::TRACE, "Yolo::process_yolov8_output");
+    fn process_output(
+        &self,
+        output: Array2<f32>,
+        image_size: (u32, u32),
+    ) -> Result<Vec<Detection>> {
+        let span = span!(Level::TRACE, "Yolo::process_output");
         let _enter = span.enter();
 
-        let mut detections = Vec::new();
-        output.axis_iter(Axis(0)).for_each(|row| {
-            // Extract bounding box coordinates
-            let bbox_x_center = row[0];
-            let bbox_y_center = row[1];
-            let bbox_width = row[2];
-            let bbox_height = row[3];
+        // Dynamic output dimensions are only known here.
+        let output_layout = match self.output_layout {
+            Some(output_layout) => output_layout,
+            None => detect_output_format(output.dim(), self.class_names.len())?,
+        };
+        check_output_shape(output_layout, output.dim())?;
+        trace!(
+            "Decoding output of shape {:?} as {:?}",
+            output.dim(),
+            output_layout
+        );
+
+        let transform = self.image_transform(image_size);
+        trace!("Mapping boxes back with {:?}", transform);
+
+        let detections = match output_layout {
+            OutputLayout::YoloV5 => self.decode_yolov5(output.view(), &transform),
+            OutputLayout::YoloV8 => self.decode_yolov8(output.view(), &transform),
+            OutputLayout::EndToEnd => self.decode_end_to_end(output.view(), &transform),
+        };
+        trace!("Output decoded, number of candidates: {}", detections.len());
+
+        if output_layout == OutputLayout::EndToEnd {
+            trace!("End-to-end output, skipped Non-Max Suppression.");
+            return Ok(detections);
+        }
 
+        let final_detections = self.non_max_suppression(detections);
+        trace!("Non-Max Suppression completed.");
+
+        Ok(final_detections)
+    }
+
+    // Rows: [x_center, y_center, width, height, class scores...], columns: anchors
+    fn decode_yolov8(&self, output: ArrayView2<f32>, transform: &ImageTransform) -> Vec<Detection> {
+        let mut detections = Vec::new();
+        output.axis_iter(Axis(1)).for_each(|column| {
             // Find the class with the highest confidence score
-            let (class_id, confidence) = row
+            let Some((class_id, confidence)) = column
                 .iter()
                 .skip(4) // Skip bounding box coordinates
+                .copied()
                 .enumerate()
-                .map(|(index, value)| (index, *value))
                 .reduce(|accum, class_confidence_pair| {
                     if class_confidence_pair.1 > accum.1 {
                         class_confidence_pair
@@ -244,57 +834,187 @@ impl Yolo {
                         accum
                     }
                 })
-                .unwrap(); // Assuming there's always at least one class
+            else {
+                return;
+            };
 
-            if confidence < self.conf_threshold {
+            if confidence.is_nan() || confidence < self.conf_threshold {
                 return;
             }
-            let (input_width, input_height) = self.input_size;
-            let (image_width, image_height) = image_size;
 
             detections.push(Detection {
-                bbox: BBox {
-                    x_center: bbox_x_center / (input_width as f32) * (image_width as f32),
-                    y_center: bbox_y_center / (input_height as f32) * (image_height as f32),
-                    width: bbox_width / (input_width as f32) * (image_width as f32),
-                    height: bbox_height / (input_height as f32) * (image_height as f32),
-                },
+                bbox: transform.restore_bbox(&BBox {
+                    x_center: column[0],
+                    y_center: column[1],
+                    width: column[2],
+                    height: column[3],
+                }),
                 confidence,
                 class_id: class_id as u32,
             });
         });
 
-        trace!("YOLOv8 output processed.");
+        detections
+    }
 
-        let final_detections = self.non_max_suppression(detections);
-        trace!("Non-Max Suppression completed.");
+    // Rows: anchors, columns: [x_center, y_center, width, height, objectness, class scores...]
+    fn decode_yolov5(&self, output: ArrayView2<f32>, transform: &ImageTransform) -> Vec<Detection> {
+        let mut detections = Vec::new();
+        output.axis_iter(Axis(0)).for_each(|row| {
+            let objectness = row[4];
+            if objectness.is_nan() || objectness < self.conf_threshold {
+                return;
+            }
 
-        final_detections
+            let Some((class_id, class_confidence)) = row
+                .iter()
+                .skip(5)
+                .copied()
+                .enumerate()
+                .reduce(|accum, class_confidence_pair| {
+                    if class_confidence_pair.1 > accum.1 {
+                        class_confidence_pair
+                    } else {
+                        accum
+                    }
+                })
+            else {
+                return;
+            };
+
+            let confidence = objectness * class_confidence;
+            if confidence.is_nan() || confidence < self.conf_threshold {
+                return;
+            }
+
+            detections.push(Detection {
+                bbox: transform.restore_bbox(&BBox {
+                    x_center: row[0],
+                    y_center: row[1],
+                    width: row[2],
+                    height: row[3],
+                }),
+                confidence,
+                class_id: class_id as u32,
+            });
+        });
+
+        detections
+    }
+
+    // Rows: detections, columns: [x_min, y_min, x_max, y_max, confidence, class_id]
+    fn decode_end_to_end(
+        &self,
+        output: ArrayView2<f32>,
+        transform: &ImageTransform,
+    ) -> Vec<Detection> {
+        output
+            .axis_iter(Axis(0))
+            .filter(|row| row[4] >= self.conf_threshold && row[5] >= 0.0)
+            .map(|row| Detection {
+                bbox: transform.restore_bbox(&BBox {
+                    x_center: (row[0] + row[2]) / 2.0,
+                    y_center: (row[1] + row[3]) / 2.0,
+                    width: row[2] - row[0],
+                    height: row[3] - row[1],
+                }),
+                confidence: row[4],
+                class_id: row[5].round() as u32,
+            })
+            .collect()
     }
 
     fn non_max_suppression(&self, mut detections: Vec<Detection>) -> Vec<Detection> {
         let span = span!(Level::TRACE, "Yolo::non_max_suppression");
         let _enter = span.enter();
 
+        detections.retain(|detection| !detection.confidence.is_nan());
+        detections.sort_unstable_by(|a, b| b.confidence.total_cmp(&a.confidence));
+
+        let final_detections = match self.spec.nms_method {
+            NmsMethod::Hard => self.hard_nms(detections),
+            NmsMethod::Soft(decay) => self.soft_nms(detections, decay),
+        };
+
+        debug!("Detections: {:?}.", final_detections);
+        final_detections
+    }
+
+    // Detections must be sorted by confidence in descending order. Each candidate is only
+    // compared with the kept boxes of its own group, so the cost is O(candidates * kept).
+    fn hard_nms(&self, detections: Vec<Detection>) -> Vec<Detection> {
+        let mut kept_bboxes: HashMap<u32, Vec<BBox>> = HashMap::new();
         let mut final_detections = Vec::new();
 
-        detections.sort_by(|a, b| b.confidence.partial_cmp(&a.confidence).unwrap());
+        for detection in detections {
+            let threshold = self.nms_threshold_of(detection.class_id);
+            let kept = kept_bboxes
+                .entry(self.nms_group_of(detection.class_id))
+                .or_default();
+            if kept
+                .iter()
+                .all(|bbox| Self::compute_iou(bbox, &detection.bbox) < threshold)
+            {
+                kept.push(detection.bbox);
+                final_detections.push(detection);
+            }
+        }
 
-        while !detections.is_empty() {
-            let best_detection = detections.remove(0);
-            final_detections.push(best_detection.clone());
+        final_detections
+    }
+
+    // Picks the most confident remaining box by a linear scan as the decay reorders the others.
+    fn soft_nms(&self, mut detections: Vec<Detection>, decay: SoftNmsDecay) -> Vec<Detection> {
+        let mut final_detections = Vec::new();
 
-            detections.retain(|detection| {
+        while let Some(best_idx) = detections
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.confidence.total_cmp(&b.confidence))
+            .map(|(idx, _)| idx)
+        {
+            let best_detection = detections.swap_remove(best_idx);
+            let threshold = self.nms_threshold_of(best_detection.class_id);
+            let group = self.nms_group_of(best_detection.class_id);
+
+            detections.retain_mut(|detection| {
+                if self.nms_group_of(detection.class_id) != group {
+                    return true;
+                }
                 let iou = Self::compute_iou(&best_detection.bbox, &detection.bbox);
-                iou < self.nms_threshold
+                detection.confidence *= match decay {
+                    SoftNmsDecay::Linear if iou >= threshold => 1.0 - iou,
+                    SoftNmsDecay::Linear => 1.0,
+                    SoftNmsDecay::Gaussian { sigma } => (-iou * iou / sigma).exp(),
+                };
+                detection.confidence >= self.conf_threshold
             });
+
+            final_detections.push(best_detection);
         }
 
-        debug!("Detections: {:?}.", final_detections);
         final_detections
     }
 
-    fn compute_iou(bbox1: &BBox, bbox2: &BBox) -> f32 {
+    #[inline]
+    fn nms_threshold_of(&self, class_id: u32) -> f32 {
+        self.spec
+            .class_nms_thresholds
+            .get(&class_id)
+            .copied()
+            .unwrap_or(self.nms_threshold)
+    }
+
+    #[inline]
+    fn nms_group_of(&self, class_id: u32) -> u32 {
+        if self.spec.nms_class_agnostic {
+            0
+        } else {
+            class_id
+        }
+    }
+
+    pub(super) fn compute_iou(bbox1: &BBox, bbox2: &BBox) -> f32 {
         let x1_min = bbox1.x_center - bbox1.width / 2.0;
         let y1_min = bbox1.y_center - bbox1.height / 2.0;
         let x1_max = bbox1.x_center + bbox1.width / 2.0;
@@ -319,6 +1039,170 @@ impl Yolo {
     }
 }
 
+fn resolve_input_size(
+    dimensions: &[i64],
+    size_override: Option<(u32, u32)>,
+    imgsz: Option<&str>,
+) -> Result<(u32, u32)> {
+    if dimensions.len() != 4 {
+        return Err(anyhow!(
+            "Dimensions {:?} is not [batch, channel, height, width]",
+            dimensions
+        ));
+    }
+    if dimensions[1] > 0 && dimensions[1] != 3 {
+        return Err(anyhow!("Channel {} is not 3", dimensions[1]));
+    }
+
+    let (height, width) = (dimensions[2], dimensions[3]);
+    if height > 0 && width > 0 {
+        let model_size = (width as u32, height as u32);
+        return match size_override {
+            Some(size) if size != model_size => Err(anyhow!(
+                "Input size {:?} conflicts with fixed model input size {:?}",
+                size,
+                model_size
+            )),
+            _ => Ok(model_size),
+        };
+    }
+
+    if let Some(size) = size_override {
+        return Ok(size);
+    }
+    // Ultralytics records the export size as "[height, width]"
+    if let Some(imgsz) = imgsz {
+        let values = imgsz
+            .trim_matches(|c| c == '[' || c == ']')
+            .split(',')
+            .map(|value| value.trim().parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| anyhow!("Failed to parse imgsz {imgsz}: {e}"))?;
+        match values.as_slice() {
+            [size] => return Ok((*size, *size)),
+            [height, width] => return Ok((*width, *height)),
+            _ => return Err(anyhow!("Failed to parse imgsz {imgsz}")),
+        }
+    }
+
+    Err(anyhow!(
+        "Input size of dynamic dimensions {:?} is unknown, please set it in config",
+        dimensions
+    ))
+}
+
+// Output shape is (rows, columns) of a single batch. Known class numbers are matched first, so a
+// single-class YOLOv5 output of 6 columns is not taken for an end-to-end one. Explicit format in
+// config is preferred when shapes are ambiguous, e.g. a single-class end-to-end model.
+fn detect_output_format(shape: (usize, usize), class_num: usize) -> Result<OutputLayout> {
+    let (rows, columns) = shape;
+    if class_num > 0 {
+        if columns == class_num + 5 && rows != class_num + 5 {
+            return Ok(OutputLayout::YoloV5);
+        }
+        if rows == class_num + 4 && columns != class_num + 4 {
+            return Ok(OutputLayout::YoloV8);
+        }
+    }
+
+    if columns == 6 && rows != 6 {
+        Ok(OutputLayout::EndToEnd)
+    } else if rows > 4 && rows < columns {
+        Ok(OutputLayout::YoloV8)
+    } else if columns > 5 && columns < rows {
+        Ok(OutputLayout::YoloV5)
+    } else {
+        Err(anyhow!(
+            "Failed to detect output format of shape {:?}, please set it in config",
+            shape
+        ))
+    }
+}
+
+// The decoders index the box and score columns, smaller shapes would panic there.
+fn check_output_shape(output_layout: OutputLayout, shape: (usize, usize)) -> Result<()> {
+    let (rows, columns) = shape;
+    let valid = match output_layout {
+        OutputLayout::YoloV5 => columns > 5,
+        OutputLayout::YoloV8 => rows > 4,
+        OutputLayout::EndToEnd => columns >= 6,
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Output shape {:?} is too small for {:?} format",
+            shape,
+            output_layout
+        ))
+    }
+}
+
+fn class_num_of_output(output_layout: OutputLayout, shape: (usize, usize)) -> Option<usize> {
+    let (rows, columns) = shape;
+    match output_layout {
+        OutputLayout::YoloV5 => columns.checked_sub(5),
+        OutputLayout::YoloV8 => rows.checked_sub(4),
+        OutputLayout::EndToEnd => None,
+    }
+}
+
+// Parses Ultralytics metadata like "{0: 'person', 1: 'bicycle'}".
+fn parse_ultralytics_names(names: &str) -> Result<Vec<String>> {
+    let mut entries = Vec::new();
+    let mut chars = names
+        .trim()
+        .trim_start_matches('{')
+        .trim_end_matches('}')
+        .chars()
+        .peekable();
+
+    loop {
+        while chars.peek().is_some_and(|c| c.is_whitespace() || *c == ',') {
+            chars.next();
+        }
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let key: String = chars.by_ref().take_while(|c| *c != ':').collect();
+        let key = key
+            .trim()
+            .parse::<usize>()
+            .map_err(|e| anyhow!("Invalid class id {key}: {e}"))?;
+
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        let quote = chars
+            .next()
+            .filter(|c| *c == '\'' || *c == '"')
+            .ok_or_else(|| anyhow!("Class name of id {key} is not quoted"))?;
+        let mut name = String::new();
+        loop {
+            match chars.next() {
+                Some('\\') => name.extend(chars.next()),
+                Some(c) if c == quote => break,
+                Some(c) => name.push(c),
+                None => return Err(anyhow!("Class name of id {key} is not terminated")),
+            }
+        }
+
+        entries.push((key, name));
+    }
+
+    entries.sort_by_key(|(key, _)| *key);
+    if entries
+        .iter()
+        .enumerate()
+        .any(|(idx, (key, _))| idx != *key)
+    {
+        return Err(anyhow!("Class ids are not contiguous from 0"));
+    }
+
+    Ok(entries.into_iter().map(|(_, name)| name).collect())
+}
+
 #[cfg(test)]
 mod tests {
     use std::{path::PathBuf, str::FromStr};
@@ -328,6 +1212,13 @@ mod tests {
     use assert_approx_eq::assert_approx_eq;
     use image::RgbImage;
 
+    fn input_size_spec(input_size: (u32, u32)) -> ModelSpec {
+        ModelSpec {
+            input_size: Some(input_size),
+            ..Default::default()
+        }
+    }
+
     #[test]
     fn test_preprocess_image() -> Result<()> {
         let img_buffer = RgbImage::from_fn(4, 4, |x, y| {
@@ -338,7 +1229,7 @@ mod tests {
             }
         });
 
-        let yolo = Yolo::new("", 0.0, 0.0, (2, 2));
+        let yolo = Yolo::new("", 0.0, 0.0, Preprocess::default(), input_size_spec((2, 2)));
         let dynamic_image = DynamicImage::ImageRgb8(img_buffer);
         let (processed_array, original_dims) = yolo.preprocess_image(&dynamic_image)?;
 
@@ -358,6 +1249,128 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_preprocess_image_letterbox() -> Result<()> {
+        let img_buffer = RgbImage::from_pixel(4, 2, image::Rgb([255, 0, 0]));
+
+        let yolo = Yolo::new("", 0.0, 0.0, Preprocess::default(), input_size_spec((4, 4)));
+        let dynamic_image = DynamicImage::ImageRgb8(img_buffer);
+        let (processed_array, original_dims) = yolo.preprocess_image(&dynamic_image)?;
+
+        assert_eq!(original_dims, (4, 2));
+        assert_eq!(processed_array.shape(), &[1, 3, 4, 4]);
+
+        for x in 0..4 {
+            assert_approx_eq!(processed_array[[0, 0, 0, x]], 114.0 / 255.0);
+            assert_approx_eq!(processed_array[[0, 0, 1, x]], 1.0);
+            assert_approx_eq!(processed_array[[0, 1, 2, x]], 0.0);
+            assert_approx_eq!(processed_array[[0, 2, 3, x]], 114.0 / 255.0);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_image_transform_restore_bbox() {
+        let bbox = BBox {
+            x_center: 2.0,
+            y_center: 2.0,
+            width: 2.0,
+            height: 1.0,
+        };
+
+        let yolo = Yolo::new("", 0.0, 0.0, Preprocess::default(), input_size_spec((4, 4)));
+        let transform = yolo.image_transform((8, 4));
+        assert_eq!(transform.resized, (4, 2));
+        assert_eq!(transform.pad, (0, 1));
+
+        let original = transform.restore_bbox(&bbox);
+        assert_approx_eq!(original.x_center, 4.0);
+        assert_approx_eq!(original.y_center, 2.0);
+        assert_approx_eq!(original.width, 4.0);
+        assert_approx_eq!(original.height, 2.0);
+
+        let preprocess = Preprocess::new("stretch", [0, 0, 0], "nearest").unwrap();
+        let yolo = Yolo::new("", 0.0, 0.0, preprocess, input_size_spec((4, 4)));
+        let transform = yolo.image_transform((8, 4));
+        assert_eq!(transform.resized, (4, 4));
+        assert_eq!(transform.pad, (0, 0));
+
+        let original = transform.restore_bbox(&bbox);
+        assert_approx_eq!(original.x_center, 4.0);
+        assert_approx_eq!(original.y_center, 2.0);
+        assert_approx_eq!(original.width, 4.0);
+        assert_approx_eq!(original.height, 1.0);
+    }
+
+    #[test]
+    fn test_preprocess_from_str() {
+        assert!(Preprocess::new("Letterbox", [114, 114, 114], "Lanczos3").is_ok());
+        assert!(Preprocess::new("crop", [114, 114, 114], "nearest").is_err());
+        assert!(Preprocess::new("stretch", [114, 114, 114], "box").is_err());
+    }
+
+    #[test]
+    fn test_resolve_input_size() -> Result<()> {
+        assert_eq!(
+            resolve_input_size(&[1, 3, 640, 1280], None, None)?,
+            (1280, 640)
+        );
+        assert_eq!(
+            resolve_input_size(&[1, 3, 640, 640], Some((640, 640)), None)?,
+            (640, 640)
+        );
+        assert!(resolve_input_size(&[1, 3, 640, 640], Some((1280, 1280)), None).is_err());
+
+        assert_eq!(
+            resolve_input_size(&[-1, 3, -1, -1], Some((1280, 1280)), Some("[640, 640]"))?,
+            (1280, 1280)
+        );
+        assert_eq!(
+            resolve_input_size(&[-1, 3, -1, -1], None, Some("[480, 640]"))?,
+            (640, 480)
+        );
+        assert!(resolve_input_size(&[-1, 3, -1, -1], None, None).is_err());
+
+        assert!(resolve_input_size(&[1, 1, 640, 640], None, None).is_err());
+        assert!(resolve_input_size(&[1, 640, 640], None, None).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_ultralytics_names() -> Result<()> {
+        assert_eq!(
+            parse_ultralytics_names("{0: 'person', 1: 'bicycle', 2: \"teddy's bear\"}")?,
+            vec!["person", "bicycle", "teddy's bear"]
+        );
+        assert_eq!(parse_ultralytics_names("{}")?, Vec::<String>::new());
+        assert!(parse_ultralytics_names("{0: 'person', 2: 'car'}").is_err());
+        assert!(parse_ultralytics_names("{0: person}").is_err());
+        assert!(parse_ultralytics_names("{0: 'person").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_execution_from_str() -> Result<()> {
+        assert_eq!(Execution::try_from("default")?, Execution::default());
+        assert!(Execution::try_from("CPU")?.providers.is_empty());
+
+        let execution = Execution::try_from("TensorRT")?;
+        assert_eq!(
+            execution.providers,
+            vec![ProviderOptions::from(ExecutionProvider::TensorRT)]
+        );
+        assert_eq!(execution.optimization_level, OptimizationLevel::Level3);
+        assert_eq!(execution.inter_threads, Some(4));
+
+        assert!(Execution::try_from("rocm").is_err());
+        assert!(OptimizationLevel::try_from("level4").is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn test_iou_no_overlap() {
         let bbox1 = BBox {
@@ -496,7 +1509,13 @@ mod tests {
         let detections = vec![detection1, detection2, detection3];
 
         let nms_threshold = 0.3;
-        let yolo = Yolo::new("", 0.0, nms_threshold, (0, 0));
+        let yolo = Yolo::new(
+            "",
+            0.0,
+            nms_threshold,
+            Preprocess::default(),
+            ModelSpec::default(),
+        );
         let final_detections = yolo.non_max_suppression(detections);
 
         assert_eq!(
@@ -511,6 +1530,164 @@ mod tests {
         );
     }
 
+    fn detection(x_center: f32, confidence: f32, class_id: u32) -> Detection {
+        Detection {
+            bbox: BBox {
+                x_center,
+                y_center: 0.5,
+                width: 0.2,
+                height: 0.2,
+            },
+            confidence,
+            class_id,
+        }
+    }
+
+    #[test]
+    fn test_non_max_suppression_class_aware() {
+        let detections = vec![
+            detection(0.5, 0.9, 0),
+            detection(0.51, 0.8, 1),
+            detection(0.52, 0.7, 0),
+        ];
+
+        let yolo = Yolo::new(
+            "",
+            0.0,
+            0.5,
+            Preprocess::default(),
+            ModelSpec {
+                nms_class_agnostic: false,
+                ..Default::default()
+            },
+        );
+        let final_detections = yolo.non_max_suppression(detections.clone());
+        assert_eq!(final_detections.len(), 2);
+        assert_eq!(final_detections[0].class_id, 0);
+        assert_eq!(final_detections[1].class_id, 1);
+
+        let yolo = Yolo::new("", 0.0, 0.5, Preprocess::default(), ModelSpec::default());
+        let final_detections = yolo.non_max_suppression(detections);
+        assert_eq!(final_detections.len(), 1);
+        assert_eq!(final_detections[0].class_id, 0);
+    }
+
+    #[test]
+    fn test_non_max_suppression_class_thresholds() {
+        let detections = vec![
+            detection(0.5, 0.9, 0),
+            detection(0.55, 0.8, 0),
+            detection(0.5, 0.9, 1),
+            detection(0.55, 0.8, 1),
+        ];
+
+        let yolo = Yolo::new(
+            "",
+            0.0,
+            0.5,
+            Preprocess::default(),
+            ModelSpec {
+                nms_class_agnostic: false,
+                class_nms_thresholds: HashMap::from([(1, 0.9)]),
+                ..Default::default()
+            },
+        );
+        let final_detections = yolo.non_max_suppression(detections);
+        assert_eq!(
+            final_detections
+                .iter()
+                .filter(|det| det.class_id == 0)
+                .count(),
+            1
+        );
+        assert_eq!(
+            final_detections
+                .iter()
+                .filter(|det| det.class_id == 1)
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_non_max_suppression_nan() {
+        let detections = vec![
+            detection(0.5, f32::NAN, 0),
+            detection(0.5, 0.9, 0),
+            detection(0.9, 0.8, 0),
+        ];
+
+        let yolo = Yolo::new("", 0.0, 0.5, Preprocess::default(), ModelSpec::default());
+        let final_detections = yolo.non_max_suppression(detections);
+        assert_eq!(final_detections.len(), 2);
+        assert!(final_detections.iter().all(|det| !det.confidence.is_nan()));
+    }
+
+    #[test]
+    fn test_soft_non_max_suppression() {
+        let detections = vec![detection(0.5, 0.9, 0), detection(0.52, 0.8, 0)];
+
+        let yolo = Yolo::new(
+            "",
+            0.1,
+            0.5,
+            Preprocess::default(),
+            ModelSpec {
+                nms_method: NmsMethod::new("gaussian", 0.5).unwrap(),
+                ..Default::default()
+            },
+        );
+        let final_detections = yolo.non_max_suppression(detections.clone());
+        assert_eq!(final_detections.len(), 2);
+        assert_approx_eq!(final_detections[0].confidence, 0.9);
+        assert!(final_detections[1].confidence < 0.8);
+
+        let yolo = Yolo::new(
+            "",
+            0.5,
+            0.5,
+            Preprocess::default(),
+            ModelSpec {
+                nms_method: NmsMethod::Soft(SoftNmsDecay::Linear),
+                ..Default::default()
+            },
+        );
+        let final_detections = yolo.non_max_suppression(detections);
+        assert_eq!(final_detections.len(), 1);
+
+        assert!(NmsMethod::new("gaussian", 0.0).is_err());
+        assert!(NmsMethod::new("matrix", 0.5).is_err());
+    }
+
+    #[test]
+    fn test_non_max_suppression_many_candidates() {
+        let detections: Vec<_> = (0..10000)
+            .map(|idx| Detection {
+                bbox: BBox {
+                    x_center: (idx % 100) as f32 * 10.0,
+                    y_center: (idx / 100 % 10) as f32 * 10.0,
+                    width: 8.0,
+                    height: 8.0,
+                },
+                confidence: (idx % 97) as f32 / 97.0,
+                class_id: (idx % 3) as u32,
+            })
+            .collect();
+
+        let yolo = Yolo::new(
+            "",
+            0.0,
+            0.5,
+            Preprocess::default(),
+            ModelSpec {
+                nms_class_agnostic: false,
+                ..Default::default()
+            },
+        );
+        let final_detections = yolo.non_max_suppression(detections);
+        assert_eq!(final_detections.len(), 3000);
+    }
+
     fn create_mock_yolov8_output() -> Array2<f32> {
         let num_predictions = 5;
         let num_classes = 80;
@@ -533,14 +1710,24 @@ mod tests {
     }
 
     #[test]
-    fn test_process_yolov8_output() {
+    fn test_process_yolov8_output() -> Result<()> {
         let mock_output = create_mock_yolov8_output();
 
         let conf_threshold = 0.5;
         let nms_threshold = 0.4;
-        let yolo = Yolo::new("", conf_threshold, nms_threshold, (1, 1));
+        let yolo = Yolo::new(
+            "",
+            conf_threshold,
+            nms_threshold,
+            Preprocess::default(),
+            ModelSpec {
+                input_size: Some((1, 1)),
+                output_format: OutputFormat::YoloV8,
+                ..Default::default()
+            },
+        );
 
-        let detections = yolo.process_yolov8_output(mock_output, (1, 1));
+        let detections = yolo.process_output(mock_output.reversed_axes(), (1, 1))?;
 
         assert_eq!(detections.len(), 2, "Incorrect size of detections");
 
@@ -549,12 +1736,162 @@ mod tests {
 
         assert!(detections[0].bbox.x_center > 0.5);
         assert!(detections[1].bbox.x_center < 0.5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_yolov5_output() -> Result<()> {
+        let mut mock_output = Array2::zeros((4, 5 + 3));
+        mock_output
+            .row_mut(0)
+            .assign(&ndarray::arr1(&[0.7, 0.5, 0.2, 0.2, 0.9, 0.1, 0.9, 0.0]));
+        mock_output
+            .row_mut(1)
+            .assign(&ndarray::arr1(&[0.3, 0.6, 0.2, 0.2, 0.9, 0.5, 0.0, 0.0]));
+        mock_output
+            .row_mut(2)
+            .assign(&ndarray::arr1(&[0.5, 0.5, 0.2, 0.2, 0.3, 0.0, 0.0, 1.0]));
+
+        let yolo = Yolo::new(
+            "",
+            0.5,
+            0.4,
+            Preprocess::default(),
+            ModelSpec {
+                input_size: Some((1, 1)),
+                output_format: OutputFormat::YoloV5,
+                ..Default::default()
+            },
+        );
+
+        let detections = yolo.process_output(mock_output, (1, 1))?;
+
+        assert_eq!(detections.len(), 1, "Incorrect size of detections");
+        assert_eq!(detections[0].class_id, 1);
+        assert_approx_eq!(detections[0].confidence, 0.81);
+        assert_approx_eq!(detections[0].bbox.x_center, 0.7);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_single_class_yolov5_output() -> Result<()> {
+        // (anchors, 6) is also the end-to-end shape, one class name makes it YOLOv5.
+        let mut mock_output = Array2::zeros((25200, 6));
+        mock_output
+            .row_mut(0)
+            .assign(&ndarray::arr1(&[0.7, 0.5, 0.2, 0.2, 0.9, 0.8]));
+        mock_output
+            .row_mut(1)
+            .assign(&ndarray::arr1(&[0.3, 0.6, 0.2, 0.2, 0.9, 0.5]));
+        assert_eq!(
+            detect_output_format(mock_output.dim(), 1)?,
+            OutputLayout::YoloV5
+        );
+        assert_eq!(detect_output_format((300, 6), 1)?, OutputLayout::YoloV5);
+
+        let yolo = Yolo::new(
+            "",
+            0.6,
+            0.4,
+            Preprocess::default(),
+            ModelSpec {
+                input_size: Some((1, 1)),
+                class_names: Some(vec!["car".to_string()]),
+                ..Default::default()
+            },
+        );
+
+        let detections = yolo.process_output(mock_output, (1, 1))?;
+
+        assert_eq!(detections.len(), 1);
+        assert_eq!(detections[0].class_id, 0);
+        assert_approx_eq!(detections[0].confidence, 0.72);
+        assert_approx_eq!(detections[0].bbox.x_center, 0.7);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_end_to_end_output() -> Result<()> {
+        let mut mock_output = Array2::zeros((300, 6));
+        mock_output
+            .row_mut(0)
+            .assign(&ndarray::arr1(&[10.0, 20.0, 30.0, 60.0, 0.9, 3.0]));
+        mock_output
+            .row_mut(1)
+            .assign(&ndarray::arr1(&[11.0, 21.0, 31.0, 61.0, 0.8, 2.0]));
+        mock_output
+            .row_mut(2)
+            .assign(&ndarray::arr1(&[11.0, 21.0, 31.0, 61.0, 0.1, 2.0]));
+
+        let yolo = Yolo::new(
+            "",
+            0.5,
+            0.4,
+            Preprocess::default(),
+            input_size_spec((100, 100)),
+        );
+
+        let detections = yolo.process_output(mock_output, (200, 200))?;
+
+        assert_eq!(
+            detections.len(),
+            2,
+            "NMS should be skipped for end-to-end output"
+        );
+        assert_eq!(detections[0].class_id, 3);
+        assert_approx_eq!(detections[0].bbox.x_center, 40.0);
+        assert_approx_eq!(detections[0].bbox.y_center, 80.0);
+        assert_approx_eq!(detections[0].bbox.width, 40.0);
+        assert_approx_eq!(detections[0].bbox.height, 80.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_detect_output_format() -> Result<()> {
+        assert_eq!(detect_output_format((84, 8400), 80)?, OutputLayout::YoloV8);
+        assert_eq!(detect_output_format((84, 8400), 0)?, OutputLayout::YoloV8);
+        assert_eq!(detect_output_format((25200, 85), 80)?, OutputLayout::YoloV5);
+        assert_eq!(detect_output_format((25200, 85), 0)?, OutputLayout::YoloV5);
+        assert_eq!(detect_output_format((300, 6), 12)?, OutputLayout::EndToEnd);
+        assert_eq!(detect_output_format((6, 8400), 2)?, OutputLayout::YoloV8);
+        assert!(detect_output_format((4, 4), 0).is_err());
+
+        assert_eq!(
+            class_num_of_output(OutputLayout::YoloV8, (84, 8400)),
+            Some(80)
+        );
+        assert_eq!(
+            class_num_of_output(OutputLayout::YoloV5, (25200, 85)),
+            Some(80)
+        );
+        assert_eq!(class_num_of_output(OutputLayout::EndToEnd, (300, 6)), None);
+
+        assert!(check_output_shape(OutputLayout::EndToEnd, (300, 6)).is_ok());
+        assert!(check_output_shape(OutputLayout::EndToEnd, (8400, 5)).is_err());
+        assert!(check_output_shape(OutputLayout::YoloV5, (25200, 5)).is_err());
+        assert!(check_output_shape(OutputLayout::YoloV8, (4, 8400)).is_err());
+
+        Ok(())
     }
 
     #[test]
     fn test_yolo() -> Result<()> {
-        let mut yolo = Yolo::new("assets/test/yolov8n.onnx", 0.5, 0.75, (640, 640));
-        yolo.build(Execution::CPU)?;
+        let mut yolo = Yolo::new(
+            "assets/test/yolov8n.onnx",
+            0.5,
+            0.75,
+            Preprocess::default(),
+            ModelSpec::default(),
+        );
+        yolo.build(&Execution::try_from("CPU")?)?;
+
+        assert_eq!(yolo.input_size(), (640, 640));
+        assert_eq!(yolo.class_names().len(), 80);
+        assert_eq!(yolo.class_names()[0], "person");
 
         let img = image::open(PathBuf::from_str("assets/test/zidane.jpg")?)?;
         let detections = yolo.infer(&img)?;
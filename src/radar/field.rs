@@ -0,0 +1,274 @@
+use anyhow::{anyhow, Result};
+use nalgebra::{Matrix3, Matrix4, Point3, Vector3};
+use tracing::{info, span, warn, Level};
+
+use super::locate::RobotLocation;
+use crate::config::FieldConfig;
+
+// Field outputs are in metres, the lidar frame works in mm.
+const FIELD_UNIT_SCALE: f32 = 0.001;
+// Landmark fits with a larger RMS residual in mm are reported.
+const MAX_LANDMARK_RESIDUAL: f32 = 100.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFrame {
+    // Lidar coordinates in mm.
+    #[default]
+    Lidar,
+    // RoboMaster field coordinates in metres, origin at the red base corner.
+    Field,
+}
+
+impl TryFrom<&str> for OutputFrame {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> std::result::Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "lidar" => Ok(OutputFrame::Lidar),
+            "field" => Ok(OutputFrame::Field),
+            _ => Err(anyhow!("Failed to convert {value} to output frame")),
+        }
+    }
+}
+
+// Rigid transform from lidar to field coordinates, both in mm.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FieldTransform {
+    lidar_to_field: Matrix4<f32>,
+}
+
+impl FieldTransform {
+    pub fn new(lidar_to_field: Matrix4<f32>) -> Result<Self> {
+        let rotation = lidar_to_field.fixed_view::<3, 3>(0, 0);
+        let orthogonality = (rotation.transpose() * rotation - Matrix3::identity())
+            .abs()
+            .max();
+        if orthogonality > 1e-3 || lidar_to_field.row(3) != Matrix4::identity().row(3) {
+            return Err(anyhow!(
+                "Lidar to field transform {lidar_to_field} is not a rigid transform"
+            ));
+        }
+
+        Ok(Self { lidar_to_field })
+    }
+
+    // The matrix is used when given, otherwise the transform is fitted to the landmarks.
+    pub fn from_config(config: &FieldConfig) -> Result<Option<Self>> {
+        if let Some(matrix) = config.lidar_to_field {
+            return Self::new(Matrix4::from_row_slice(&matrix)).map(Some);
+        }
+        if config.landmarks.is_empty() {
+            return Ok(None);
+        }
+
+        let (lidar_points, field_points): (Vec<_>, Vec<_>) = config
+            .landmarks
+            .iter()
+            .map(|landmark| (Point3::from(landmark.lidar), Point3::from(landmark.field)))
+            .unzip();
+        Self::from_landmarks(&lidar_points, &field_points).map(Some)
+    }
+
+    // Least-squares rigid fit (Kabsch) of lidar landmarks onto their field coordinates.
+    pub fn from_landmarks(
+        lidar_points: &[Point3<f32>],
+        field_points: &[Point3<f32>],
+    ) -> Result<Self> {
+        let span = span!(Level::TRACE, "FieldTransform::from_landmarks");
+        let _enter = span.enter();
+
+        if lidar_points.len() != field_points.len() || lidar_points.len() < 3 {
+            return Err(anyhow!(
+                "Expected at least 3 landmark pairs, got {} lidar and {} field points",
+                lidar_points.len(),
+                field_points.len()
+            ));
+        }
+
+        let centroid = |points: &[Point3<f32>]| {
+            points
+                .iter()
+                .fold(Vector3::zeros(), |sum, point| sum + point.coords)
+                / points.len() as f32
+        };
+        let (lidar_centroid, field_centroid) = (centroid(lidar_points), centroid(field_points));
+        let covariance = lidar_points.iter().zip(field_points).fold(
+            Matrix3::zeros(),
+            |sum, (lidar_point, field_point)| {
+                sum + (lidar_point.coords - lidar_centroid)
+                    * (field_point.coords - field_centroid).transpose()
+            },
+        );
+
+        let svd = covariance.svd(true, true);
+        let (Some(u), Some(v_t)) = (svd.u, svd.v_t) else {
+            return Err(anyhow!("Failed to decompose landmark covariance"));
+        };
+        let mut sorted_values: Vec<_> = svd.singular_values.iter().copied().collect();
+        sorted_values.sort_by(|a, b| b.total_cmp(a));
+        if sorted_values[1] < sorted_values[0] * 1e-6 {
+            return Err(anyhow!("Landmarks are collinear"));
+        }
+
+        // Flips the axis of the smallest singular value when the fit would be a reflection.
+        let mut correction = Matrix3::identity();
+        if (v_t.transpose() * u.transpose()).determinant() < 0.0 {
+            let (min_idx, _) = svd
+                .singular_values
+                .iter()
+                .enumerate()
+                .min_by(|a, b| a.1.total_cmp(b.1))
+                .unwrap();
+            correction[(min_idx, min_idx)] = -1.0;
+        }
+        let rotation = v_t.transpose() * correction * u.transpose();
+        let translation = field_centroid - rotation * lidar_centroid;
+
+        let mut lidar_to_field = Matrix4::identity();
+        lidar_to_field
+            .fixed_view_mut::<3, 3>(0, 0)
+            .copy_from(&rotation);
+        lidar_to_field
+            .fixed_view_mut::<3, 1>(0, 3)
+            .copy_from(&translation);
+        let transform = Self::new(lidar_to_field)?;
+
+        let residual = (lidar_points
+            .iter()
+            .zip(field_points)
+            .map(|(lidar_point, field_point)| {
+                (transform.to_field(lidar_point) - field_point).norm_squared()
+            })
+            .sum::<f32>()
+            / lidar_points.len() as f32)
+            .sqrt();
+        if residual > MAX_LANDMARK_RESIDUAL {
+            warn!("Landmark fit residual {residual} mm is large, check the landmarks.");
+        }
+        info!("Fitted lidar to field transform {lidar_to_field} with residual {residual} mm");
+
+        Ok(transform)
+    }
+
+    #[inline]
+    pub fn matrix(&self) -> &Matrix4<f32> {
+        &self.lidar_to_field
+    }
+
+    #[inline]
+    pub fn to_field(&self, point: &Point3<f32>) -> Point3<f32> {
+        self.lidar_to_field.transform_point(point)
+    }
+
+    // Rotation of the lidar x axis around the field z axis.
+    #[inline]
+    pub fn yaw(&self) -> f32 {
+        self.lidar_to_field[(1, 0)].atan2(self.lidar_to_field[(0, 0)])
+    }
+
+    // Point in field coordinates and metres.
+    #[inline]
+    pub fn to_field_output(&self, point: &Point3<f32>) -> Point3<f32> {
+        self.to_field(point) * FIELD_UNIT_SCALE
+    }
+
+    // Extrinsic taking output points, field coordinates in metres, to the camera frame of
+    // `lidar_to_camera` with its translation in metres.
+    pub fn output_to_camera(&self, lidar_to_camera: &Matrix4<f32>) -> Matrix4<f32> {
+        let Some(field_to_lidar) = self.lidar_to_field.try_inverse() else {
+            unreachable!("Rigid transforms are invertible");
+        };
+        let mut output_to_camera = lidar_to_camera * field_to_lidar;
+        output_to_camera
+            .fixed_view_mut::<3, 1>(0, 3)
+            .scale_mut(FIELD_UNIT_SCALE);
+        output_to_camera
+    }
+
+    // Location in field coordinates and metres, the yaw turned into the field frame.
+    pub fn location_to_field_output(&self, location: &RobotLocation) -> RobotLocation {
+        let yaw = location.yaw + self.yaw();
+        RobotLocation {
+            center: self.to_field_output(&location.center),
+            width: location.width * FIELD_UNIT_SCALE,
+            height: location.height * FIELD_UNIT_SCALE,
+            depth: location.depth * FIELD_UNIT_SCALE,
+            yaw: yaw.sin().atan2(yaw.cos()),
+            ..location.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use assert_approx_eq::assert_approx_eq;
+
+    use super::*;
+
+    #[test]
+    fn test_field_transform_from_landmarks() {
+        // Lidar at field (1000, 7500, 2500) facing the field y axis.
+        let truth = FieldTransform::new(
+            Matrix4::new_translation(&Vector3::new(1000.0, 7500.0, 2500.0))
+                * Matrix4::from_axis_angle(&Vector3::z_axis(), FRAC_PI_2),
+        )
+        .unwrap();
+        let lidar_points = [
+            Point3::new(5000.0, 0.0, -2500.0),
+            Point3::new(8000.0, 3000.0, -2000.0),
+            Point3::new(12000.0, -4000.0, -2500.0),
+            Point3::new(3000.0, 2000.0, -1000.0),
+        ];
+        let field_points: Vec<_> = lidar_points
+            .iter()
+            .map(|point| truth.to_field(point))
+            .collect();
+
+        let transform = FieldTransform::from_landmarks(&lidar_points, &field_points).unwrap();
+        for (actual, expected) in transform.matrix().iter().zip(truth.matrix().iter()) {
+            assert!((actual - expected).abs() < 1e-2);
+        }
+        assert_approx_eq!(transform.yaw(), FRAC_PI_2, 1e-4);
+
+        let location = RobotLocation {
+            center: Point3::new(5000.0, 0.0, -2500.0),
+            width: 500.0,
+            height: 400.0,
+            depth: 600.0,
+            yaw: 0.2,
+            points: 10,
+            roi_points: 20,
+            monocular: false,
+        };
+        let field_location = transform.location_to_field_output(&location);
+        assert_approx_eq!(field_location.center.x, 1.0, 1e-3);
+        assert_approx_eq!(field_location.center.y, 12.5, 1e-3);
+        assert_approx_eq!(field_location.center.z, 0.0, 1e-3);
+        assert_approx_eq!(field_location.width, 0.5);
+        assert_approx_eq!(field_location.yaw, 0.2 + FRAC_PI_2, 1e-4);
+
+        assert!(FieldTransform::from_landmarks(&lidar_points[..2], &field_points[..2]).is_err());
+    }
+
+    #[test]
+    fn test_output_to_camera() {
+        let transform = FieldTransform::new(
+            Matrix4::new_translation(&Vector3::new(1000.0, 7500.0, 2500.0))
+                * Matrix4::from_axis_angle(&Vector3::z_axis(), FRAC_PI_2),
+        )
+        .unwrap();
+        let lidar_to_camera = Matrix4::new_translation(&Vector3::new(100.0, -50.0, 20.0))
+            * Matrix4::from_axis_angle(&Vector3::x_axis(), -FRAC_PI_2);
+
+        // A saved field point in metres lands where its lidar point lands, in metres.
+        let output_to_camera = transform.output_to_camera(&lidar_to_camera);
+        let lidar_point = Point3::new(5000.0, 1200.0, -2000.0);
+        let camera_point = lidar_to_camera.transform_point(&lidar_point);
+        let output_point = transform.to_field_output(&lidar_point);
+        let actual = output_to_camera.transform_point(&output_point);
+        assert!((actual - camera_point * FIELD_UNIT_SCALE).norm() < 1e-5);
+        assert_approx_eq!(output_to_camera[(3, 3)], 1.0);
+    }
+}
@@ -0,0 +1,361 @@
+use nalgebra::Point3;
+use tracing::{debug, span, trace, warn, Level};
+
+use super::{detect::RobotLabel, locate::RobotLocation};
+use crate::config::FusionConfig;
+
+#[derive(Debug, Clone, Copy)]
+pub struct FusionOptions {
+    // Same-label locations closer than this are merged into one robot.
+    pub merge_distance: f32,
+    // Differently labelled locations closer than this are taken as the same robot.
+    pub duplicate_distance: f32,
+}
+
+impl Default for FusionOptions {
+    fn default() -> Self {
+        Self {
+            merge_distance: 600.0,
+            duplicate_distance: 300.0,
+        }
+    }
+}
+
+impl FusionOptions {
+    pub fn from_config(config: &FusionConfig) -> Self {
+        Self {
+            merge_distance: config.merge_distance,
+            duplicate_distance: config.duplicate_distance,
+        }
+    }
+}
+
+// A located robot seen by one camera.
+#[derive(Debug, Clone)]
+pub struct Observation {
+    pub camera_idx: usize,
+    pub label: RobotLabel,
+    pub confidence: f32,
+    pub location: RobotLocation,
+}
+
+impl Observation {
+    // Confident detections backed by many lidar points count more.
+    #[inline]
+    fn weight(&self) -> f32 {
+        self.confidence.max(f32::EPSILON) * self.location.points.max(1) as f32
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FusedRobot {
+    pub label: RobotLabel,
+    pub location: RobotLocation,
+    pub confidence: f32,
+    pub cameras: Vec<usize>,
+    weight: f32,
+}
+
+#[derive(Debug, Clone)]
+pub enum Conflict {
+    // The same label located at places further apart than the merge distance, the heavier one is kept.
+    SplitLabel {
+        label: RobotLabel,
+        distance: f32,
+        cameras: (Vec<usize>, Vec<usize>),
+    },
+    // One robot given different labels, the heavier label is kept.
+    DuplicateRobot {
+        labels: (RobotLabel, RobotLabel),
+        distance: f32,
+        cameras: (Vec<usize>, Vec<usize>),
+    },
+}
+
+#[derive(Debug, Default)]
+pub struct Fusion {
+    pub robots: Vec<FusedRobot>,
+    pub conflicts: Vec<Conflict>,
+}
+
+// Merges the locations of all cameras in one frame. Observations of a label are clustered by
+// distance and averaged with their weights, unknown robots close to a labelled one are dropped.
+pub fn fuse_observations(observations: Vec<Observation>, options: &FusionOptions) -> Fusion {
+    let span = span!(Level::TRACE, "fuse_observations");
+    let _enter = span.enter();
+
+    let mut groups: Vec<(RobotLabel, Vec<Observation>)> = Vec::new();
+    for observation in observations {
+        match groups
+            .iter_mut()
+            .find(|(label, _)| *label == observation.label)
+        {
+            Some((_, group)) => group.push(observation),
+            None => groups.push((observation.label.clone(), vec![observation])),
+        }
+    }
+
+    let mut fusion = Fusion::default();
+    let mut candidates = Vec::new();
+    for (label, group) in groups {
+        let mut clusters = cluster_observations(group, options.merge_distance);
+        clusters.sort_by(|a, b| b.weight.total_cmp(&a.weight));
+        trace!("Label {label} forms {} clusters.", clusters.len());
+
+        if label.is_unknown() {
+            candidates.extend(clusters);
+            continue;
+        }
+
+        let mut clusters = clusters.into_iter();
+        let Some(kept) = clusters.next() else {
+            continue;
+        };
+        for dropped in clusters {
+            fusion.conflicts.push(Conflict::SplitLabel {
+                label: label.clone(),
+                distance: distance(&kept.location.center, &dropped.location.center),
+                cameras: (kept.cameras.clone(), dropped.cameras),
+            });
+        }
+        candidates.push(kept);
+    }
+
+    // Labelled robots first, so unknown robots never replace them.
+    candidates.sort_by(|a, b| {
+        a.label
+            .is_unknown()
+            .cmp(&b.label.is_unknown())
+            .then(b.weight.total_cmp(&a.weight))
+    });
+    for candidate in candidates {
+        let duplicate = fusion.robots.iter().find(|robot| {
+            distance(&robot.location.center, &candidate.location.center)
+                < options.duplicate_distance
+        });
+        match duplicate {
+            Some(robot) => {
+                if !candidate.label.is_unknown() {
+                    fusion.conflicts.push(Conflict::DuplicateRobot {
+                        labels: (robot.label.clone(), candidate.label.clone()),
+                        distance: distance(&robot.location.center, &candidate.location.center),
+                        cameras: (robot.cameras.clone(), candidate.cameras),
+                    });
+                } else {
+                    trace!(
+                        "Unknown robot at {:?} is {} already.",
+                        candidate.location.center,
+                        robot.label
+                    );
+                }
+            }
+            None => fusion.robots.push(candidate),
+        }
+    }
+
+    for conflict in fusion.conflicts.iter() {
+        warn!("Fusion conflict: {:?}", conflict);
+    }
+    debug!("Fused robots: {:?}", fusion.robots);
+    fusion
+}
+
+fn cluster_observations(mut group: Vec<Observation>, merge_distance: f32) -> Vec<FusedRobot> {
+    group.sort_by(|a, b| b.weight().total_cmp(&a.weight()));
+
+    let mut clusters: Vec<Vec<Observation>> = Vec::new();
+    let mut centers: Vec<Point3<f32>> = Vec::new();
+    for observation in group {
+        let nearest = centers
+            .iter()
+            .enumerate()
+            .map(|(idx, center)| (idx, distance(center, &observation.location.center)))
+            .filter(|&(_, distance)| distance < merge_distance)
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+        match nearest {
+            Some((idx, _)) => {
+                clusters[idx].push(observation);
+                centers[idx] = merge(&clusters[idx]).location.center;
+            }
+            None => {
+                centers.push(observation.location.center);
+                clusters.push(vec![observation]);
+            }
+        }
+    }
+
+    clusters.iter().map(|cluster| merge(cluster)).collect()
+}
+
+fn merge(cluster: &[Observation]) -> FusedRobot {
+    // Monocular boxes are guesses, they only count when no camera has lidar points on the robot.
+    let mut observations: Vec<_> = cluster
+        .iter()
+        .filter(|observation| !observation.location.monocular)
+        .collect();
+    if observations.is_empty() {
+        observations = cluster.iter().collect();
+    }
+
+    let weight: f32 = observations
+        .iter()
+        .map(|observation| observation.weight())
+        .sum();
+    let average = |value: fn(&RobotLocation) -> f32| {
+        observations
+            .iter()
+            .map(|observation| value(&observation.location) * observation.weight())
+            .sum::<f32>()
+            / weight
+    };
+
+    let mut cameras: Vec<_> = observations
+        .iter()
+        .map(|observation| observation.camera_idx)
+        .collect();
+    cameras.sort_unstable();
+    cameras.dedup();
+
+    FusedRobot {
+        label: observations[0].label.clone(),
+        location: RobotLocation {
+            center: Point3::new(
+                average(|location| location.center.x),
+                average(|location| location.center.y),
+                average(|location| location.center.z),
+            ),
+            width: average(|location| location.width),
+            height: average(|location| location.height),
+            depth: average(|location| location.depth),
+            // Headings do not average well, the heaviest observation gives it.
+            yaw: observations[0].location.yaw,
+            points: observations
+                .iter()
+                .map(|observation| observation.location.points)
+                .sum(),
+            roi_points: observations
+                .iter()
+                .map(|observation| observation.location.roi_points)
+                .sum(),
+            monocular: observations
+                .iter()
+                .all(|observation| observation.location.monocular),
+        },
+        confidence: observations
+            .iter()
+            .map(|observation| observation.confidence)
+            .fold(0.0, f32::max),
+        cameras,
+        weight,
+    }
+}
+
+#[inline]
+fn distance(a: &Point3<f32>, b: &Point3<f32>) -> f32 {
+    (a - b).norm()
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_approx_eq::assert_approx_eq;
+
+    use super::*;
+    use crate::radar::detect::LabelTaxonomy;
+
+    fn observation(
+        camera_idx: usize,
+        label: &str,
+        confidence: f32,
+        center: [f32; 3],
+        points: usize,
+        taxonomy: &LabelTaxonomy,
+    ) -> Observation {
+        Observation {
+            camera_idx,
+            label: taxonomy.find(label).unwrap(),
+            confidence,
+            location: RobotLocation {
+                center: Point3::from(center),
+                width: 500.0,
+                height: 400.0,
+                depth: 500.0,
+                yaw: 0.0,
+                points,
+                roi_points: points * 2,
+                monocular: false,
+            },
+        }
+    }
+
+    #[test]
+    fn test_fuse_same_label() {
+        let taxonomy = LabelTaxonomy::default().with_unknown_labels().unwrap();
+        let observations = vec![
+            observation(0, "B3", 0.9, [5000.0, 0.0, 0.0], 30, &taxonomy),
+            observation(1, "B3", 0.9, [5200.0, 0.0, 0.0], 10, &taxonomy),
+            observation(2, "BU", 0.5, [5100.0, 100.0, 0.0], 10, &taxonomy),
+            observation(2, "BU", 0.5, [9000.0, 0.0, 0.0], 10, &taxonomy),
+        ];
+
+        let fusion = fuse_observations(observations, &FusionOptions::default());
+        assert!(fusion.conflicts.is_empty());
+        assert_eq!(fusion.robots.len(), 2);
+
+        let robot = &fusion.robots[0];
+        assert_eq!(robot.label.name_abbr(), "B3");
+        assert_approx_eq!(robot.location.center.x, 5050.0);
+        assert_eq!(robot.location.points, 40);
+        assert_eq!(robot.cameras, vec![0, 1]);
+        assert_eq!(fusion.robots[1].label.name_abbr(), "BU");
+        assert_approx_eq!(fusion.robots[1].location.center.x, 9000.0);
+    }
+
+    #[test]
+    fn test_fuse_skips_monocular_with_lidar() {
+        let taxonomy = LabelTaxonomy::default();
+        let mut monocular = observation(1, "B3", 0.9, [5400.0, 0.0, 0.0], 0, &taxonomy);
+        monocular.location.monocular = true;
+        let observations = vec![
+            observation(0, "B3", 0.5, [5000.0, 0.0, 0.0], 30, &taxonomy),
+            monocular.clone(),
+        ];
+
+        let fusion = fuse_observations(observations, &FusionOptions::default());
+        assert_eq!(fusion.robots.len(), 1);
+        let robot = &fusion.robots[0];
+        assert_approx_eq!(robot.location.center.x, 5000.0);
+        assert_approx_eq!(robot.confidence, 0.5);
+        assert_eq!(robot.cameras, vec![0]);
+        assert!(!robot.location.monocular);
+
+        let fusion = fuse_observations(vec![monocular], &FusionOptions::default());
+        assert!(fusion.robots[0].location.monocular);
+    }
+
+    #[test]
+    fn test_fuse_conflicts() {
+        let taxonomy = LabelTaxonomy::default();
+        let observations = vec![
+            observation(0, "R1", 0.9, [5000.0, 0.0, 0.0], 30, &taxonomy),
+            observation(1, "R1", 0.8, [8000.0, 0.0, 0.0], 30, &taxonomy),
+            observation(1, "R2", 0.6, [5100.0, 0.0, 0.0], 20, &taxonomy),
+        ];
+
+        let fusion = fuse_observations(observations, &FusionOptions::default());
+        assert_eq!(fusion.robots.len(), 1);
+        assert_eq!(fusion.robots[0].label.name_abbr(), "R1");
+        assert_approx_eq!(fusion.robots[0].location.center.x, 5000.0);
+
+        assert_eq!(fusion.conflicts.len(), 2);
+        assert!(matches!(
+            &fusion.conflicts[0],
+            Conflict::SplitLabel { label, distance, .. }
+                if label.name_abbr() == "R1" && (*distance - 3000.0).abs() < 1e-3
+        ));
+        assert!(matches!(
+            &fusion.conflicts[1],
+            Conflict::DuplicateRobot { labels, cameras, .. }
+                if labels.1.name_abbr() == "R2" && cameras.1 == vec![1]
+        ));
+    }
+}
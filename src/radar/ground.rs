@@ -0,0 +1,169 @@
+use nalgebra::{Matrix3, Point3, Vector3};
+use tracing::{debug, span, trace, Level};
+
+use super::locate::GroundPlane;
+use crate::config::GroundConfig;
+
+// Points sampled from a frame for RANSAC, the field ground has plenty of points.
+const MAX_SAMPLES: usize = 20000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GroundOptions {
+    // Estimates the ground plane from the first point cloud when none is configured.
+    pub estimate: bool,
+    pub iterations: usize,
+    // Points closer to a candidate plane than this are its inliers.
+    pub inlier_distance: f32,
+    // Largest angle in degrees between the plane normal and the lidar z axis.
+    pub max_tilt: f32,
+    // Points lower than this above the ground are dropped before clustering, 0 keeps them.
+    pub removal_height: f32,
+}
+
+impl Default for GroundOptions {
+    fn default() -> Self {
+        Self {
+            estimate: false,
+            iterations: 500,
+            inlier_distance: 50.0,
+            max_tilt: 30.0,
+            removal_height: 0.0,
+        }
+    }
+}
+
+impl GroundOptions {
+    pub fn from_config(config: &GroundConfig) -> Self {
+        Self {
+            estimate: config.estimate,
+            iterations: config.iterations,
+            inlier_distance: config.inlier_distance,
+            max_tilt: config.max_tilt,
+            removal_height: config.removal_height,
+        }
+    }
+}
+
+// RANSAC over planes through three sampled points, the plane with the most inliers among those
+// tilted less than `max_tilt` is refined by a least-squares fit of its inliers.
+pub fn estimate_ground_plane(
+    points: &[Point3<f32>],
+    options: &GroundOptions,
+) -> Option<GroundPlane> {
+    let span = span!(Level::TRACE, "estimate_ground_plane");
+    let _enter = span.enter();
+
+    let step = points.len().div_ceil(MAX_SAMPLES).max(1);
+    let samples: Vec<_> = points.iter().step_by(step).copied().collect();
+    if samples.len() < 3 {
+        return None;
+    }
+
+    let min_normal_z = options.max_tilt.to_radians().cos();
+    let mut random = Lcg::new(0x5eed);
+    let mut best: Option<(usize, Vector3<f32>, f32)> = None;
+    for _ in 0..options.iterations {
+        let [a, b, c] = [0; 3].map(|_| samples[random.next_index(samples.len())]);
+        let normal = (b - a).cross(&(c - a));
+        let norm = normal.norm();
+        if !norm.is_normal() {
+            continue;
+        }
+        let normal = normal / norm * normal.z.signum();
+        if normal.z < min_normal_z {
+            continue;
+        }
+
+        let offset = -normal.dot(&a.coords);
+        let inliers = samples
+            .iter()
+            .filter(|point| (normal.dot(&point.coords) + offset).abs() < options.inlier_distance)
+            .count();
+        if best.is_none_or(|(best_inliers, _, _)| inliers > best_inliers) {
+            trace!("Plane {normal:?}, {offset} has {inliers} inliers.");
+            best = Some((inliers, normal, offset));
+        }
+    }
+
+    let (_, normal, offset) = best?;
+    let inliers: Vec<_> = samples
+        .iter()
+        .filter(|point| (normal.dot(&point.coords) + offset).abs() < options.inlier_distance)
+        .collect();
+    let centroid = inliers
+        .iter()
+        .fold(Vector3::zeros(), |sum, point| sum + point.coords)
+        / inliers.len() as f32;
+    let covariance = inliers.iter().fold(Matrix3::zeros(), |sum, point| {
+        let diff = point.coords - centroid;
+        sum + diff * diff.transpose()
+    });
+    let eigen = covariance.symmetric_eigen();
+    let (min_idx, _) = eigen
+        .eigenvalues
+        .iter()
+        .enumerate()
+        .min_by(|a, b| a.1.total_cmp(b.1))?;
+    let normal: Vector3<f32> = eigen.eigenvectors.column(min_idx).into();
+    let normal = normal * normal.z.signum();
+
+    debug!(
+        "Ground plane {normal:?} through {centroid:?} with {} of {} sampled points.",
+        inliers.len(),
+        samples.len()
+    );
+    GroundPlane::new([normal.x, normal.y, normal.z, -normal.dot(&centroid)]).ok()
+}
+
+// Fixed-seed generator, the estimate is the same on every run.
+struct Lcg(u64);
+
+impl Lcg {
+    fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    fn next_index(&mut self, len: usize) -> usize {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        ((self.0 >> 33) % len as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_approx_eq::assert_approx_eq;
+
+    use super::*;
+
+    #[test]
+    fn test_estimate_ground_plane() {
+        // Slightly tilted ground 1500 below the lidar, a wall and a robot on it.
+        let ground = (0..60).flat_map(|x| {
+            (-20..20).map(move |y| {
+                let (x, y) = (1000.0 + x as f32 * 200.0, y as f32 * 200.0);
+                Point3::new(x, y, -1500.0 + x * 0.05 + ((x + y) % 7.0))
+            })
+        });
+        let wall = (-20..20).flat_map(|y| {
+            (0..20).map(move |z| Point3::new(13000.0, y as f32 * 200.0, z as f32 * 100.0 - 1000.0))
+        });
+        let robot = (0..200).map(|idx| {
+            Point3::new(
+                5000.0 + (idx % 10) as f32 * 50.0,
+                (idx / 10) as f32 * 25.0,
+                -1200.0 + (idx % 5) as f32 * 100.0,
+            )
+        });
+        let points: Vec<_> = ground.chain(wall).chain(robot).collect();
+
+        let ground_plane = estimate_ground_plane(&points, &GroundOptions::default()).unwrap();
+        assert!((ground_plane.height_at(5000.0, 0.0) - -1247.0).abs() < 10.0);
+        assert!((ground_plane.height_at(9000.0, 2000.0) - -1047.0).abs() < 10.0);
+        assert_approx_eq!(ground_plane.normal.norm(), 1.0);
+
+        assert!(estimate_ground_plane(&points[..2], &GroundOptions::default()).is_none());
+    }
+}
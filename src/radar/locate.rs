@@ -1,21 +1,148 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    f32::consts::{FRAC_PI_2, FRAC_PI_4},
+};
 
 use anyhow::{anyhow, Result};
 use dbscan::Classification;
 use image::{ImageBuffer, Luma};
 use nalgebra::{Matrix3, Matrix4, Point3, Vector3, Vector4};
 use rayon::prelude::*;
-use tracing::{debug, error, span, trace, Level};
+use tracing::{debug, error, info, span, trace, warn, Level};
 
-use super::detect::{BBox, RobotDetection};
+use super::{
+    background::{
+        calibration_hash, BackgroundMap, BackgroundMeta, BackgroundModel, BackgroundOptions,
+        DepthHistogram, VoxelGrid,
+    },
+    cluster::{dbscan, ClusterMethod},
+    detect::{BBox, RobotDetection},
+    ground::{estimate_ground_plane, GroundOptions},
+    validity::ValidityVolume,
+};
 use crate::config::{LocatorConfig, RadarInstanceConfig};
 
-#[derive(Debug)]
+const YAW_SEARCH_STEPS: usize = 180;
+
+#[derive(Debug, Clone)]
 pub struct RobotLocation {
     pub center: Point3<f32>,
     pub width: f32,
     pub height: f32,
     pub depth: f32,
+    // Heading around the lidar z axis in radians, `depth` is the extent along it.
+    pub yaw: f32,
+    // Number of lidar points the location is computed from.
+    pub points: usize,
+    // Number of foreground lidar points inside the detection box.
+    pub roi_points: usize,
+    // Located from the camera ray and the ground plane without lidar points, a low quality guess.
+    pub monocular: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum YawMethod {
+    // Axis-aligned boxes, yaw is always 0.
+    None,
+    // Principal axis of the points in the ground plane.
+    Pca,
+    // Heading of the minimum-area rectangle around the points in the ground plane.
+    #[default]
+    MinArea,
+}
+
+impl TryFrom<&str> for YawMethod {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> std::result::Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "none" => Ok(YawMethod::None),
+            "pca" => Ok(YawMethod::Pca),
+            "min_area" => Ok(YawMethod::MinArea),
+            _ => Err(anyhow!("Failed to convert {value} to yaw method")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BoxFitting {
+    // Boxes span the clustered points.
+    #[default]
+    Extent,
+    // Boxes take the size prior of the label kind, anchored to the visible surface.
+    Prior,
+}
+
+impl TryFrom<&str> for BoxFitting {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> std::result::Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "extent" => Ok(BoxFitting::Extent),
+            "prior" => Ok(BoxFitting::Prior),
+            _ => Err(anyhow!("Failed to convert {value} to box fitting")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LocateMode {
+    // Points are rasterized into a depth map and compared with the background depth map.
+    #[default]
+    DepthMap,
+    // Raw points projecting into the detection are selected, the background is a voxel grid.
+    Frustum,
+}
+
+impl TryFrom<&str> for LocateMode {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> std::result::Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "depth_map" => Ok(LocateMode::DepthMap),
+            "frustum" => Ok(LocateMode::Frustum),
+            _ => Err(anyhow!("Failed to convert {value} to locate mode")),
+        }
+    }
+}
+
+// Robot dimensions, `length` along the heading.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SizePrior {
+    pub length: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+// Plane `normal . p + offset = 0` in lidar coordinates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GroundPlane {
+    pub normal: Vector3<f32>,
+    pub offset: f32,
+}
+
+impl GroundPlane {
+    pub fn new(coefficients: [f32; 4]) -> Result<Self> {
+        let [a, b, c, d] = coefficients;
+        let normal = Vector3::new(a, b, c);
+        let norm = normal.norm();
+        if !norm.is_normal() || (c / norm).abs() < 0.5 {
+            return Err(anyhow!(
+                "Ground plane normal {normal:?} is far from the z axis"
+            ));
+        }
+
+        Ok(Self {
+            normal: normal / norm,
+            offset: d / norm,
+        })
+    }
+
+    // Height of the plane below (x, y).
+    #[inline]
+    pub fn height_at(&self, x: f32, y: f32) -> f32 {
+        -(self.normal.x * x + self.normal.y * y + self.offset) / self.normal.z
+    }
 }
 
 pub struct Locator {
@@ -28,11 +155,27 @@ pub struct Locator {
     scale_factor: f32,
     zoom_factor: f32,
     roi_offset: (u32, u32),
+    validity: ValidityVolume,
     background_depth_map: ImageBuffer<Luma<f32>, Vec<f32>>,
     lidar_to_camera_transform: Matrix4<f32>,
     camera_to_lidar_transform: Matrix4<f32>,
     camera_intrinsic: Matrix3<f32>,
     camera_intrinsic_inverse: Matrix3<f32>,
+    locate_mode: LocateMode,
+    cluster_method: ClusterMethod,
+    yaw_method: YawMethod,
+    box_fitting: BoxFitting,
+    size_priors: HashMap<String, SizePrior>,
+    ground_plane: Option<GroundPlane>,
+    ground_options: GroundOptions,
+    ground_estimated: bool,
+    monocular_fallback: bool,
+    background_options: BackgroundOptions,
+    background_histogram: Option<DepthHistogram>,
+    background_stale: bool,
+    // A loaded background is used as is and not updated any more.
+    background_loaded: bool,
+    voxel_grid: Option<VoxelGrid>,
 }
 
 impl Locator {
@@ -95,11 +238,26 @@ impl Locator {
             scale_factor,
             zoom_factor,
             roi_offset,
+            validity: ValidityVolume::default(),
             background_depth_map: ImageBuffer::default(),
             lidar_to_camera_transform,
             camera_to_lidar_transform,
             camera_intrinsic,
             camera_intrinsic_inverse,
+            locate_mode: LocateMode::default(),
+            cluster_method: ClusterMethod::default(),
+            yaw_method: YawMethod::default(),
+            box_fitting: BoxFitting::default(),
+            size_priors: HashMap::new(),
+            ground_plane: None,
+            ground_options: GroundOptions::default(),
+            ground_estimated: false,
+            monocular_fallback: false,
+            background_options: BackgroundOptions::default(),
+            background_histogram: None,
+            background_stale: false,
+            background_loaded: false,
+            voxel_grid: None,
         };
         Ok(locator)
     }
@@ -118,6 +276,34 @@ impl Locator {
             detections.len()
         );
 
+        if self.locate_mode == LocateMode::Frustum {
+            let Some(voxel_grid) = self.voxel_grid.as_ref() else {
+                return Err(anyhow!("Background voxel grid is empty"));
+            };
+            if detections.is_empty() {
+                trace!("Detections is empty, no need for location.");
+                return Ok(Vec::new());
+            }
+
+            let foreground_points: Vec<_> = points
+                .iter()
+                .filter(|lidar_point| {
+                    self.is_valid_point(lidar_point)
+                        && self.is_above_ground(lidar_point)
+                        && !voxel_grid.is_background(lidar_point)
+                })
+                .map(|lidar_point| (*lidar_point, self.lidar_to_image(lidar_point)))
+                .filter(|(_, image_point)| image_point.z > 0.0)
+                .collect();
+            debug!("Foreground point number: {}", foreground_points.len());
+
+            let robot_locations = self.search_in_frustums(detections, &foreground_points);
+            let robot_locations = self.fall_back_to_monocular(detections, robot_locations);
+            debug!("Robot locations found: {:?}", robot_locations);
+            return Ok(robot_locations);
+        }
+
+        self.refresh_background();
         if self.background_depth_map.is_empty() {
             return Err(anyhow!("Background depth map is empty"));
         }
@@ -129,10 +315,12 @@ impl Locator {
 
         trace!("Getting robot depth map");
         let robot_depth_map = self.get_robot_depth_map(points);
+        let foreground_pixels = self.get_foreground_pixels(points);
 
         trace!("Searching for robot location");
-        let bboxes: Vec<_> = detections.iter().map(|det| det.bbox()).collect();
-        let robot_locations = self.search_for_location(&bboxes, &robot_depth_map);
+        let robot_locations =
+            self.search_for_location(detections, &robot_depth_map, &foreground_pixels);
+        let robot_locations = self.fall_back_to_monocular(detections, robot_locations);
 
         debug!("Robot locations found: {:?}", robot_locations);
         Ok(robot_locations)
@@ -142,7 +330,18 @@ impl Locator {
         locator_config: &LocatorConfig,
         instance_config: &RadarInstanceConfig,
     ) -> Result<Self> {
-        Locator::new(
+        if !(locator_config.cluster_epsilon.is_finite() && locator_config.cluster_epsilon > 0.0) {
+            error!(
+                "Invalid cluster epsilon {}, expected a positive distance",
+                locator_config.cluster_epsilon
+            );
+            return Err(anyhow!(
+                "Invalid cluster epsilon {}, expected a positive distance",
+                locator_config.cluster_epsilon
+            ));
+        }
+
+        let mut locator = Locator::new(
             locator_config.cluster_epsilon,
             locator_config.cluster_min_points,
             locator_config.min_valid_distance,
@@ -154,7 +353,182 @@ impl Locator {
             instance_config.roi_offset.into(),
             Matrix4::from_row_slice(&instance_config.lidar_to_camera),
             Matrix3::from_row_slice(&instance_config.intrinsic),
-        )
+        )?;
+        locator.set_locate_mode(LocateMode::try_from(locator_config.mode.as_str()).map_err(
+            |e| {
+                error!("Invalid locate mode {}: {e}", locator_config.mode);
+                anyhow!("Invalid locate mode {}: {e}", locator_config.mode)
+            },
+        )?);
+        // Only depth map backgrounds are saved, a frustum locator would silently rebuild its own.
+        if locator.locate_mode == LocateMode::Frustum
+            && locator_config.background.load_dir.is_some()
+        {
+            error!("Background load_dir is only supported by the depth map locate mode");
+            return Err(anyhow!(
+                "Background load_dir is only supported by the depth map locate mode"
+            ));
+        }
+        locator.set_cluster_method(
+            ClusterMethod::try_from(locator_config.cluster_method.as_str()).map_err(|e| {
+                error!(
+                    "Invalid cluster method {}: {e}",
+                    locator_config.cluster_method
+                );
+                anyhow!(
+                    "Invalid cluster method {}: {e}",
+                    locator_config.cluster_method
+                )
+            })?,
+        );
+        locator.set_yaw_method(
+            YawMethod::try_from(locator_config.yaw_method.as_str()).map_err(|e| {
+                error!("Invalid yaw method {}: {e}", locator_config.yaw_method);
+                anyhow!("Invalid yaw method {}: {e}", locator_config.yaw_method)
+            })?,
+        );
+        locator.set_box_fitting(
+            BoxFitting::try_from(locator_config.box_fitting.as_str()).map_err(|e| {
+                error!("Invalid box fitting {}: {e}", locator_config.box_fitting);
+                anyhow!("Invalid box fitting {}: {e}", locator_config.box_fitting)
+            })?,
+            locator_config
+                .size_priors
+                .iter()
+                .map(|(kind, &[length, width, height])| {
+                    (
+                        kind.to_lowercase(),
+                        SizePrior {
+                            length,
+                            width,
+                            height,
+                        },
+                    )
+                })
+                .collect(),
+        );
+        locator.set_background_options(
+            BackgroundOptions::from_config(&locator_config.background).map_err(|e| {
+                error!(
+                    "Invalid background config {:?}: {e}",
+                    locator_config.background
+                );
+                anyhow!(
+                    "Invalid background config {:?}: {e}",
+                    locator_config.background
+                )
+            })?,
+        );
+        locator.set_ground_options(GroundOptions::from_config(&locator_config.ground));
+        locator.set_monocular_fallback(locator_config.monocular_fallback);
+        if let Some(coefficients) = locator_config.ground_plane {
+            locator.set_ground_plane(Some(GroundPlane::new(coefficients).map_err(|e| {
+                error!("Invalid ground plane {:?}: {e}", coefficients);
+                anyhow!("Invalid ground plane {:?}: {e}", coefficients)
+            })?));
+        }
+
+        Ok(locator)
+    }
+
+    // Points outside the volume are ignored by the background, the ground estimate and locating.
+    pub fn set_validity(&mut self, validity: ValidityVolume) {
+        self.validity = validity;
+    }
+
+    pub fn set_locate_mode(&mut self, locate_mode: LocateMode) {
+        self.locate_mode = locate_mode;
+    }
+
+    #[inline]
+    pub fn locate_mode(&self) -> LocateMode {
+        self.locate_mode
+    }
+
+    pub fn set_cluster_method(&mut self, cluster_method: ClusterMethod) {
+        self.cluster_method = cluster_method;
+    }
+
+    pub fn set_yaw_method(&mut self, yaw_method: YawMethod) {
+        self.yaw_method = yaw_method;
+    }
+
+    // Priors are looked up by label kind, then by "default"; kinds without a prior fall back
+    // to the extent of the points.
+    pub fn set_box_fitting(
+        &mut self,
+        box_fitting: BoxFitting,
+        size_priors: HashMap<String, SizePrior>,
+    ) {
+        self.box_fitting = box_fitting;
+        self.size_priors = size_priors;
+    }
+
+    pub fn set_ground_plane(&mut self, ground_plane: Option<GroundPlane>) {
+        self.ground_plane = ground_plane;
+    }
+
+    pub fn set_ground_options(&mut self, ground_options: GroundOptions) {
+        self.ground_options = ground_options;
+    }
+
+    // Detections without lidar points are located from the camera ray and the ground plane.
+    pub fn set_monocular_fallback(&mut self, monocular_fallback: bool) {
+        self.monocular_fallback = monocular_fallback;
+    }
+
+    #[inline]
+    pub fn ground_plane(&self) -> Option<&GroundPlane> {
+        self.ground_plane.as_ref()
+    }
+
+    // Estimates the ground plane from the first point cloud given, when estimation is enabled
+    // and no plane is set. Later calls do nothing.
+    pub fn update_ground_plane(&mut self, points: &[Point3<f32>]) {
+        let span = span!(Level::TRACE, "Locator::update_ground_plane");
+        let _enter = span.enter();
+
+        if !self.ground_options.estimate || self.ground_estimated || self.ground_plane.is_some() {
+            return;
+        }
+        self.ground_estimated = true;
+
+        let points: Vec<_> = points
+            .iter()
+            .filter(|point| self.is_valid_point(point))
+            .copied()
+            .collect();
+        match estimate_ground_plane(&points, &self.ground_options) {
+            Some(ground_plane) => {
+                info!(
+                    "Estimated ground plane {:?}, {}",
+                    ground_plane.normal, ground_plane.offset
+                );
+                self.ground_plane = Some(ground_plane);
+            }
+            None => warn!(
+                "Failed to estimate ground plane from {} points.",
+                points.len()
+            ),
+        }
+    }
+
+    // Points of the floor are not part of robots, they are dropped when removal is enabled.
+    #[inline]
+    fn is_above_ground(&self, lidar_point: &Point3<f32>) -> bool {
+        match &self.ground_plane {
+            Some(ground_plane) if self.ground_options.removal_height > 0.0 => {
+                lidar_point.z - ground_plane.height_at(lidar_point.x, lidar_point.y)
+                    >= self.ground_options.removal_height
+            }
+            _ => true,
+        }
+    }
+
+    fn size_prior(&self, kind: &str) -> Option<&SizePrior> {
+        self.size_priors
+            .get(&kind.to_lowercase())
+            .or_else(|| self.size_priors.get("default"))
     }
 
     pub fn update_background_depth_map(
@@ -162,33 +536,106 @@ impl Locator {
         points: &[Point3<f32>],
         image_size: (u32, u32),
     ) -> Result<()> {
+        self.update_background(points, image_size, &[])
+    }
+
+    // Adds a frame to the background model. Points projecting into `exclusions`, boxes in image
+    // coordinates, are left out when the options exclude detections.
+    pub fn update_background(
+        &mut self,
+        points: &[Point3<f32>],
+        image_size: (u32, u32),
+        exclusions: &[BBox],
+    ) -> Result<()> {
+        let span = span!(Level::TRACE, "Locator::update_background");
+        let _enter = span.enter();
+
         let (image_width, image_height) = image_size;
 
         let depth_map_width = (image_width as f32 * self.zoom_factor) as u32;
         let depth_map_height = (image_height as f32 * self.zoom_factor) as u32;
 
-        if self.background_depth_map.is_empty() {
-            debug!("Background depth map is empty, initializing.");
-            self.background_depth_map = ImageBuffer::new(depth_map_width, depth_map_height);
-        } else if self.background_depth_map.dimensions() != (depth_map_width, depth_map_height) {
-            return Err(anyhow!(
-                "Dimensions of image is not equal to background depth image"
-            ));
+        if self.locate_mode == LocateMode::Frustum {
+            let exclusions = self.exclusion_ranges(exclusions);
+            let background_points: Vec<_> = points
+                .iter()
+                .filter(|lidar_point| self.is_valid_point(lidar_point))
+                .filter(|lidar_point| {
+                    let image_point = self.lidar_to_image(lidar_point);
+                    image_point.z <= 0.0
+                        || !exclusions.iter().any(|&(x_min, x_max, y_min, y_max)| {
+                            image_point.x >= x_min
+                                && image_point.x <= x_max
+                                && image_point.y >= y_min
+                                && image_point.y <= y_max
+                        })
+                })
+                .collect();
+            debug!(
+                "Size of filtered points in updating background voxel grid: {}",
+                background_points.len()
+            );
+
+            let options = self.background_options;
+            self.voxel_grid
+                .get_or_insert_with(|| VoxelGrid::new(options.voxel_size, options.voxel_occupancy))
+                .add_frame(background_points);
+            return Ok(());
+        }
+
+        if self.background_loaded {
+            if self.background_depth_map.dimensions() != (depth_map_width, depth_map_height) {
+                return Err(anyhow!(
+                    "Dimensions of image is not equal to loaded background depth image"
+                ));
+            }
+            trace!("Background depth map is loaded, skipping update.");
+            return Ok(());
         }
 
+        match self.background_options.model {
+            BackgroundModel::Max => {
+                if self.background_depth_map.is_empty() {
+                    debug!("Background depth map is empty, initializing.");
+                    self.background_depth_map = ImageBuffer::new(depth_map_width, depth_map_height);
+                } else if self.background_depth_map.dimensions()
+                    != (depth_map_width, depth_map_height)
+                {
+                    return Err(anyhow!(
+                        "Dimensions of image is not equal to background depth image"
+                    ));
+                }
+            }
+            BackgroundModel::Percentile { bin_size, .. } => match &self.background_histogram {
+                None => {
+                    debug!("Background histogram is empty, initializing.");
+                    self.background_histogram = Some(DepthHistogram::new(
+                        depth_map_width,
+                        depth_map_height,
+                        bin_size,
+                    ));
+                }
+                Some(histogram)
+                    if histogram.dimensions() != (depth_map_width, depth_map_height) =>
+                {
+                    return Err(anyhow!(
+                        "Dimensions of image is not equal to background histogram"
+                    ));
+                }
+                Some(_) => {}
+            },
+        }
+
+        let exclusions = self.exclusion_ranges(exclusions);
+
         let image_points_filtered: Vec<_> = points
             .iter()
             .filter_map(|lidar_point| {
-                if !lidar_point.is_empty()
-                    && lidar_point.x.is_normal()
-                    && lidar_point.y.is_normal()
-                    && lidar_point.z.is_normal()
-                    && lidar_point.x < self.max_valid_distance
-                    && lidar_point.x > self.min_valid_distance
-                {
+                if self.is_valid_point(lidar_point) {
                     let image_point = self.lidar_to_image(lidar_point);
                     let (u, v) = (image_point.x.round() as i32, image_point.y.round() as i32);
-                    if u >= 0
+                    if image_point.z > 0.0
+                        && u >= 0
                         && (u as u32) < depth_map_width
                         && v >= 0
                         && (v as u32) < depth_map_height
@@ -201,6 +648,12 @@ impl Locator {
                     None
                 }
             })
+            .filter(|&(u, v, _)| {
+                let (u, v) = (u as f32, v as f32);
+                !exclusions.iter().any(|&(x_min, x_max, y_min, y_max)| {
+                    u >= x_min && u <= x_max && v >= y_min && v <= y_max
+                })
+            })
             .collect();
 
         debug!(
@@ -208,17 +661,172 @@ impl Locator {
             image_points_filtered.len()
         );
 
-        image_points_filtered.into_iter().for_each(|point| {
-            let (u, v, depth) = point;
-            let background_depth = self.background_depth_map.get_pixel_mut(u, v);
-            if depth > background_depth.0[0] {
-                background_depth.0[0] = depth;
+        match self.background_histogram.as_mut() {
+            Some(histogram) => {
+                image_points_filtered
+                    .into_iter()
+                    .for_each(|(u, v, depth)| histogram.add(u, v, depth));
+                self.background_stale = true;
             }
-        });
+            None => image_points_filtered.into_iter().for_each(|point| {
+                let (u, v, depth) = point;
+                let background_depth = self.background_depth_map.get_pixel_mut(u, v);
+                if depth > background_depth.0[0] {
+                    background_depth.0[0] = depth;
+                }
+            }),
+        }
 
         Ok(())
     }
 
+    pub fn set_background_options(&mut self, background_options: BackgroundOptions) {
+        self.background_options = background_options;
+        self.background_histogram = None;
+        self.background_stale = false;
+        self.voxel_grid = None;
+    }
+
+    // Detection boxes in depth map coordinates, empty when detections are not excluded.
+    fn exclusion_ranges(&self, exclusions: &[BBox]) -> Vec<(f32, f32, f32, f32)> {
+        if !self.background_options.exclude_detections {
+            return Vec::new();
+        }
+
+        exclusions
+            .iter()
+            .map(|bbox| {
+                let x_offset = self.roi_offset.0 as f32;
+                let y_offset = self.roi_offset.1 as f32;
+                (
+                    (bbox.x_center - bbox.width * 0.5) * self.zoom_factor + x_offset,
+                    (bbox.x_center + bbox.width * 0.5) * self.zoom_factor + x_offset,
+                    (bbox.y_center - bbox.height * 0.5) * self.zoom_factor + y_offset,
+                    (bbox.y_center + bbox.height * 0.5) * self.zoom_factor + y_offset,
+                )
+            })
+            .collect()
+    }
+
+    #[inline]
+    pub fn background_options(&self) -> &BackgroundOptions {
+        &self.background_options
+    }
+
+    #[inline]
+    pub fn has_background(&self) -> bool {
+        !self.background_depth_map.is_empty()
+            || self.background_histogram.is_some()
+            || self.voxel_grid.is_some()
+    }
+
+    // Hash of the calibration the background depth map depends on.
+    pub fn calibration_hash(&self) -> String {
+        let values: Vec<f32> = self
+            .camera_intrinsic
+            .iter()
+            .chain(self.lidar_to_camera_transform.iter())
+            .copied()
+            .chain([
+                self.roi_offset.0 as f32,
+                self.roi_offset.1 as f32,
+                self.zoom_factor,
+            ])
+            .collect();
+        calibration_hash(&values)
+    }
+
+    // Uses a saved background instead of building one, it is rejected if the calibration changed.
+    pub fn load_background(&mut self, background: BackgroundMap) -> Result<()> {
+        let span = span!(Level::TRACE, "Locator::load_background");
+        let _enter = span.enter();
+
+        let hash = self.calibration_hash();
+        if background.meta.calibration_hash != hash {
+            return Err(anyhow!(
+                "Background {} was built with calibration {}, current calibration is {}",
+                background.meta.name,
+                background.meta.calibration_hash,
+                hash
+            ));
+        }
+
+        debug!(
+            "Loaded background {} of {:?}.",
+            background.meta.name,
+            background.depth_map.dimensions()
+        );
+        self.background_depth_map = background.depth_map;
+        self.background_histogram = None;
+        self.background_stale = false;
+        self.background_loaded = true;
+        Ok(())
+    }
+
+    // The current background for saving, `None` before any frame was added.
+    pub fn background(&mut self, name: &str) -> Option<BackgroundMap> {
+        self.refresh_background();
+        if self.background_depth_map.is_empty() {
+            return None;
+        }
+
+        let (width, height) = self.background_depth_map.dimensions();
+        Some(BackgroundMap {
+            meta: BackgroundMeta {
+                name: name.to_string(),
+                calibration_hash: self.calibration_hash(),
+                width,
+                height,
+                model: format!("{:?}", self.background_options.model),
+            },
+            depth_map: self.background_depth_map.clone(),
+        })
+    }
+
+    // Rebuilds the depth map from the histogram after it changed.
+    fn refresh_background(&mut self) {
+        if let (
+            true,
+            Some(histogram),
+            BackgroundModel::Percentile {
+                percentile,
+                min_samples,
+                ..
+            },
+        ) = (
+            self.background_stale,
+            &self.background_histogram,
+            self.background_options.model,
+        ) {
+            self.background_depth_map = histogram.percentile_map(percentile, min_samples);
+            self.background_stale = false;
+        }
+    }
+
+    #[inline]
+    fn is_valid_point(&self, lidar_point: &Point3<f32>) -> bool {
+        !lidar_point.is_empty()
+            && lidar_point.x.is_normal()
+            && lidar_point.y.is_normal()
+            && lidar_point.z.is_normal()
+            && (self.validity.has_range_limits()
+                || (lidar_point.x < self.max_valid_distance
+                    && lidar_point.x > self.min_valid_distance))
+            && self.validity.contains(lidar_point)
+    }
+
+    // Scaled detection box in depth map coordinates as (x_min, x_max, y_min, y_max).
+    fn detection_range(&self, bbox: &BBox) -> (f32, f32, f32, f32) {
+        let x_offset = self.roi_offset.0 as f32;
+        let y_offset = self.roi_offset.1 as f32;
+        (
+            (bbox.x_center - bbox.width * self.scale_factor * 0.5) * self.zoom_factor + x_offset,
+            (bbox.x_center + bbox.width * self.scale_factor * 0.5) * self.zoom_factor + x_offset,
+            (bbox.y_center - bbox.height * self.scale_factor * 0.5) * self.zoom_factor + y_offset,
+            (bbox.y_center + bbox.height * self.scale_factor * 0.5) * self.zoom_factor + y_offset,
+        )
+    }
+
     fn image_to_lidar(&self, point: &Point3<f32>) -> Point3<f32> {
         let camera_coor_vector =
             Vector3::new(point.x / self.zoom_factor, point.y / self.zoom_factor, 1.0);
@@ -261,16 +869,15 @@ impl Locator {
         let image_points_filtered: Vec<_> = points
             .iter()
             .filter_map(|lidar_point| {
-                if !lidar_point.is_empty()
-                    && lidar_point.x.is_normal()
-                    && lidar_point.y.is_normal()
-                    && lidar_point.z.is_normal()
-                    && lidar_point.x < self.max_valid_distance
-                    && lidar_point.x > self.min_valid_distance
-                {
+                if self.is_valid_point(lidar_point) {
                     let image_point = self.lidar_to_image(lidar_point);
                     let (u, v) = (image_point.x.round() as i32, image_point.y.round() as i32);
-                    if u >= 0 && (u as u32) < image_width && v >= 0 && (v as u32) < image_height {
+                    if image_point.z > 0.0
+                        && u >= 0
+                        && (u as u32) < image_width
+                        && v >= 0
+                        && (v as u32) < image_height
+                    {
                         Some((u as u32, v as u32, image_point.z))
                     } else {
                         None
@@ -293,15 +900,17 @@ impl Locator {
             depth_map.put_pixel(u, v, Luma([depth]));
         });
 
-        let mut difference_depth_map: ImageBuffer<Luma<f32>, Vec<f32>> =
+        // The difference to the background only selects the foreground pixels, they keep their
+        // own depth so they can be projected back to the lidar frame.
+        let mut foreground_depth_map: ImageBuffer<Luma<f32>, Vec<f32>> =
             ImageBuffer::new(image_width, image_height);
 
-        difference_depth_map
+        foreground_depth_map
             .iter_mut()
             .zip(depth_map.into_iter())
             .zip(self.background_depth_map.iter())
             .par_bridge()
-            .for_each(|((diff_depth, depth), background_depth)| {
+            .for_each(|((foreground_depth, depth), background_depth)| {
                 if !depth.is_normal() || !background_depth.is_normal() {
                     return;
                 }
@@ -309,43 +918,72 @@ impl Locator {
                 if difference > self.min_valid_distance_diff
                     && difference < self.max_valid_distance_diff
                 {
-                    *diff_depth = difference;
+                    *foreground_depth = *depth;
                 }
             });
 
-        difference_depth_map
+        foreground_depth_map
+    }
+
+    // Pixels of the foreground lidar points, one per point where the depth map keeps one point
+    // per pixel, so detections count their real lidar points.
+    fn get_foreground_pixels(&self, points: &[Point3<f32>]) -> Vec<(u32, u32)> {
+        let (image_width, image_height) = self.background_depth_map.dimensions();
+
+        points
+            .iter()
+            .filter(|lidar_point| {
+                self.is_valid_point(lidar_point) && self.is_above_ground(lidar_point)
+            })
+            .filter_map(|lidar_point| {
+                let image_point = self.lidar_to_image(lidar_point);
+                let (u, v) = (image_point.x.round() as i32, image_point.y.round() as i32);
+                if image_point.z <= 0.0
+                    || u < 0
+                    || (u as u32) >= image_width
+                    || v < 0
+                    || (v as u32) >= image_height
+                {
+                    return None;
+                }
+
+                let (u, v) = (u as u32, v as u32);
+                let depth = image_point.z;
+                let background_depth = self.background_depth_map.get_pixel(u, v).0[0];
+                let difference = background_depth - depth;
+                (background_depth.is_normal()
+                    && depth > self.min_valid_distance
+                    && depth < self.max_valid_distance
+                    && difference > self.min_valid_distance_diff
+                    && difference < self.max_valid_distance_diff)
+                    .then_some((u, v))
+            })
+            .collect()
     }
 
     fn search_for_location(
         &self,
-        bboxes: &[BBox],
-        difference_depth_map: &ImageBuffer<Luma<f32>, Vec<f32>>,
+        detections: &[RobotDetection],
+        foreground_depth_map: &ImageBuffer<Luma<f32>, Vec<f32>>,
+        foreground_pixels: &[(u32, u32)],
     ) -> Vec<Option<RobotLocation>> {
         let span = span!(Level::TRACE, "Locator::search_for_location");
         let _enter = span.enter();
 
         debug!(
             "Searching for robot locations in {} bounding boxes",
-            bboxes.len()
+            detections.len()
         );
-        let (image_width, image_height) = difference_depth_map.dimensions();
+        let (image_width, image_height) = foreground_depth_map.dimensions();
 
-        bboxes
+        detections
             .iter()
-            .map(|bbox| {
-                let x_min =
-                    (bbox.x_center - bbox.width * self.scale_factor * 0.5) * self.zoom_factor;
-                let x_max =
-                    (bbox.x_center + bbox.width * self.scale_factor * 0.5) * self.zoom_factor;
-                let y_min =
-                    (bbox.y_center - bbox.height * self.scale_factor * 0.5) * self.zoom_factor;
-                let y_max =
-                    (bbox.y_center + bbox.height * self.scale_factor * 0.5) * self.zoom_factor;
-
-                let x_min = (x_min + self.roi_offset.0 as f32).max(0.0) as u32;
-                let x_max = (x_max + self.roi_offset.0 as f32).min(image_width as f32) as u32;
-                let y_min = (y_min + self.roi_offset.1 as f32).max(0.0) as u32;
-                let y_max = (y_max + self.roi_offset.1 as f32).min(image_height as f32) as u32;
+            .map(|det| {
+                let (x_min, x_max, y_min, y_max) = self.detection_range(&det.bbox());
+                let x_min = x_min.max(0.0) as u32;
+                let x_max = x_max.min(image_width as f32) as u32;
+                let y_min = y_min.max(0.0) as u32;
+                let y_max = y_max.min(image_height as f32) as u32;
 
                 debug!("BBox: ({x_min}, {y_min})~({x_max}, {y_max})");
 
@@ -354,111 +992,385 @@ impl Locator {
                 let mut lidar_points = Vec::with_capacity(size);
                 for y in y_min..y_max {
                     for x in x_min..x_max {
-                        let depth = difference_depth_map.get_pixel(x, y).0[0];
+                        let depth = foreground_depth_map.get_pixel(x, y).0[0];
                         if depth > self.min_valid_distance && depth < self.max_valid_distance {
                             debug!("x: {x}, y: {y}, depth: {depth}");
                             let image_point = Point3::new(x as f32, y as f32, depth);
                             let lidar_point = self.image_to_lidar(&image_point);
-                            lidar_points.push(vec![lidar_point.x, lidar_point.y, lidar_point.z]);
+                            if !self.is_above_ground(&lidar_point) {
+                                continue;
+                            }
+                            lidar_points.push(lidar_point);
                             image_points.push(image_point);
                         }
                     }
                 }
 
-                let dbscan_model =
-                    dbscan::Model::new(self.cluster_epsilon as f64, self.cluster_min_points);
-                let categories = dbscan_model.run(&lidar_points);
-
-                let mut category_mapping: HashMap<usize, Vec<Point3<f32>>> =
-                    HashMap::with_capacity(size);
-                image_points.iter().zip(categories.into_iter()).for_each(
-                    |(image_point, category)| {
-                        if let Classification::Core(category) = category {
-                            category_mapping
-                                .entry(category)
-                                .or_insert_with(Vec::new)
-                                .push(*image_point);
-                        }
-                    },
-                );
-                debug!("Category of pixels: {:?}", category_mapping);
-
-                let pixels = if let Some((category, pixels)) = category_mapping
-                    .iter()
-                    .max_by_key(|&(_, pixels)| pixels.len())
-                {
-                    trace!("Category {category} selected for location");
-                    pixels
-                } else {
-                    trace!("No category selected, will return average of points");
-                    &image_points
-                };
-
+                let cluster = self.select_cluster(&lidar_points);
+                let pixels: Vec<_> = cluster.iter().map(|&idx| image_points[idx]).collect();
                 debug!("Pixels: {:?}", pixels);
 
                 if pixels.is_empty() {
                     return None;
                 }
 
-                let (sum_point, count, min_point, max_point) = pixels
+                let cluster_points: Vec<_> =
+                    cluster.into_iter().map(|idx| lidar_points[idx]).collect();
+                let roi_points = foreground_pixels
                     .iter()
-                    .filter_map(|image_point| {
-                        if image_point.z.is_normal() {
-                            let lidar_point = self.image_to_lidar(&image_point);
-                            Some(lidar_point)
-                        } else {
-                            None
-                        }
+                    .filter(|&&(u, v)| u >= x_min && u < x_max && v >= y_min && v < y_max)
+                    .count();
+
+                Some(self.fit_location(det.label.kind(), &cluster_points, roi_points))
+            })
+            .collect()
+    }
+
+    // Frustum mode, selects the foreground points projecting into each scaled detection box.
+    fn search_in_frustums(
+        &self,
+        detections: &[RobotDetection],
+        foreground_points: &[(Point3<f32>, Point3<f32>)],
+    ) -> Vec<Option<RobotLocation>> {
+        let span = span!(Level::TRACE, "Locator::search_in_frustums");
+        let _enter = span.enter();
+
+        detections
+            .iter()
+            .map(|det| {
+                let (x_min, x_max, y_min, y_max) = self.detection_range(&det.bbox());
+                debug!("BBox: ({x_min}, {y_min})~({x_max}, {y_max})");
+
+                let frustum_points: Vec<_> = foreground_points
+                    .iter()
+                    .filter(|(_, image_point)| {
+                        image_point.x >= x_min
+                            && image_point.x < x_max
+                            && image_point.y >= y_min
+                            && image_point.y < y_max
                     })
-                    .fold(
-                        (
-                            Point3::<f32>::new(0.0, 0.0, 0.0),
-                            0,
-                            Point3::<f32>::new(f32::MAX, f32::MAX, f32::MAX),
-                            Point3::<f32>::new(f32::MIN, f32::MIN, f32::MIN),
-                        ),
-                        |(sum, cnt, min_point, max_point), point| {
-                            (
-                                Point3::new(sum.x + point.x, sum.y + point.y, sum.z + point.z),
-                                cnt + 1,
-                                Point3::new(
-                                    min_point.x.min(point.x),
-                                    min_point.y.min(point.y),
-                                    min_point.z.min(point.z),
-                                ),
-                                Point3::new(
-                                    max_point.x.max(point.x),
-                                    max_point.y.max(point.y),
-                                    max_point.z.max(point.z),
-                                ),
-                            )
-                        },
+                    .map(|(lidar_point, _)| *lidar_point)
+                    .collect();
+                trace!("{} points in frustum", frustum_points.len());
+
+                let cluster_points: Vec<_> = self
+                    .select_cluster(&frustum_points)
+                    .into_iter()
+                    .map(|idx| frustum_points[idx])
+                    .collect();
+                if cluster_points.is_empty() {
+                    return None;
+                }
+
+                Some(self.fit_location(det.label.kind(), &cluster_points, frustum_points.len()))
+            })
+            .collect()
+    }
+
+    fn fall_back_to_monocular(
+        &self,
+        detections: &[RobotDetection],
+        robot_locations: Vec<Option<RobotLocation>>,
+    ) -> Vec<Option<RobotLocation>> {
+        if !self.monocular_fallback {
+            return robot_locations;
+        }
+
+        robot_locations
+            .into_iter()
+            .zip(detections)
+            .map(|(robot_location, det)| {
+                robot_location.or_else(|| {
+                    let robot_location = self.monocular_location(det);
+                    debug!(
+                        "No lidar points for {}, monocular location {:?}",
+                        det.label, robot_location
                     );
+                    robot_location
+                })
+            })
+            .collect()
+    }
+
+    // Intersects the ray through the bottom centre of the box with the ground, which is the
+    // near bottom edge of the robot, and places the size prior of its kind behind it.
+    fn monocular_location(&self, det: &RobotDetection) -> Option<RobotLocation> {
+        let ground_plane = self.ground_plane.as_ref()?;
+        let prior = self.size_prior(det.label.kind())?;
+
+        let bbox = det.bbox();
+        let (u, v) = (
+            bbox.x_center * self.zoom_factor + self.roi_offset.0 as f32,
+            (bbox.y_center + bbox.height * 0.5) * self.zoom_factor + self.roi_offset.1 as f32,
+        );
+        let origin = self.image_to_lidar(&Point3::new(u, v, 0.0));
+        let direction = self.image_to_lidar(&Point3::new(u, v, 1.0)) - origin;
 
-                let robot_location = RobotLocation {
-                    center: Point3::new(
-                        sum_point.x / count as f32,
-                        sum_point.y / count as f32,
-                        sum_point.z / count as f32,
+        let denominator = ground_plane.normal.dot(&direction);
+        if denominator.abs() < f32::EPSILON {
+            return None;
+        }
+        let distance =
+            -(ground_plane.normal.dot(&origin.coords) + ground_plane.offset) / denominator;
+        if distance <= 0.0 {
+            trace!(
+                "Ray of {} does not hit the ground in front of the camera",
+                det.label
+            );
+            return None;
+        }
+        let ground_point = origin + direction * distance;
+
+        let horizontal = Vector3::new(direction.x, direction.y, 0.0)
+            .try_normalize(f32::EPSILON)
+            .unwrap_or_else(Vector3::x);
+        let center = ground_point + horizontal * prior.length * 0.5;
+        let bottom = ground_plane.height_at(center.x, center.y);
+
+        Some(RobotLocation {
+            center: Point3::new(center.x, center.y, bottom + prior.height * 0.5),
+            width: prior.width,
+            height: prior.height,
+            depth: prior.length,
+            yaw: 0.0,
+            points: 0,
+            roi_points: 0,
+            monocular: true,
+        })
+    }
+
+    // Indices of the largest DBSCAN cluster, or of all points when none is found.
+    fn select_cluster(&self, lidar_points: &[Point3<f32>]) -> Vec<usize> {
+        let categories = dbscan(
+            lidar_points,
+            self.cluster_epsilon,
+            self.cluster_min_points,
+            self.cluster_method,
+        );
+
+        let mut category_mapping: HashMap<usize, Vec<usize>> = HashMap::new();
+        categories
+            .into_iter()
+            .enumerate()
+            .for_each(|(idx, category)| {
+                if let Classification::Core(category) = category {
+                    category_mapping.entry(category).or_default().push(idx);
+                }
+            });
+        debug!("Category of points: {:?}", category_mapping);
+
+        if let Some((category, indices)) = category_mapping
+            .into_iter()
+            .max_by_key(|(_, indices)| indices.len())
+        {
+            trace!("Category {category} selected for location");
+            indices
+        } else {
+            trace!("No category selected, will return average of points");
+            (0..lidar_points.len()).collect()
+        }
+    }
+
+    // Box around the cluster, `roi_points` is the number of foreground points in the detection.
+    fn fit_location(
+        &self,
+        kind: &str,
+        cluster_points: &[Point3<f32>],
+        roi_points: usize,
+    ) -> RobotLocation {
+        let (sum_point, count, min_point, max_point) = cluster_points.iter().fold(
+            (
+                Point3::<f32>::new(0.0, 0.0, 0.0),
+                0,
+                Point3::<f32>::new(f32::MAX, f32::MAX, f32::MAX),
+                Point3::<f32>::new(f32::MIN, f32::MIN, f32::MIN),
+            ),
+            |(sum, cnt, min_point, max_point), point| {
+                (
+                    Point3::new(sum.x + point.x, sum.y + point.y, sum.z + point.z),
+                    cnt + 1,
+                    Point3::new(
+                        min_point.x.min(point.x),
+                        min_point.y.min(point.y),
+                        min_point.z.min(point.z),
+                    ),
+                    Point3::new(
+                        max_point.x.max(point.x),
+                        max_point.y.max(point.y),
+                        max_point.z.max(point.z),
                     ),
-                    width: max_point.y - min_point.y,
-                    height: max_point.z - min_point.z,
-                    depth: max_point.x - min_point.x,
-                };
+                )
+            },
+        );
+
+        let yaw = estimate_yaw(cluster_points, self.yaw_method);
+        let prior = match self.box_fitting {
+            BoxFitting::Extent => None,
+            BoxFitting::Prior => self.size_prior(kind),
+        };
+        let (center_x, center_y, depth, width) = match prior {
+            Some(prior) => fit_prior_box(cluster_points, yaw, prior),
+            None if yaw == 0.0 => (
+                sum_point.x / count as f32,
+                sum_point.y / count as f32,
+                max_point.x - min_point.x,
+                max_point.y - min_point.y,
+            ),
+            None => {
+                let (depth, width) = oriented_extent(cluster_points, yaw);
+                (
+                    sum_point.x / count as f32,
+                    sum_point.y / count as f32,
+                    depth,
+                    width,
+                )
+            }
+        };
+        trace!("Estimated yaw {yaw} with depth {depth} and width {width}");
 
-                debug!("robot location is {:?}", robot_location);
-                Some(robot_location)
+        // Boxes stand on the ground when it is known, otherwise they span the points
+        // or hang the prior height from the highest point.
+        let top = max_point.z;
+        let bottom = self
+            .ground_plane
+            .as_ref()
+            .map(|ground_plane| ground_plane.height_at(center_x, center_y));
+        let (bottom, top) = match (bottom, prior) {
+            (Some(bottom), Some(prior)) => (bottom, bottom + prior.height),
+            (Some(bottom), None) => (bottom, top),
+            (None, Some(prior)) => (top - prior.height, top),
+            (None, None) => (min_point.z, top),
+        };
+        let center_z = if self.ground_plane.is_none() && prior.is_none() {
+            sum_point.z / count as f32
+        } else {
+            (bottom + top) * 0.5
+        };
+
+        let robot_location = RobotLocation {
+            center: Point3::new(center_x, center_y, center_z),
+            width,
+            height: top - bottom,
+            depth,
+            yaw,
+            points: count,
+            roi_points,
+            monocular: false,
+        };
+
+        debug!("robot location is {:?}", robot_location);
+        robot_location
+    }
+}
+
+// Yaw in [-pi/4, pi/4), robots are close to square so headings 90 degrees apart give the same box.
+fn estimate_yaw(points: &[Point3<f32>], method: YawMethod) -> f32 {
+    if points.len() < 3 {
+        return 0.0;
+    }
+
+    let yaw = match method {
+        YawMethod::None => return 0.0,
+        YawMethod::Pca => {
+            let count = points.len() as f32;
+            let (mean_x, mean_y) = points
+                .iter()
+                .fold((0.0, 0.0), |(x, y), point| (x + point.x, y + point.y));
+            let (mean_x, mean_y) = (mean_x / count, mean_y / count);
+            let (cxx, cyy, cxy) = points.iter().fold((0.0, 0.0, 0.0), |(xx, yy, xy), point| {
+                let (dx, dy) = (point.x - mean_x, point.y - mean_y);
+                (xx + dx * dx, yy + dy * dy, xy + dx * dy)
+            });
+            0.5 * (2.0 * cxy).atan2(cxx - cyy)
+        }
+        YawMethod::MinArea => (0..YAW_SEARCH_STEPS)
+            .map(|step| step as f32 * FRAC_PI_2 / YAW_SEARCH_STEPS as f32 - FRAC_PI_4)
+            .map(|yaw| {
+                let (depth, width) = oriented_extent(points, yaw);
+                (yaw, depth * width)
             })
-            .collect()
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map_or(0.0, |(yaw, _)| yaw),
+    };
+
+    (yaw + FRAC_PI_4).rem_euclid(FRAC_PI_2) - FRAC_PI_4
+}
+
+// Centre (x, y), depth and width of a prior-sized box in the ground plane. The longer side of
+// the prior goes where the points overflow it least.
+fn fit_prior_box(points: &[Point3<f32>], yaw: f32, prior: &SizePrior) -> (f32, f32, f32, f32) {
+    let (sin, cos) = yaw.sin_cos();
+    let (min, max) = points.iter().fold(
+        ((f32::MAX, f32::MAX), (f32::MIN, f32::MIN)),
+        |(min, max), point| {
+            let along = point.x * cos + point.y * sin;
+            let across = -point.x * sin + point.y * cos;
+            (
+                (min.0.min(along), min.1.min(across)),
+                (max.0.max(along), max.1.max(across)),
+            )
+        },
+    );
+
+    let (visible_depth, visible_width) = (max.0 - min.0, max.1 - min.1);
+    let overflow = |depth: f32, width: f32| {
+        (visible_depth - depth).max(0.0) + (visible_width - width).max(0.0)
+    };
+    let (depth, width) =
+        if overflow(prior.width, prior.length) < overflow(prior.length, prior.width) {
+            (prior.width, prior.length)
+        } else {
+            (prior.length, prior.width)
+        };
+
+    let along = anchor(min.0, max.0, depth);
+    let across = anchor(min.1, max.1, width);
+    (
+        along * cos - across * sin,
+        along * sin + across * cos,
+        depth,
+        width,
+    )
+}
+
+// Centre of a box of `size` on one axis through the lidar at 0. The points are the surface
+// facing the lidar, so a box larger than them grows away from it; points around 0 or wider
+// than the box are centred on.
+fn anchor(min: f32, max: f32, size: f32) -> f32 {
+    if max - min >= size || (min <= 0.0 && max >= 0.0) {
+        (min + max) * 0.5
+    } else if min > 0.0 {
+        min + size * 0.5
+    } else {
+        max - size * 0.5
     }
 }
 
+// Extents of the points along the yaw direction and across it, in the ground plane.
+fn oriented_extent(points: &[Point3<f32>], yaw: f32) -> (f32, f32) {
+    let (sin, cos) = yaw.sin_cos();
+    let (min, max) = points.iter().fold(
+        ((f32::MAX, f32::MAX), (f32::MIN, f32::MIN)),
+        |(min, max), point| {
+            let along = point.x * cos + point.y * sin;
+            let across = -point.x * sin + point.y * cos;
+            (
+                (min.0.min(along), min.1.min(across)),
+                (max.0.max(along), max.1.max(across)),
+            )
+        },
+    );
+    (max.0 - min.0, max.1 - min.1)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use assert_approx_eq::assert_approx_eq;
     use nalgebra::{Matrix3, Matrix4, Point3};
 
+    use crate::radar::detect::{Detection, LabelTaxonomy};
+
     #[test]
     fn test_lidar_image_conversion() {
         let camera_intrinsic = Matrix3::<f32>::identity();
@@ -519,4 +1431,336 @@ mod tests {
         let pixel = depth_map.get_pixel(2, 3);
         assert_approx_eq!(pixel.0[0], 1.0);
     }
+
+    #[test]
+    fn test_robot_depth_map_keeps_depth() {
+        let mut locator = locator_with_roi_offset((0, 0));
+        locator
+            .update_background_depth_map(&[Point3::new(6.0, 9.0, 3.0)], (10, 10))
+            .unwrap();
+
+        // 2 in front of the background, the pixel keeps its own depth rather than the difference.
+        let depth_map = locator.get_robot_depth_map(&[Point3::new(2.0, 3.0, 1.0)]);
+        assert_approx_eq!(depth_map.get_pixel(2, 3).0[0], 1.0);
+
+        // Only 0.05 in front of the background, not foreground.
+        let depth_map = locator.get_robot_depth_map(&[Point3::new(5.9, 8.85, 2.95)]);
+        assert_approx_eq!(depth_map.get_pixel(2, 3).0[0], 0.0);
+    }
+
+    #[test]
+    fn test_update_background_exclusions() {
+        let mut locator = Locator::new(
+            0.5,
+            10,
+            0.1,
+            100.0,
+            0.1,
+            100.0,
+            1.0,
+            1.0,
+            (0, 0),
+            Matrix4::<f32>::identity(),
+            Matrix3::<f32>::identity(),
+        )
+        .unwrap();
+        locator.set_background_options(BackgroundOptions {
+            model: BackgroundModel::Percentile {
+                percentile: 0.5,
+                bin_size: 0.1,
+                min_samples: 1,
+            },
+            frames: None,
+            exclude_detections: true,
+            ..Default::default()
+        });
+        assert!(!locator.has_background());
+
+        let exclusions = [BBox {
+            x_center: 2.0,
+            y_center: 3.0,
+            width: 2.0,
+            height: 2.0,
+        }];
+        let points = [Point3::new(4.0, 6.0, 2.0), Point3::new(8.0, 2.0, 2.0)];
+        locator
+            .update_background(&points, (10, 10), &exclusions)
+            .unwrap();
+        locator.refresh_background();
+
+        assert_eq!(locator.background_depth_map.get_pixel(2, 3).0[0], 0.0);
+        assert_approx_eq!(locator.background_depth_map.get_pixel(4, 1).0[0], 2.05);
+
+        // A saved background is reused as is, and only with the same calibration.
+        let background = locator.background("Left").unwrap();
+        let mut other = locator_with_roi_offset((0, 0));
+        other.load_background(background.clone()).unwrap();
+        other.update_background(&points, (10, 10), &[]).unwrap();
+        assert_eq!(other.background_depth_map, background.depth_map);
+        assert!(other.update_background(&points, (20, 20), &[]).is_err());
+        assert!(locator_with_roi_offset((1, 0))
+            .load_background(background)
+            .is_err());
+    }
+
+    #[test]
+    fn test_locate_in_frustums() {
+        let taxonomy = LabelTaxonomy::default();
+        let mut locator = locator_with_roi_offset((0, 0));
+        locator.set_locate_mode(LocateMode::Frustum);
+        locator.set_yaw_method(YawMethod::None);
+        locator.set_background_options(BackgroundOptions {
+            voxel_size: 1.0,
+            ..Default::default()
+        });
+
+        // A wall 10 away, then a robot 5 away in front of it.
+        let wall: Vec<_> = (1..20)
+            .flat_map(|x| (1..20).map(move |y| Point3::new(x as f32 * 0.5, y as f32 * 0.5, 10.0)))
+            .collect();
+        let robot: Vec<_> = (0..5)
+            .flat_map(|x| {
+                (0..5).map(move |y| Point3::new(2.3 + x as f32 * 0.1, 2.3 + y as f32 * 0.1, 5.0))
+            })
+            .collect();
+        let detections = [RobotDetection {
+            car_detection: Detection {
+                bbox: BBox {
+                    x_center: 0.5,
+                    y_center: 0.5,
+                    width: 0.2,
+                    height: 0.2,
+                },
+                confidence: 0.9,
+                class_id: 0,
+            },
+            armor_detections: Vec::new(),
+            label: taxonomy.find("B3").unwrap(),
+            confidence: 0.9,
+            label_scores: Vec::new(),
+        }];
+
+        assert!(locator.locate_detections(&wall, &detections).is_err());
+        locator.update_background(&wall, (1, 1), &[]).unwrap();
+
+        let points: Vec<_> = wall.iter().chain(robot.iter()).copied().collect();
+        let locations = locator.locate_detections(&points, &detections).unwrap();
+        let location = locations[0].as_ref().unwrap();
+        assert_eq!(location.points, 25);
+        assert_eq!(location.roi_points, 25);
+        assert_approx_eq!(location.center.x, 2.5);
+        assert_approx_eq!(location.center.y, 2.5);
+        assert_approx_eq!(location.center.z, 5.0);
+        assert_approx_eq!(location.depth, 0.4);
+        assert!(!location.monocular);
+
+        // Without points in the box the robot is lost, unless the ray to the bottom of the box
+        // is intersected with the ground, here 5 away along the lidar z axis.
+        assert!(locator.locate_detections(&wall, &detections).unwrap()[0].is_none());
+        locator.set_monocular_fallback(true);
+        locator.set_ground_plane(Some(GroundPlane::new([0.0, 0.0, 1.0, -5.0]).unwrap()));
+        locator.set_box_fitting(
+            BoxFitting::Extent,
+            HashMap::from([(
+                "default".to_string(),
+                SizePrior {
+                    length: 0.4,
+                    width: 0.4,
+                    height: 0.3,
+                },
+            )]),
+        );
+        let locations = locator.locate_detections(&wall, &detections).unwrap();
+        let location = locations[0].as_ref().unwrap();
+        assert!(location.monocular);
+        assert_eq!(location.points, 0);
+        let horizontal = Vector3::new(0.5, 0.6, 0.0).normalize() * 0.2;
+        assert_approx_eq!(location.center.x, 2.5 + horizontal.x);
+        assert_approx_eq!(location.center.y, 3.0 + horizontal.y);
+        assert_approx_eq!(location.center.z, 5.15);
+        assert_approx_eq!(location.height, 0.3);
+    }
+
+    #[test]
+    fn test_locate_prior_box_in_depth_map() {
+        // Lidar x forward, y left and z up, seen by a 100x100 camera looking along x.
+        let lidar_to_camera_transform = Matrix4::new(
+            0.0, -1.0, 0.0, 0.0, //
+            0.0, 0.0, -1.0, 0.0, //
+            1.0, 0.0, 0.0, 0.0, //
+            0.0, 0.0, 0.0, 1.0,
+        );
+        let camera_intrinsic = Matrix3::new(100.0, 0.0, 50.0, 0.0, 100.0, 50.0, 0.0, 0.0, 1.0);
+        let mut locator = Locator::new(
+            0.2,
+            5,
+            0.1,
+            100.0,
+            0.1,
+            100.0,
+            1.0,
+            1.0,
+            (0, 0),
+            lidar_to_camera_transform,
+            camera_intrinsic,
+        )
+        .unwrap();
+        locator.set_yaw_method(YawMethod::None);
+        locator.set_ground_plane(Some(GroundPlane::new([0.0, 0.0, 1.0, 1.0]).unwrap()));
+        locator.set_box_fitting(
+            BoxFitting::Prior,
+            HashMap::from([(
+                "default".to_string(),
+                SizePrior {
+                    length: 0.6,
+                    width: 0.6,
+                    height: 0.5,
+                },
+            )]),
+        );
+
+        // A wall 12 away, then the front face of a robot 5 away standing above the ground.
+        let wall: Vec<_> = (0..200)
+            .flat_map(|y| {
+                (0..200).map(move |z| {
+                    Point3::new(12.0, y as f32 * 0.04 - 3.987, z as f32 * 0.04 - 3.987)
+                })
+            })
+            .collect();
+        let robot: Vec<_> = (0..=30)
+            .flat_map(|y| {
+                (0..=20)
+                    .map(move |z| Point3::new(5.0, 0.7 + y as f32 * 0.02, -0.8 + z as f32 * 0.02))
+            })
+            .collect();
+        locator.update_background(&wall, (100, 100), &[]).unwrap();
+
+        let detections = [RobotDetection {
+            car_detection: Detection {
+                bbox: BBox {
+                    x_center: 30.0,
+                    y_center: 62.0,
+                    width: 16.0,
+                    height: 12.0,
+                },
+                confidence: 0.9,
+                class_id: 0,
+            },
+            armor_detections: Vec::new(),
+            label: LabelTaxonomy::default().find("B3").unwrap(),
+            confidence: 0.9,
+            label_scores: Vec::new(),
+        }];
+        let points: Vec<_> = wall.iter().chain(robot.iter()).copied().collect();
+        let locations = locator.locate_detections(&points, &detections).unwrap();
+        let location = locations[0].as_ref().unwrap();
+
+        // The box grows away from the visible face and stands on the ground.
+        assert!((location.center.x - 5.3).abs() < 0.05);
+        assert!((location.center.y - 1.0).abs() < 0.05);
+        assert_approx_eq!(location.center.z, -0.75);
+        assert_approx_eq!(location.depth, 0.6);
+        assert_approx_eq!(location.height, 0.5);
+        // Several robot points share a pixel, all of them count.
+        assert_eq!(location.roi_points, robot.len());
+        assert!(location.points < robot.len());
+    }
+
+    #[test]
+    fn test_validity_replaces_forward_window() {
+        let mut locator = locator_with_roi_offset((0, 0));
+        let behind = Point3::new(-5.0, 0.1, 0.5);
+        let ahead = Point3::new(5.0, 0.1, 0.5);
+        assert!(!locator.is_valid_point(&behind));
+        assert!(locator.is_valid_point(&ahead));
+
+        // An azimuth interval through the back of the lidar keeps the points behind it.
+        locator.set_validity(ValidityVolume {
+            min_azimuth: Some(150f32.to_radians()),
+            max_azimuth: Some(-150f32.to_radians()),
+            ..Default::default()
+        });
+        assert!(locator.is_valid_point(&behind));
+        assert!(!locator.is_valid_point(&ahead));
+    }
+
+    fn locator_with_roi_offset(roi_offset: (u32, u32)) -> Locator {
+        Locator::new(
+            0.5,
+            10,
+            0.1,
+            100.0,
+            0.1,
+            100.0,
+            1.0,
+            1.0,
+            roi_offset,
+            Matrix4::<f32>::identity(),
+            Matrix3::<f32>::identity(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_estimate_yaw() {
+        // A 600 x 400 rectangle outline rotated by 0.3 rad.
+        let (sin, cos) = 0.3f32.sin_cos();
+        let points: Vec<_> = (0..=20)
+            .flat_map(|idx| {
+                let t = idx as f32 / 20.0;
+                [
+                    (t * 600.0 - 300.0, -200.0),
+                    (t * 600.0 - 300.0, 200.0),
+                    (-300.0, t * 400.0 - 200.0),
+                    (300.0, t * 400.0 - 200.0),
+                ]
+            })
+            .map(|(x, y)| Point3::new(5000.0 + x * cos - y * sin, x * sin + y * cos, 0.0))
+            .collect();
+
+        let yaw = estimate_yaw(&points, YawMethod::MinArea);
+        assert!((yaw - 0.3).abs() < 0.01);
+        let (depth, width) = oriented_extent(&points, yaw);
+        assert!((depth - 600.0).abs() < 5.0);
+        assert!((width - 400.0).abs() < 5.0);
+
+        let yaw = estimate_yaw(&points, YawMethod::Pca);
+        assert!((yaw - 0.3).abs() < 0.01);
+
+        assert_eq!(estimate_yaw(&points, YawMethod::None), 0.0);
+        assert_eq!(estimate_yaw(&points[..2], YawMethod::MinArea), 0.0);
+    }
+
+    #[test]
+    fn test_fit_prior_box() {
+        let prior = SizePrior {
+            length: 600.0,
+            width: 500.0,
+            height: 450.0,
+        };
+
+        // Only the face towards the lidar is seen.
+        let points: Vec<_> = (0..=10)
+            .map(|idx| Point3::new(5000.0, idx as f32 * 40.0 - 200.0, 0.0))
+            .collect();
+        let (x, y, depth, width) = fit_prior_box(&points, 0.0, &prior);
+        assert_approx_eq!(x, 5300.0);
+        assert_approx_eq!(y, 0.0);
+        assert_approx_eq!(depth, 600.0);
+        assert_approx_eq!(width, 500.0);
+
+        // A side seen from the left, longer than the prior width.
+        let points: Vec<_> = (0..=10)
+            .map(|idx| Point3::new(4700.0 + idx as f32 * 55.0, -1000.0, 0.0))
+            .collect();
+        let (x, y, depth, width) = fit_prior_box(&points, 0.0, &prior);
+        assert_approx_eq!(x, 5000.0);
+        assert_approx_eq!(y, -1250.0);
+        assert_approx_eq!(depth, 600.0);
+        assert_approx_eq!(width, 500.0);
+
+        let ground_plane = GroundPlane::new([0.0, 0.0, 2.0, 3000.0]).unwrap();
+        assert_approx_eq!(ground_plane.height_at(5000.0, 100.0), -1500.0);
+        assert!(GroundPlane::new([1.0, 0.0, 0.0, 0.0]).is_err());
+    }
 }
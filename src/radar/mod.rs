@@ -1,2 +1,8 @@
+pub mod background;
+pub mod cluster;
 pub mod detect;
+pub mod field;
+pub mod fuse;
+pub mod ground;
 pub mod locate;
+pub mod validity;
@@ -0,0 +1,161 @@
+use anyhow::{anyhow, Result};
+use nalgebra::{Point2, Point3};
+use tracing::debug;
+
+use super::field::FieldTransform;
+use crate::config::ValidityConfig;
+
+// Region of the lidar frame robots can be in. Points outside are dropped before background
+// modelling and locating, so the stands, walls and the rest of the hall never show up.
+#[derive(Debug, Clone, Default)]
+pub struct ValidityVolume {
+    pub min_range: Option<f32>,
+    pub max_range: Option<f32>,
+    // Radians from the lidar x axis, counter-clockwise. The interval wraps through the back
+    // when `min_azimuth` > `max_azimuth`.
+    pub min_azimuth: Option<f32>,
+    pub max_azimuth: Option<f32>,
+    pub min_height: Option<f32>,
+    pub max_height: Option<f32>,
+    // Arena outline in field coordinates with the transform points are taken there by.
+    pub arena: Option<(Vec<Point2<f32>>, FieldTransform)>,
+}
+
+impl ValidityVolume {
+    pub fn from_config(
+        config: &ValidityConfig,
+        field_transform: Option<FieldTransform>,
+    ) -> Result<Self> {
+        let arena = match (config.arena.len(), field_transform) {
+            (0, _) => None,
+            (1..=2, _) => {
+                return Err(anyhow!(
+                    "Arena polygon needs at least 3 vertices, got {:?}",
+                    config.arena
+                ))
+            }
+            (_, None) => return Err(anyhow!("Arena polygon needs the lidar to field transform")),
+            (_, Some(field_transform)) => Some((
+                config
+                    .arena
+                    .iter()
+                    .map(|&[x, y]| Point2::new(x, y))
+                    .collect(),
+                field_transform,
+            )),
+        };
+
+        let volume = Self {
+            min_range: config.min_range,
+            max_range: config.max_range,
+            min_azimuth: config.min_azimuth.map(f32::to_radians),
+            max_azimuth: config.max_azimuth.map(f32::to_radians),
+            min_height: config.min_height,
+            max_height: config.max_height,
+            arena,
+        };
+        debug!("Validity volume: {:?}", volume);
+        Ok(volume)
+    }
+
+    // Range or azimuth limits replace the forward distance window of the locator, so points
+    // behind the lidar can be kept.
+    pub fn has_range_limits(&self) -> bool {
+        self.min_range.is_some()
+            || self.max_range.is_some()
+            || self.min_azimuth.is_some()
+            || self.max_azimuth.is_some()
+    }
+
+    pub fn contains(&self, point: &Point3<f32>) -> bool {
+        let range = point.coords.norm();
+        if self.min_range.is_some_and(|min| range < min)
+            || self.max_range.is_some_and(|max| range > max)
+            || self.min_height.is_some_and(|min| point.z < min)
+            || self.max_height.is_some_and(|max| point.z > max)
+        {
+            return false;
+        }
+
+        let azimuth = point.y.atan2(point.x);
+        let in_azimuth = match (self.min_azimuth, self.max_azimuth) {
+            (Some(min), Some(max)) if min > max => azimuth >= min || azimuth <= max,
+            (min, max) => {
+                min.is_none_or(|min| azimuth >= min) && max.is_none_or(|max| azimuth <= max)
+            }
+        };
+        if !in_azimuth {
+            return false;
+        }
+
+        self.arena
+            .as_ref()
+            .is_none_or(|(polygon, field_transform)| {
+                let field_point = field_transform.to_field(point);
+                contains_point(polygon, field_point.x, field_point.y)
+            })
+    }
+}
+
+// Even-odd rule, a ray along +x crosses the outline an odd number of times from inside.
+fn contains_point(polygon: &[Point2<f32>], x: f32, y: f32) -> bool {
+    let mut inside = false;
+    let mut previous = polygon[polygon.len() - 1];
+    for &vertex in polygon {
+        if (vertex.y > y) != (previous.y > y)
+            && x < (previous.x - vertex.x) * (y - vertex.y) / (previous.y - vertex.y) + vertex.x
+        {
+            inside = !inside;
+        }
+        previous = vertex;
+    }
+    inside
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Matrix4, Vector3};
+
+    use super::*;
+
+    #[test]
+    fn test_validity_volume() {
+        // Lidar at field (1000, 7500) looking along the field x axis.
+        let field_transform =
+            FieldTransform::new(Matrix4::new_translation(&Vector3::new(1000.0, 7500.0, 0.0)))
+                .unwrap();
+        let config = ValidityConfig {
+            max_range: Some(25000.0),
+            min_azimuth: Some(-60.0),
+            max_azimuth: Some(60.0),
+            min_height: Some(-2000.0),
+            max_height: Some(500.0),
+            arena: vec![
+                [0.0, 0.0],
+                [28000.0, 0.0],
+                [28000.0, 15000.0],
+                [0.0, 15000.0],
+            ],
+            ..Default::default()
+        };
+        let volume = ValidityVolume::from_config(&config, Some(field_transform)).unwrap();
+
+        assert!(volume.contains(&Point3::new(10000.0, 2000.0, -1000.0)));
+        // Too far, too high, behind the lidar, outside the arena.
+        assert!(!volume.contains(&Point3::new(26000.0, 0.0, -1000.0)));
+        assert!(!volume.contains(&Point3::new(10000.0, 2000.0, 1000.0)));
+        assert!(!volume.contains(&Point3::new(-5000.0, 0.0, -1000.0)));
+        assert!(!volume.contains(&Point3::new(5000.0, 8000.0, -1000.0)));
+
+        // Azimuth interval through the back of the lidar.
+        let volume = ValidityVolume {
+            min_azimuth: Some(150f32.to_radians()),
+            max_azimuth: Some(-150f32.to_radians()),
+            ..Default::default()
+        };
+        assert!(volume.contains(&Point3::new(-5000.0, 100.0, 0.0)));
+        assert!(!volume.contains(&Point3::new(5000.0, 100.0, 0.0)));
+
+        assert!(ValidityVolume::from_config(&config, None).is_err());
+    }
+}